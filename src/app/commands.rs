@@ -1,20 +1,46 @@
-use crate::file::chunks::{HashesChunk, NamesChunk};
+use crate::file::chunks::{HashesChunk, LinksChunk, NamesChunk, SizeEntry, SizesChunk};
+use crate::file::Snapshot;
+use crate::store::{build_tree, entry_links};
 use crate::utils::{BungeeIndex, BungeeStr, ByteSize};
-use crate::{DepthFileScanner, DigestConsumer, HashEntry, RunnerConfig, ScanRunner};
+use crate::vfs::{FileSystem, RealFs};
+use crate::{
+    Consumer, DepthFileScanner, DigestConsumer, HardLinks, HashArray, HashEntry, HashKey, LinkGroup, PathIdentity, RunnerConfig, ScanRunner,
+};
 use parking_lot::Mutex;
 use sha2::Sha256;
-use std::mem::{replace, size_of_val};
+use std::collections::HashMap;
+use std::mem::replace;
 use std::path::Path;
 use std::sync::Arc;
 
-pub fn snapshot_files(path: &Path) {
+pub fn snapshot_files(path: &Path) -> Snapshot {
+    snapshot_files_with(path, PathIdentity::EXACT)
+}
+
+/// Snapshot files, where names are normalized with given identity policy, both in names chunk and in name hashes.
+pub fn snapshot_files_with(path: &Path, identity: PathIdentity) -> Snapshot {
+    snapshot_files_in(Arc::new(RealFs), path, identity)
+}
+
+/// Snapshot files of any file system, eg. contents of archive.
+pub fn snapshot_files_in(fs: Arc<dyn FileSystem>, path: &Path, identity: PathIdentity) -> Snapshot {
+    snapshot_files_with_key(fs, path, identity, None)
+}
+
+/// Snapshot files with names and contents hashed with given key, see [`HashKey`]. Names stay in returned snapshot for
+/// local use, but they are not written to file, so the fingerprint can be given to anyone without the key.
+pub fn snapshot_files_keyed(fs: Arc<dyn FileSystem>, path: &Path, identity: PathIdentity, key: &HashKey) -> Snapshot {
+    snapshot_files_with_key(fs, path, identity, Some(key))
+}
+
+fn snapshot_files_with_key(fs: Arc<dyn FileSystem>, path: &Path, identity: PathIdentity, key: Option<&HashKey>) -> Snapshot {
     let path_buffer = Arc::new(Mutex::new(BungeeStr::new()));
     let path_indexes = Arc::new(Mutex::new(Vec::new()));
     let paths = {
         let mut pb = path_buffer.lock_arc();
         let mut pi = path_indexes.lock_arc();
-        DepthFileScanner::from_dir(path, true)
-            .save_to_bungee(move |a, b| pb.push(a, b), |v, t| Some(v.to_string_lossy()))
+        DepthFileScanner::from_fs(fs.clone(), path, true)
+            .save_to_bungee(move |a, b| pb.push_os(a, b), move |v, t| Some(identity.normalize(v)))
             .filter_map(|(i, d, t)| Some((t.is_file().then_some(i).flatten()?, d, i)))
             .map(move |(i, d, _)| {
                 pi.push(i);
@@ -25,31 +51,141 @@ pub fn snapshot_files(path: &Path) {
     let mutex: Arc<Mutex<Vec<HashEntry<32, 32>>>> = Default::default();
     let cons = {
         let mutex = mutex.clone();
-        Arc::new(DigestConsumer::<32, 32, Sha256, _>::new(move |value| mutex.lock().push(value)))
+        let cons = DigestConsumer::<32, 32, Sha256, _>::new(move |value| mutex.lock().push(value))
+            .with_identity(identity)
+            .with_root(path);
+        Arc::new(SizeRecorder::new(match key {
+            Some(key) => cons.with_key(key),
+            None => cons,
+        }))
         // Arc::new(HashZeroChunksFinder {
         //     min_size: 16000,
         //     chunks: Default::default(),
         // })
     };
-    let cfg = RunnerConfig::new(128, None);
-    let runner = ScanRunner::run(paths, cons, cfg);
+    let hard_links = Arc::new(HardLinks::new());
+    let mut cfg = RunnerConfig::new(128, None);
+    cfg.hard_links = Some(hard_links.clone());
+    cfg.fs = Some(fs);
+    let runner = ScanRunner::run(paths, cons.clone(), cfg);
     runner.wait_for_finish();
 
-    let vals = Arc::into_inner(mutex).expect("More than one mutex reference").into_inner();
+    //consumer is still referenced for name hashing of links, so take values out of mutex
+    let mut vals = std::mem::take(&mut *mutex.lock());
+    let mut sizes = std::mem::take(&mut *cons.sizes.lock());
     let idx = Arc::into_inner(path_indexes).expect("More than one mutex reference").into_inner();
     let paths = Arc::into_inner(path_buffer).expect("More than one mutex reference").into_inner();
+    let groups = Arc::into_inner(hard_links).expect("More than one links reference").into_groups();
+    let links = resolve_links(&cons.inner, groups, &mut vals, &mut sizes);
 
     let mut hashes = HashesChunk::new_sha256(vals, false);
+    hashes.identity = identity;
+    hashes.root_relative = true;
+    hashes.prefix_id = cons.inner.prefix_id();
+    hashes.key_id = cons.inner.key_id();
     hashes.sort();
-    let restored = idx.iter().map(|&i| paths.path_of("/", i)).collect::<Vec<_>>();
-    //println!("restored [{}]{restored:#?}", restored.len());
     let mut names = NamesChunk::new(paths, idx);
+    //links and tree are made of plain name hashes, so they are not built for keyed hashes
+    let tree = match key {
+        Some(_) => Default::default(),
+        None => {
+            names.set_entry_links(entry_links::<Sha256>(&hashes, &names, ""), hashes.sort);
+            build_tree::<Sha256>(&hashes, &names, "")
+        }
+    };
+    Snapshot {
+        hashes,
+        names,
+        links,
+        sizes: SizesChunk::new(sizes),
+        tree,
+        filters: Vec::new(),
+        suffixes: Default::default(),
+    }
+}
+
+/// Consumer that records number of bytes of every consumed file under the name hash of inner consumer, so that names
+/// are hashed only once.
+struct SizeRecorder<C> {
+    inner: C,
+    sizes: Mutex<Vec<SizeEntry>>,
+}
+
+impl<C> SizeRecorder<C> {
+    fn new(inner: C) -> Self {
+        Self {
+            inner,
+            sizes: Default::default(),
+        }
+    }
+}
+
+impl<C> Consumer for SizeRecorder<C>
+where
+    C: for<'a> Consumer<NameState<'a> = HashArray<32>>,
+{
+    type NameState<'a> = HashArray<32>;
+    type FileState<'a> = (C::FileState<'a>, u64);
+
+    fn consume_name<'a>(&self, path: &'a Path) -> Self::NameState<'a> {
+        self.inner.consume_name(path)
+    }
+
+    fn start_file(&self) -> Self::FileState<'_> {
+        (self.inner.start_file(), 0)
+    }
 
-    let bytes = size_of_val(hashes.data.as_slice()) as f64 / (1024.0 * 1024.0);
-    println!("Memory taken: {bytes:.3}Mb count:{}", hashes.data.len());
+    fn update_file<'a>(&'a self, state: &mut Self::FileState<'a>, data: &[u8]) {
+        state.1 += data.len() as u64;
+        self.inner.update_file(&mut state.0, data);
+    }
+
+    fn finish_consume(&self, name: Self::NameState<'_>, file: Self::FileState<'_>) {
+        self.sizes.lock().push(SizeEntry { id: name, size: file.1 });
+        self.inner.finish_consume(name, file.0);
+    }
 
-    println!("first: {:?}", hashes.data.first().unwrap());
-    println!("last:  {:?}", hashes.data.last().unwrap());
+    fn on_error(&self, error: std::io::Error, path: &Path) {
+        self.inner.on_error(error, path);
+    }
+}
+
+/// Add entries and sizes for hard links that were skipped by runner, they share data hash and size of the link that
+/// was hashed.
+fn resolve_links<C>(cons: &C, groups: Vec<LinkGroup>, entries: &mut Vec<HashEntry<32, 32>>, sizes: &mut Vec<SizeEntry>) -> LinksChunk
+where
+    C: for<'a> Consumer<NameState<'a> = HashArray<32>>,
+{
+    let groups = groups
+        .iter()
+        .map(|g| {
+            let mut names = vec![cons.consume_name(&g.primary)];
+            names.extend(g.links.iter().map(|p| cons.consume_name(p)));
+            names
+        })
+        .collect::<Vec<_>>();
+    let mut primaries = groups.iter().map(|g| (g[0], (None, None))).collect::<HashMap<_, _>>();
+    for e in entries.iter() {
+        if let Some((data, _)) = primaries.get_mut(&e.id) {
+            *data = Some(e.data);
+        }
+    }
+    for e in sizes.iter() {
+        if let Some((_, size)) = primaries.get_mut(&e.id) {
+            *size = Some(e.size);
+        }
+    }
+    for group in &groups {
+        //primary might be missing when it couldn't be read
+        let (data, size) = primaries[&group[0]];
+        if let Some(data) = data {
+            entries.extend(group[1..].iter().map(|&id| HashEntry { id, data }));
+        }
+        if let Some(size) = size {
+            sizes.extend(group[1..].iter().map(|&id| SizeEntry { id, size }));
+        }
+    }
+    LinksChunk::new(groups)
 }
 
 #[derive(Default, Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -64,13 +200,17 @@ pub struct FileCounts {
 
 impl FileCounts {
     pub fn count_all_in(path: &Path) -> Self {
+        Self::count_all_in_fs(Arc::new(RealFs), path)
+    }
+
+    pub fn count_all_in_fs(fs: Arc<dyn FileSystem>, path: &Path) -> Self {
         let mut files = FileCounts::default();
-        for (entry, typ) in DepthFileScanner::from_dir(path, true).into_iter() {
+        for (entry, typ) in DepthFileScanner::from_fs(fs.clone(), path, true).into_iter() {
             if typ.is_file() {
                 files.files += 1;
-                match entry.metadata() {
-                    Ok(meta) if meta.len() == 0 => files.empty_files += 1,
-                    Ok(meta) => files.total_size += meta.len(),
+                match fs.metadata(entry.as_path()) {
+                    Ok(meta) if meta.len == 0 => files.empty_files += 1,
+                    Ok(meta) => files.total_size += meta.len,
                     Err(_err) => files.errors += 1,
                 }
             } else if typ.is_dir() {
@@ -86,6 +226,7 @@ impl FileCounts {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::vfs::MemFs;
     use std::path::Path;
 
     #[test]
@@ -95,6 +236,39 @@ mod tests {
         snapshot_files(path);
     }
 
+    #[test]
+    #[cfg(unix)]
+    fn test_snapshot_hard_links() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a"), b"shared").unwrap();
+        std::fs::write(dir.path().join("c"), b"shared").unwrap();
+        std::fs::hard_link(dir.path().join("a"), dir.path().join("b")).unwrap();
+
+        let snapshot = snapshot_files(dir.path());
+        assert_eq!(snapshot.hashes.data.len(), 3);
+        assert!(snapshot.hashes.data.windows(2).all(|w| w[0].data == w[1].data));
+        assert_eq!(snapshot.links.groups.len(), 1);
+        assert_eq!(snapshot.links.groups[0].len(), 2);
+        //skipped link gets size of the hashed one
+        assert_eq!(snapshot.sizes.entries().len(), 3);
+        assert_eq!(snapshot.sizes.total_size(), 18);
+    }
+
+    #[test]
+    fn test_count_all_in_fs() {
+        let fs = MemFs::new().with_file("a/b", b"12".as_slice()).with_file("a/c", b"".as_slice());
+        assert_eq!(
+            FileCounts::count_all_in_fs(Arc::new(fs), Path::new("")),
+            FileCounts {
+                dirs: 1,
+                files: 2,
+                total_size: ByteSize(2),
+                empty_files: 1,
+                ..Default::default()
+            }
+        );
+    }
+
     #[test]
     #[ignore]
     fn test_count_all() {
@@ -0,0 +1,256 @@
+use crate::file::chunks::{BlockType, HashesChunk};
+use crate::file::StdHashArray;
+use crate::utils::MeasureMemory;
+use crate::HashArray;
+use std::f64::consts::LN_2;
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::mem::size_of;
+
+/// Which hashes of entries are inserted into filter.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum FilterKind {
+    /// Content hashes
+    Data,
+    /// Name hashes
+    Names,
+}
+
+/// Bloom filter over hashes, answers if hash was possibly inserted. Hashes are expected to be uniformly distributed,
+/// so bit positions are derived directly from their bytes.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct FilterChunk {
+    kind: FilterKind,
+    hash_count: u32,
+    items: u64,
+    bits: Vec<u64>,
+}
+
+pub struct FilterHeader {
+    kind: FilterKind,
+    bit_count: u64,
+    hash_count: u32,
+    items: u64,
+}
+
+impl FilterHeader {
+    const FLAG_NAMES: u32 = 0x1;
+
+    pub fn to_array(&self) -> StdHashArray {
+        let mut array = HashArray::zero();
+        array.set_slice(0, BlockType::Filter.magic());
+        let flags = match self.kind {
+            FilterKind::Data => 0,
+            FilterKind::Names => Self::FLAG_NAMES,
+        };
+        array.set_u32(4, flags);
+        array.set_u64(8, self.bit_count);
+        array.set_u32(16, self.hash_count);
+        array.set_u64(24, self.items);
+        //bytes 32..64 are zeroed
+        array
+    }
+
+    pub fn from_array(array: StdHashArray) -> io::Result<Self> {
+        BlockType::Filter.require_magic(array.get_slice(0))?;
+        let flags = array.get_u32(4);
+        let kind = if flags & Self::FLAG_NAMES != 0 {
+            FilterKind::Names
+        } else {
+            FilterKind::Data
+        };
+        Ok(Self {
+            kind,
+            bit_count: array.get_u64(8),
+            hash_count: array.get_u32(16),
+            items: array.get_u64(24),
+        })
+    }
+}
+
+impl FilterChunk {
+    const MAX_HASHES: u32 = 16;
+
+    /// Empty filter sized for expected number of items, so that false positive rate stays around given value. Rates
+    /// are clamped to `1e-9..=0.5`.
+    pub fn new(kind: FilterKind, expected_items: usize, false_positive_rate: f64) -> Self {
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let items = expected_items.max(1) as f64;
+        let optimal_bits = -items * rate.ln() / (LN_2 * LN_2);
+        let optimal_hashes = (optimal_bits / items) * LN_2;
+        let hash_count = optimal_hashes.round().clamp(1.0, Self::MAX_HASHES as f64);
+        let bit_count = if optimal_hashes > Self::MAX_HASHES as f64 {
+            //fewer hashes than optimal need more bits for the same rate, (1 - e^(-kn/m))^k = rate solved for m
+            -hash_count * items / (1.0 - rate.powf(1.0 / hash_count)).ln()
+        } else {
+            optimal_bits
+        };
+        let hash_count = hash_count as u32;
+        let words = (bit_count.ceil().max(64.0) as u64).div_ceil(64);
+        Self {
+            kind,
+            hash_count,
+            items: 0,
+            bits: vec![0; words as usize],
+        }
+    }
+
+    /// Filter of all content or name hashes of chunk.
+    pub fn from_hashes(kind: FilterKind, hashes: &HashesChunk, false_positive_rate: f64) -> Self {
+        let mut filter = Self::new(kind, hashes.data.len(), false_positive_rate);
+        for entry in &hashes.data {
+            filter.insert(match kind {
+                FilterKind::Data => &entry.data,
+                FilterKind::Names => &entry.id,
+            });
+        }
+        filter
+    }
+
+    pub fn kind(&self) -> FilterKind {
+        self.kind
+    }
+
+    /// Number of inserted hashes.
+    pub fn len(&self) -> u64 {
+        self.items
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items == 0
+    }
+
+    pub fn bit_count(&self) -> u64 {
+        self.bits.len() as u64 * 64
+    }
+
+    pub fn insert(&mut self, hash: &HashArray<32>) {
+        for bit in self.bit_indexes(hash) {
+            self.bits[(bit / 64) as usize] |= 1 << (bit % 64);
+        }
+        self.items += 1;
+    }
+
+    /// `false` if hash was never inserted, `true` if it was inserted or on false positive.
+    pub fn contains(&self, hash: &HashArray<32>) -> bool {
+        self.bit_indexes(hash)
+            .all(|bit| self.bits[(bit / 64) as usize] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Double hashing with two independent halves of hash.
+    fn bit_indexes(&self, hash: &HashArray<32>) -> impl Iterator<Item = u64> {
+        let bytes = hash.get_ref();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let bit_count = self.bit_count();
+        (0..self.hash_count as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bit_count)
+    }
+
+    pub fn read_body<R: Read + ?Sized>(header: FilterHeader, read: &mut R) -> io::Result<Self> {
+        if header.bit_count == 0 || header.bit_count & 63 != 0 || header.hash_count == 0 || header.hash_count > Self::MAX_HASHES {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid filter parameters"));
+        }
+        let words = usize::try_from(header.bit_count / 64)
+            .ok()
+            .filter(|v| v.checked_mul(size_of::<u64>()).is_some())
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Filter is too big"))?;
+        let mut bits = Vec::with_capacity(words.min(1024 * 1024));
+        let mut buf = [0u8; size_of::<u64>()];
+        for _ in 0..words {
+            read.read_exact(&mut buf)?;
+            bits.push(u64::from_le_bytes(buf));
+        }
+        Ok(Self {
+            kind: header.kind,
+            hash_count: header.hash_count,
+            items: header.items,
+            bits,
+        })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let header = FilterHeader {
+            kind: self.kind,
+            bit_count: self.bit_count(),
+            hash_count: self.hash_count,
+            items: self.items,
+        };
+        write.write_all(header.to_array().get_ref())?;
+        for word in &self.bits {
+            write.write_all(&word.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl MeasureMemory for FilterChunk {
+    fn memory_usage(&self) -> usize {
+        self.bits.capacity() * size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::Snapshot;
+    use crate::store::tests::snapshot_of;
+    use crate::PathIdentity;
+    use digest::Digest;
+    use sha2::Sha256;
+
+    fn hash(i: usize) -> HashArray<32> {
+        HashArray::new(Sha256::digest(i.to_le_bytes()).into())
+    }
+
+    #[test]
+    fn test_filter() {
+        let mut filter = FilterChunk::new(FilterKind::Data, 1000, 0.01);
+        (0..1000).for_each(|i| filter.insert(&hash(i)));
+        assert_eq!(filter.len(), 1000);
+        assert!((0..1000).all(|i| filter.contains(&hash(i))));
+        let false_positives = (1000..11000).filter(|i| filter.contains(&hash(*i))).count();
+        assert!(false_positives < 300, "too many false positives: {false_positives}");
+
+        let mut bytes = Vec::new();
+        filter.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, 64 + filter.bit_count() / 8);
+        let mut read = bytes.as_slice();
+        let mut header = StdHashArray::zero();
+        read.read_exact(header.get_mut()).unwrap();
+        let restored = FilterChunk::read_body(FilterHeader::from_array(header).unwrap(), &mut read).unwrap();
+        assert_eq!(restored, filter);
+    }
+
+    #[test]
+    fn test_filter_low_rate() {
+        //optimal number of hashes for this rate is above the limit, so more bits are used instead
+        let mut filter = FilterChunk::new(FilterKind::Names, 1000, 1e-7);
+        assert_eq!(filter.hash_count, FilterChunk::MAX_HASHES);
+        assert!(filter.bit_count() > (1000.0 * -(1e-7f64).ln() / (LN_2 * LN_2)) as u64);
+        (0..1000).for_each(|i| filter.insert(&hash(i)));
+        let false_positives = (1000..201000).filter(|i| filter.contains(&hash(*i))).count();
+        assert!(false_positives <= 2, "too many false positives: {false_positives}");
+    }
+
+    #[test]
+    fn test_snapshot_filters() {
+        let snapshot = snapshot_of(PathIdentity::EXACT, &[("a", b"first"), ("b", b"second")]).with_filters(0.001);
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        let full = Snapshot::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(full.filters, snapshot.filters);
+
+        //filters are read without the rest of snapshot
+        let filters = Snapshot::read_filters(&mut bytes.as_slice()).unwrap();
+        assert_eq!(filters.len(), 2);
+        let seen = |data: &[u8]| {
+            let hash = HashArray::new(Sha256::digest(data).into());
+            filters.iter().any(|f| f.kind() == FilterKind::Data && f.contains(&hash))
+        };
+        assert!(seen(b"first"));
+        assert!(seen(b"second"));
+        assert!(!seen(b"never backed up"));
+        let names = snapshot.filter(FilterKind::Names).unwrap();
+        assert!(snapshot.hashes.data.iter().all(|e| names.contains(&e.id)));
+    }
+}
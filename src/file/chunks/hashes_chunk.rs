@@ -1,7 +1,7 @@
 use crate::file::chunks::{BlockType, BLOCK_HEADER_MAGIC};
 use crate::file::StdHashArray;
 use crate::utils::{BungeeIndex, BungeeStr, MeasureMemory};
-use crate::{DataEntry, HashArray, HashEntry};
+use crate::{DataEntry, HashArray, HashEntry, PathIdentity};
 use rustfft::num_traits::ToPrimitive;
 use std::borrow::Cow;
 use std::cmp::Ordering;
@@ -15,17 +15,39 @@ pub struct HashesChunk {
     pub sort: SortOrder,
     pub name_hash: HashType,
     pub data_hash: HashType,
+    /// Normalization that was applied to paths before hashing them into name ids
+    pub identity: PathIdentity,
+    /// Name ids were derived from paths relative to scanned root
+    pub root_relative: bool,
+    /// Id of prefix prepended to relative paths before hashing them, see [`PathIdentity::prefix_id`]
+    pub prefix_id: Option<u64>,
+    /// Identifier of key when names and contents were hashed with key, see [`crate::HashKey`]
+    pub key_id: Option<u64>,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
 #[repr(u8)]
 pub enum SortOrder {
+    #[default]
     Unordered = 0,
     SortedByName = 1,
     Unknown = 2,
     SortedByData = 3,
 }
 
+impl SortOrder {
+    /// Order stored as byte, `None` for unknown values.
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Unordered),
+            1 => Some(Self::SortedByName),
+            2 => Some(Self::Unknown),
+            3 => Some(Self::SortedByData),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum HashType {
     Sha256,
@@ -66,24 +88,96 @@ pub struct HashesHeader {
     sort: SortOrder,
     name_hash: HashType,
     data_hash: HashType,
+    identity: PathIdentity,
+    root_relative: bool,
+    prefix_id: Option<u64>,
+    key_id: Option<u64>,
 }
 
 impl HashesHeader {
     const FLAG_SORTED: u32 = 1;
-    const FLAG_SORTED_BY_DATA: u32 = 1;
+    const FLAG_SORTED_BY_DATA: u32 = 2;
+    const FLAG_ROOT_RELATIVE: u32 = 0x4;
+    const FLAG_KEYED: u32 = 0x8;
+    const FLAG_PREFIXED: u32 = 0x10;
 
     pub fn to_array(&self) -> HashArray<64> {
         let mut array = HashArray::zero();
         array.set_slice(0, BlockType::Hashes.magic());
         let mut flags = 0;
-        flags |= self.sort as u32 & 0x3;
+        flags |= match self.sort {
+            SortOrder::Unordered => 0,
+            SortOrder::SortedByName => Self::FLAG_SORTED,
+            SortOrder::Unknown => Self::FLAG_SORTED_BY_DATA,
+            SortOrder::SortedByData => Self::FLAG_SORTED | Self::FLAG_SORTED_BY_DATA,
+        };
+        if self.root_relative {
+            flags |= Self::FLAG_ROOT_RELATIVE;
+        }
+        if self.prefix_id.is_some() {
+            flags |= Self::FLAG_PREFIXED;
+        }
+        if self.key_id.is_some() {
+            flags |= Self::FLAG_KEYED;
+        }
         array.set_u32(4, flags);
         array.set_u64(8, self.size);
         array.set_slice(16, self.name_hash.get_fingerprint());
         array.set_slice(24, self.data_hash.get_fingerprint());
-        //bytes 32..64 are zeroed
+        array.set_slice(32, [self.identity.to_bits()]);
+        //bytes 33..40 are zeroed
+        array.set_u64(40, self.key_id.unwrap_or(0));
+        array.set_u64(48, self.prefix_id.unwrap_or(0));
+        //bytes 56..64 are zeroed
         array
     }
+    /// Header of chunk with given number of sha256 entries, for chunks that are written as stream of entries.
+    pub fn sha256(size: u64, sort: SortOrder) -> Self {
+        Self {
+            size,
+            sort,
+            name_hash: HashType::Sha256,
+            data_hash: HashType::Sha256,
+            identity: PathIdentity::EXACT,
+            root_relative: false,
+            prefix_id: None,
+            key_id: None,
+        }
+    }
+
+    pub fn with_identity(mut self, identity: PathIdentity, root_relative: bool) -> Self {
+        self.identity = identity;
+        self.root_relative = root_relative;
+        self
+    }
+
+    /// Same header for chunk with different number of entries in given order, e.g. when entries were re-sorted.
+    pub fn with_entries(mut self, size: u64, sort: SortOrder) -> Self {
+        self.size = size;
+        self.sort = sort;
+        self
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn sort(&self) -> SortOrder {
+        self.sort
+    }
+
+    pub fn identity(&self) -> PathIdentity {
+        self.identity
+    }
+
+    pub fn root_relative(&self) -> bool {
+        self.root_relative
+    }
+
+    pub fn key_id(&self) -> Option<u64> {
+        self.key_id
+    }
+
     pub fn read<R: Read + ?Sized>(read: &mut R) -> io::Result<Self> {
         let mut header = HashArray::zero();
         read.read_exact(header.get_mut())?;
@@ -94,54 +188,74 @@ impl HashesHeader {
         BlockType::Hashes.require_magic(array.get_slice(0))?;
         let flags = array.get_u32(4);
         let size = array.get_u64(8);
-        let sort = match flags & 0x3 {
-            0 => SortOrder::Unordered,
-            1 => SortOrder::SortedByName,
-            3 => SortOrder::SortedByData,
-            _ => SortOrder::Unknown,
-        };
         let sorted = (flags & Self::FLAG_SORTED) != 0;
         let sorted_by_data = (flags & Self::FLAG_SORTED_BY_DATA) != 0;
+        let sort = match (sorted, sorted_by_data) {
+            (false, false) => SortOrder::Unordered,
+            (true, false) => SortOrder::SortedByName,
+            (true, true) => SortOrder::SortedByData,
+            (false, true) => SortOrder::Unknown,
+        };
         let name_hash = HashType::from_fingerprint(array.get_slice(16))
             .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Unknown name hash type fingerprint"))?;
         let data_hash = HashType::from_fingerprint(array.get_slice(24))
             .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Unknown data hash type fingerprint"))?;
+        let [identity] = array.get_slice(32);
+        let identity =
+            PathIdentity::from_bits(identity).ok_or_else(|| Error::new(ErrorKind::Unsupported, "Unknown path identity policy"))?;
 
         Ok(Self {
             sort,
             size,
             name_hash,
             data_hash,
+            identity,
+            root_relative: flags & Self::FLAG_ROOT_RELATIVE != 0,
+            prefix_id: (flags & Self::FLAG_PREFIXED != 0).then(|| array.get_u64(48)),
+            key_id: (flags & Self::FLAG_KEYED != 0).then(|| array.get_u64(40)),
         })
     }
 }
 
 impl HashesChunk {
+    const READ_BATCH: usize = 64 * 1024;
+
     pub fn new_sha256(data: Vec<DataEntry>, sorted: bool) -> Self {
         Self {
             data,
             sort: SortOrder::SortedByName,
             name_hash: HashType::Sha256,
             data_hash: HashType::Sha256,
+            identity: PathIdentity::EXACT,
+            root_relative: false,
+            prefix_id: None,
+            key_id: None,
         }
     }
 
+    /// Whether name ids were derived with given prefix, names are compared after normalizing with chunk identity.
+    pub fn matches_prefix(&self, prefix: &str) -> bool {
+        self.prefix_id == self.identity.prefix_id(prefix)
+    }
+
     pub fn read<R: Read + ?Sized>(read: &mut R) -> io::Result<Self> {
         let header = HashesHeader::read(read)?;
         Self::read_body(header, read)
     }
     pub fn read_body<R: Read + ?Sized>(header: HashesHeader, read: &mut R) -> io::Result<Self> {
-        if header.size > u32::MAX as _ {
-            return Err(Error::new(
-                ErrorKind::Unsupported,
-                "More that u32::MAX hash entries are not supported",
-            ));
-        }
+        let size = usize::try_from(header.size)
+            .ok()
+            .filter(|v| v.checked_mul(size_of::<DataEntry>()).is_some())
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Hash entries don't fit in memory, use DiskHashStore"))?;
 
-        let mut data = vec![HashEntry::zero(); header.size as usize];
-
-        let data_bytes = unsafe { data.as_mut_slice().align_to_mut::<u8>().1 };
-        read.read_exact(data_bytes)?;
+        //entries are read in batches, so that corrupted size doesn't allocate more than the stream holds
+        let mut data = Vec::with_capacity(size.min(Self::READ_BATCH));
+        while data.len() < size {
+            let start = data.len();
+            data.resize(start + (size - start).min(Self::READ_BATCH), HashEntry::zero());
+            let data_bytes = unsafe { data[start..].align_to_mut::<u8>().1 };
+            read.read_exact(data_bytes)?;
+        }
 
         //todo fix any endianess issues?
         //Self::fix_endianness(data_bytes);
@@ -151,24 +265,28 @@ impl HashesChunk {
             data,
             name_hash: header.name_hash,
             data_hash: header.data_hash,
+            identity: header.identity,
+            root_relative: header.root_relative,
+            prefix_id: header.prefix_id,
+            key_id: header.key_id,
         })
     }
 
-    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
-        if self.data.len() > u32::MAX as _ {
-            return Err(Error::new(
-                ErrorKind::Unsupported,
-                "More that u32::MAX hash entries are not supported",
-            ));
-        }
-
-        let header = HashesHeader {
+    pub fn header(&self) -> HashesHeader {
+        HashesHeader {
             size: self.data.len() as _,
             sort: self.sort,
             name_hash: self.name_hash,
             data_hash: self.data_hash,
-        };
-        write.write_all(header.to_array().get_ref())?;
+            identity: self.identity,
+            root_relative: self.root_relative,
+            prefix_id: self.prefix_id,
+            key_id: self.key_id,
+        }
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_all(self.header().to_array().get_ref())?;
 
         let data_bytes = unsafe { Cow::Borrowed(self.data.as_slice().align_to::<u8>().1) };
 
@@ -178,22 +296,39 @@ impl HashesChunk {
         write.write_all(data_bytes.as_ref())
     }
 
+    fn compare_by_data(a: &DataEntry, b: &DataEntry) -> Ordering {
+        a.data.cmp(&b.data).then_with(|| a.id.cmp(&b.id))
+    }
+
+    /// Check if entries are sorted by data when [`Self::sort`] says so, otherwise check if they are sorted by name.
     pub fn verify_sorted(&self) -> bool {
-        self.data.as_slice().windows(2).all(|w| w[0].cmp(&w[1]) != Ordering::Greater)
+        let windows = self.data.as_slice().windows(2);
+        match self.sort {
+            SortOrder::SortedByData => windows.map(|w| Self::compare_by_data(&w[0], &w[1])).all(|o| o != Ordering::Greater),
+            _ => windows.map(|w| w[0].cmp(&w[1])).all(|o| o != Ordering::Greater),
+        }
     }
 
     pub fn verify_update_sorted(&mut self) {
-        self.sort = if self.verify_sorted() {
-            SortOrder::SortedByName
-        } else {
-            SortOrder::Unordered
-        };
+        self.sort = SortOrder::SortedByName;
+        if !self.verify_sorted() {
+            self.sort = SortOrder::SortedByData;
+            if !self.verify_sorted() {
+                self.sort = SortOrder::Unordered;
+            }
+        }
     }
 
     pub fn sort(&mut self) {
         self.data.sort_unstable();
         self.sort = SortOrder::SortedByName;
     }
+
+    /// Sort entries by data hash, entries with the same data are sorted by name.
+    pub fn sort_by_data(&mut self) {
+        self.data.sort_unstable_by(Self::compare_by_data);
+        self.sort = SortOrder::SortedByData;
+    }
 }
 
 impl MeasureMemory for HashesChunk {
@@ -228,6 +363,10 @@ impl<R: Read + Seek> HashesIterChunk<R> {
     pub fn new(mut reader: R) -> io::Result<Self> {
         Self::with_header(HashesHeader::read(&mut reader)?, reader, Some(true))
     }
+
+    pub fn header(&self) -> &HashesHeader {
+        &self.header
+    }
 }
 
 impl<R: Read> Iterator for HashesIterChunk<R> {
@@ -239,9 +378,8 @@ impl<R: Read> Iterator for HashesIterChunk<R> {
         }
         self.count += 1;
         let mut entry = DataEntry::zero();
-        Some(self.reader.read_exact(entry.as_mut_buf()).map(|_| entry).map_err(|e| {
+        Some(self.reader.read_exact(entry.as_mut_buf()).map(|_| entry).inspect_err(|e| {
             self.count = self.header.size as _; //mark as finished after first error
-            e
         }))
     }
     fn size_hint(&self) -> (usize, Option<usize>) {
@@ -251,3 +389,42 @@ impl<R: Read> Iterator for HashesIterChunk<R> {
 }
 
 impl<R: Read> ExactSizeIterator for HashesIterChunk<R> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnicodeForm;
+
+    #[test]
+    fn test_write_read_header() {
+        let mut chunk = HashesChunk::new_sha256(vec![HashEntry::zero()], true);
+        chunk.identity = PathIdentity::new(UnicodeForm::Nfc, true);
+        chunk.root_relative = true;
+        chunk.prefix_id = chunk.identity.prefix_id("Backup");
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let restored = HashesChunk::read(&mut bytes.as_slice()).unwrap();
+        assert!(restored == chunk);
+        assert_eq!(restored.identity, chunk.identity);
+        assert!(restored.root_relative);
+        assert_eq!(restored.prefix_id, chunk.identity.prefix_id("backup"));
+        assert!(restored.matches_prefix("BACKUP"));
+        assert!(!restored.matches_prefix(""));
+
+        //unknown policy bits are rejected
+        bytes[32] = 0x10;
+        assert!(HashesChunk::read(&mut bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let chunk = HashesChunk::new_sha256(vec![HashEntry::zero(); 3], true);
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        assert!(HashesChunk::read(&mut &bytes[..bytes.len() - 1]).is_err());
+        //size far above data length fails when stream ends, without allocating all entries up front
+        bytes[8..16].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        let err = HashesChunk::read(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::file::chunks::BlockType;
+use crate::file::StdHashArray;
+use crate::utils::MeasureMemory;
+use crate::HashArray;
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::mem::size_of;
+
+/// Groups of name hashes that were hard links to the same file content at the time of snapshot.
+/// First name in each group is the one that was hashed, other ones share its data hash.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct LinksChunk {
+    pub groups: Vec<Vec<HashArray<32>>>,
+}
+
+pub struct LinksHeader {
+    group_count: u64,
+    entry_count: u64,
+}
+
+impl LinksHeader {
+    pub fn to_array(&self) -> StdHashArray {
+        let mut array = HashArray::zero();
+        array.set_slice(0, BlockType::Links.magic());
+        array.set_u32(4, 0); //flags
+        array.set_u64(8, self.group_count);
+        array.set_u64(16, self.entry_count);
+        //bytes 24..64 are zeroed
+        array
+    }
+
+    pub fn from_array(array: StdHashArray) -> io::Result<Self> {
+        BlockType::Links.require_magic(array.get_slice(0))?;
+        if array.get_u32(4) != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "Unknown links header flags"));
+        }
+        let group_count = array.get_u64(8);
+        let entry_count = array.get_u64(16);
+        Ok(Self { group_count, entry_count })
+    }
+}
+
+impl LinksChunk {
+    pub fn new(groups: Vec<Vec<HashArray<32>>>) -> Self {
+        Self { groups }
+    }
+
+    pub fn entry_count(&self) -> usize {
+        self.groups.iter().map(|g| g.len()).sum()
+    }
+
+    pub fn read_body<R: Read + ?Sized>(header: LinksHeader, read: &mut R) -> io::Result<Self> {
+        let mut remaining = header.entry_count;
+        let mut groups = Vec::with_capacity((header.group_count as usize).min(1024 * 1024));
+        for _ in 0..header.group_count {
+            let mut len = [0u8; size_of::<u64>()];
+            read.read_exact(&mut len)?;
+            let len = u64::from_le_bytes(len);
+            remaining = remaining
+                .checked_sub(len)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Link group exceeds declared entry count"))?;
+            let mut group = Vec::with_capacity((len as usize).min(1024 * 1024));
+            for _ in 0..len {
+                let mut id = HashArray::zero();
+                read.read_exact(id.get_mut())?;
+                group.push(id);
+            }
+            groups.push(group);
+        }
+        if remaining != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Link groups are shorter than declared entry count",
+            ));
+        }
+        Ok(Self { groups })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let header = LinksHeader {
+            group_count: self.groups.len() as _,
+            entry_count: self.entry_count() as _,
+        };
+        write.write_all(header.to_array().get_ref())?;
+        for group in &self.groups {
+            write.write_all(&(group.len() as u64).to_le_bytes())?;
+            for id in group {
+                write.write_all(id.get_ref())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MeasureMemory for LinksChunk {
+    fn memory_usage(&self) -> usize {
+        self.groups.capacity() * size_of::<Vec<HashArray<32>>>()
+            + self.groups.iter().map(|g| g.capacity() * size_of::<HashArray<32>>()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_read() {
+        let chunk = LinksChunk::new(vec![
+            vec![HashArray::parse_fill_zero("01"), HashArray::parse_fill_zero("02")],
+            vec![
+                HashArray::parse_fill_zero("03"),
+                HashArray::parse_fill_zero("04"),
+                HashArray::parse_fill_zero("05"),
+            ],
+        ]);
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 64 + 2 * 8 + 5 * 32);
+
+        let mut read = bytes.as_slice();
+        let mut header = StdHashArray::zero();
+        read.read_exact(header.get_mut()).unwrap();
+        let mut flagged = header;
+        flagged.set_u32(4, 1);
+        assert!(LinksHeader::from_array(flagged).is_err());
+        let header = LinksHeader::from_array(header).unwrap();
+        let restored = LinksChunk::read_body(header, &mut read).unwrap();
+        assert_eq!(restored, chunk);
+        assert!(read.is_empty());
+    }
+}
@@ -1,14 +1,26 @@
+mod filter_chunk;
 mod hashes_chunk;
+mod links_chunk;
 mod names_chunk;
+mod shared_names;
+mod sizes_chunk;
+mod suffix_chunk;
+mod tree_chunk;
 
 use crate::HashArray;
 use digest::Digest;
+pub use filter_chunk::*;
 pub use hashes_chunk::*;
+pub use links_chunk::*;
 pub use names_chunk::*;
 use num_traits::FromPrimitive;
 use rustfft::num_traits;
+pub use shared_names::*;
+pub use sizes_chunk::*;
 use std::io;
 use std::io::ErrorKind;
+pub use suffix_chunk::*;
+pub use tree_chunk::*;
 
 pub const BLOCK_HEADER_MAGIC: [u8; 3] = *b"hSb";
 
@@ -24,6 +36,11 @@ pub enum BlockType {
     MainHeader = 1, //main header is always 64 bytes, should be only one in file,
     Hashes = 2,     //hashes chunk
     Names = 3,      //names of files for corresponding hashes
+    Links = 4,      //groups of names that were hard links to the same content
+    Sizes = 5,      //sizes of file contents for names
+    Tree = 6,       //aggregate hashes of directories
+    Filter = 7,     //probabilistic filter over content or name hashes
+    Suffixes = 8,   //suffix array over file paths for substring search
 
     Reserved = 254,
     MoreBlocks = 255,
@@ -79,6 +96,11 @@ pub trait ReadableChunk {
 pub enum AnyBlock {
     Hashes(HashesChunk),
     Names(NamesChunk),
+    Links(LinksChunk),
+    Sizes(SizesChunk),
+    Tree(TreeChunk),
+    Filter(FilterChunk),
+    Suffixes(SuffixChunk),
     Snapshot(),
     EndSnapshot(),
     Info(InfoChunk),
@@ -1,56 +1,505 @@
-use crate::file::chunks::BlockType;
+use crate::file::chunks::{BlockType, HashesChunk, SharedNamesChunk, SortOrder};
 use crate::file::StdHashArray;
-use crate::utils::{BungeeIndex, BungeeStr, MeasureMemory};
+use crate::utils::{
+    read_sized, read_u64, BungeeIndex, BungeeLookup, BungeeMerger, BungeeName, BungeeStr, BungeeStr32, MeasureMemory, NameTable,
+};
+use crate::HashArray;
 use std::io;
-use std::io::{Error, ErrorKind, Read};
+use std::io::{Error, ErrorKind, Read, Write};
 use std::mem::size_of;
 
+/// Names of files of snapshot, stored in any names table, see [`NameTable`]. Chunk is always stored with table in
+/// layout of [`BungeeStr`], so chunks of all tables read what the others wrote.
 #[derive(Clone, Eq, PartialEq, Hash)]
-pub struct NamesChunk {
-    bungee: BungeeStr,
-    indexes: Vec<BungeeIndex>,
+pub struct NamesChunk<T: NameTable = BungeeStr> {
+    bungee: T,
+    indexes: Vec<T::Index>,
+    /// Position in indexes of every hashes entry, in order of hashes chunk, empty when entries are not linked
+    entry_links: Vec<u64>,
+    /// Sort order of hashes chunk the links were built for
+    links_sort: SortOrder,
 }
 
+/// Names chunk with [`BungeeStr32`] table, its indexes take half of memory, for trees with many files. Table is
+/// limited to 4 GiB.
+pub type NamesChunk32 = NamesChunk<BungeeStr32>;
+
+/// Link value of hashes entry without name, see [`NamesChunk::set_entry_links`].
+pub const NO_LINK: u64 = u64::MAX;
+
 pub struct InfoChunk {}
 
+/// Entry visited by [`NamesWalk`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct NameEntry<'a, I = BungeeIndex> {
+    pub name: BungeeName<'a>,
+    pub at: I,
+    pub parent: Option<I>,
+    /// Zero for top level entries
+    pub depth: usize,
+}
+
+/// Positions of files in names chunk indexes, sorted by their entries, so entries are mapped back to positions
+/// without a copy of indexes.
+pub struct NamePositions<'a> {
+    indexes: &'a [BungeeIndex],
+    sorted: Vec<usize>,
+}
+
+impl<'a> NamePositions<'a> {
+    fn new(indexes: &'a [BungeeIndex]) -> Self {
+        let mut sorted = (0..indexes.len()).collect::<Vec<_>>();
+        sorted.sort_unstable_by_key(|&i| indexes[i].index);
+        Self { indexes, sorted }
+    }
+
+    /// Position of entry in indexes, `None` for directories.
+    pub fn get(&self, at: BungeeIndex) -> Option<usize> {
+        let found = self.sorted.binary_search_by_key(&at.index, |&i| self.indexes[i].index).ok()?;
+        Some(self.sorted[found])
+    }
+
+    pub fn is_file(&self, at: BungeeIndex) -> bool {
+        self.get(at).is_some()
+    }
+}
+
+/// Walk over all entries of names chunk in reverse order of pushing, from the last pushed entry to the first one.
+/// Entries are always pushed after their parents, so every entry is visited before its parent directory, and
+/// entries pushed by depth first scan stay grouped by directory. Nothing is allocated, files are told from
+/// directories by [`NamePositions`].
+pub struct NamesWalk<'a, T: NameTable = BungeeStr> {
+    bungee: &'a T,
+    next: Option<T::Index>,
+}
+
+impl<'a, T: NameTable> Iterator for NamesWalk<'a, T> {
+    type Item = NameEntry<'a, T::Index>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let at = self.next?;
+        self.next = self.bungee.reverse_skip(at);
+        let (name, parent) = self.bungee.reverse_follow(at);
+        Some(NameEntry {
+            name,
+            at,
+            parent,
+            depth: self.bungee.reverse_follow_iter(at).count() - 1,
+        })
+    }
+}
+
 pub struct NamesHeader {
-    bungee_size: u64,
-    bungee_entry_count: u64,
+    pub(super) bungee_size: u64,
+    pub(super) bungee_entry_count: u64,
+    /// Body is stored as [`SharedNamesChunk`]
+    pub(super) shared: bool,
+    /// Number of entry links stored after body
+    pub(super) link_count: u64,
+    /// Sort order of hashes chunk the entry links were built for
+    pub(super) links_sort: SortOrder,
 }
 
 impl NamesHeader {
+    const FLAG_SHARED: u32 = 0x1;
+    const FLAG_LINKED: u32 = 0x2;
+
+    pub fn to_array(&self) -> StdHashArray {
+        let mut array = HashArray::zero();
+        array.set_slice(0, BlockType::Names.magic());
+        let mut flags = 0;
+        if self.shared {
+            flags |= Self::FLAG_SHARED;
+        }
+        if self.link_count > 0 {
+            flags |= Self::FLAG_LINKED;
+        }
+        array.set_u32(4, flags);
+        array.set_u64(8, self.bungee_size);
+        array.set_u64(16, self.bungee_entry_count);
+        array.set_u64(24, self.link_count);
+        array.set_slice(32, [self.links_sort as u8]);
+        //bytes 33..64 are zeroed
+        array
+    }
+
     pub fn from_array(array: StdHashArray) -> io::Result<Self> {
         BlockType::Names.require_magic(array.get_slice(0))?;
         let flags = array.get_u32(4);
         let bungee_size = array.get_u64(8);
         let bungee_entry_count = array.get_u64(16);
+        let links_sort = SortOrder::from_u8(array.get_slice::<1>(32)[0])
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Unknown sort order of entry links"))?;
 
         Ok(Self {
             bungee_size,
             bungee_entry_count,
+            shared: flags & Self::FLAG_SHARED != 0,
+            link_count: if flags & Self::FLAG_LINKED != 0 { array.get_u64(24) } else { 0 },
+            links_sort,
         })
     }
 }
 
-impl NamesChunk {
-    pub fn new(bungee: BungeeStr, indexes: Vec<BungeeIndex>) -> Self {
-        Self { bungee, indexes }
+impl<T: NameTable> NamesChunk<T> {
+    pub fn new(bungee: T, indexes: Vec<T::Index>) -> Self {
+        Self {
+            bungee,
+            indexes,
+            entry_links: Vec::new(),
+            links_sort: SortOrder::Unordered,
+        }
+    }
+
+    /// Chunk with table of other type, entry links are kept.
+    pub fn from_names(names: NamesChunk) -> io::Result<Self> {
+        let (bungee, indexes) = T::from_bungee(names.bungee, names.indexes.into_iter().map(Ok))?;
+        Ok(Self {
+            bungee,
+            indexes,
+            entry_links: names.entry_links,
+            links_sort: names.links_sort,
+        })
+    }
+
+    /// Chunk with [`BungeeStr`] table, entry links are kept.
+    pub fn to_names(&self) -> io::Result<NamesChunk> {
+        let (bytes, indexes) = self.bungee.bungee_parts(&self.indexes);
+        let (bungee, ends) = BungeeStr::with_ends(bytes.into_owned())?;
+        Ok(NamesChunk {
+            bungee,
+            indexes: indexes.map(|at| ends.index(at)).collect::<io::Result<_>>()?,
+            entry_links: self.entry_links.clone(),
+            links_sort: self.links_sort,
+        })
+    }
+
+    /// Link every entry of hashes chunk to position in indexes, see [`crate::store::entry_links`]. Links are built for
+    /// hashes in given sort order, and they are not used for hashes sorted differently, see [`Self::links_match`].
+    pub fn set_entry_links(&mut self, links: Vec<u64>, sort: SortOrder) {
+        self.entry_links = links;
+        self.links_sort = sort;
+    }
+
+    /// Position in indexes of every hashes entry, empty when entries are not linked.
+    pub fn entry_links(&self) -> &[u64] {
+        &self.entry_links
+    }
+
+    /// Sort order of hashes chunk the entry links were built for.
+    pub fn links_sort(&self) -> SortOrder {
+        self.links_sort
+    }
+
+    /// Entry links point to names of given hashes chunk. Links of chunks without defined order are never used, as
+    /// order of their entries can change without notice.
+    pub fn links_match(&self, hashes: &HashesChunk) -> bool {
+        matches!(self.links_sort, SortOrder::SortedByName | SortOrder::SortedByData)
+            && self.links_sort == hashes.sort
+            && self.entry_links.len() == hashes.data.len()
+    }
+
+    /// Name entry of hashes entry at given position, resolved without hashing names.
+    pub fn entry_index(&self, entry: usize) -> Option<T::Index> {
+        let link = usize::try_from(*self.entry_links.get(entry)?).ok()?;
+        self.indexes.get(link).copied()
+    }
+
+    pub fn bungee(&self) -> &T {
+        &self.bungee
+    }
+
+    pub fn indexes(&self) -> &[T::Index] {
+        &self.indexes
+    }
+
+    /// Walk over all files and directories, from the last pushed entry to the first one, see [`NamesWalk`].
+    pub fn walk(&self) -> NamesWalk<'_, T> {
+        NamesWalk {
+            bungee: &self.bungee,
+            next: self.bungee.last_index(),
+        }
     }
 
+    /// Parent directory of entry, `None` for top level entries.
+    pub fn parent_of(&self, at: T::Index) -> Option<T::Index> {
+        self.bungee.reverse_follow(at).1
+    }
+
+    /// Compact chunk with files at given positions only, in given order. Entry links are not kept.
+    pub fn subset(&self, positions: impl IntoIterator<Item = usize>) -> NamesChunk {
+        let entries = positions.into_iter().map(|i| self.indexes[i]).collect::<Vec<_>>();
+        let mut merger = BungeeMerger::new();
+        let indexes = merger.add(None, &self.bungee, &entries);
+        NamesChunk::new(merger.into_bungee(), indexes)
+    }
+
+    /// Files of both chunks in one table with shared common directories, files of this chunk go first. Entry links
+    /// are not kept.
+    pub fn merge(&self, other: &Self) -> NamesChunk {
+        let mut merger = BungeeMerger::new();
+        let mut indexes = merger.add(None, &self.bungee, &self.indexes);
+        indexes.extend(merger.add(None, &other.bungee, &other.indexes));
+        NamesChunk::new(merger.into_bungee(), indexes)
+    }
+
+    /// Read names, shared form is expanded to full names table.
     pub fn read_body<R: Read + ?Sized>(header: NamesHeader, read: &mut R) -> io::Result<Self> {
-        if header.bungee_size > u32::MAX as _ {
-            return Err(Error::new(
-                ErrorKind::Unsupported,
-                "More that u32::MAX hash entries are not supported",
-            ));
+        if header.shared {
+            return Self::from_names(SharedNamesChunk::read_body(header, read)?.to_names()?);
+        }
+        if header.bungee_size > T::MAX_SIZE {
+            return Err(Error::new(ErrorKind::Unsupported, "Names table is too big for this table type"));
+        }
+        let (bungee, ends) = BungeeStr::with_ends(read_sized(read, header.bungee_size)?)?;
+        let indexes = (0..header.bungee_entry_count).map(|_| ends.index(read_u64(read)?));
+        let (bungee, indexes) = T::from_bungee(bungee, indexes)?;
+
+        let entry_links = read_entry_links(header.link_count, indexes.len(), read)?;
+        Ok(Self {
+            bungee,
+            indexes,
+            entry_links,
+            links_sort: header.links_sort,
+        })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let (bytes, indexes) = self.bungee.bungee_parts(&self.indexes);
+        let header = NamesHeader {
+            bungee_size: bytes.len() as _,
+            bungee_entry_count: indexes.len() as _,
+            shared: false,
+            link_count: self.entry_links.len() as _,
+            links_sort: self.links_sort,
+        };
+        write.write_all(header.to_array().get_ref())?;
+        write.write_all(&bytes)?;
+        for index in indexes {
+            write.write_all(&index.to_le_bytes())?;
         }
+        write_entry_links(&self.entry_links, write)
+    }
+}
+
+impl NamesChunk {
+    /// Index of names for resolving paths to entries and listing directories.
+    pub fn lookup(&self) -> BungeeLookup<'_> {
+        BungeeLookup::new(&self.bungee)
+    }
+
+    /// Map from entries back to their positions in indexes, built once for all lookups.
+    pub fn positions(&self) -> NamePositions<'_> {
+        NamePositions::new(&self.indexes)
+    }
+}
+
+pub(super) fn read_entry_links<R: Read + ?Sized>(count: u64, index_count: usize, read: &mut R) -> io::Result<Vec<u64>> {
+    let mut links = Vec::with_capacity(count.min(1024 * 1024) as usize);
+    for _ in 0..count {
+        let link = read_u64(read)?;
+        if link != NO_LINK && link >= index_count as u64 {
+            return Err(Error::new(ErrorKind::InvalidData, "Entry link out of indexes bounds"));
+        }
+        links.push(link);
+    }
+    Ok(links)
+}
 
-        todo!()
+pub(super) fn write_entry_links<W: Write>(links: &[u64], write: &mut W) -> io::Result<()> {
+    for link in links {
+        write.write_all(&link.to_le_bytes())?;
     }
+    Ok(())
 }
 
-impl MeasureMemory for NamesChunk {
+impl<T: NameTable> Default for NamesChunk<T> {
+    fn default() -> Self {
+        Self::new(T::default(), Vec::new())
+    }
+}
+
+impl<T: NameTable> MeasureMemory for NamesChunk<T> {
     fn memory_usage(&self) -> usize {
-        (self.indexes.capacity() * size_of::<BungeeIndex>()) + self.bungee.memory_usage()
+        (self.indexes.capacity() * size_of::<T::Index>()) + self.entry_links.capacity() * size_of::<u64>() + self.bungee.memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_read_raw_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut bungee = BungeeStr::new();
+        let dir = bungee.push(None, "archive");
+        let a = bungee.push_os(dir, OsStr::from_bytes(b"r\xe9sum\xe9.txt")).unwrap();
+        let b = bungee.push_os(dir, OsStr::from_bytes("résumé.txt".as_bytes())).unwrap();
+        let chunk = NamesChunk::new(bungee, vec![a, b]);
+
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let mut read = bytes.as_slice();
+        let mut header = StdHashArray::zero();
+        read.read_exact(header.get_mut()).unwrap();
+        let restored = NamesChunk::read_body(NamesHeader::from_array(header).unwrap(), &mut read).unwrap();
+        assert!(read.is_empty());
+        assert!(restored == chunk);
+
+        let paths = restored
+            .indexes()
+            .iter()
+            .map(|i| restored.bungee().os_path_of(*i))
+            .collect::<Vec<_>>();
+        assert_eq!(paths[0].as_os_str().as_bytes(), b"archive/r\xe9sum\xe9.txt");
+        assert_eq!(paths[1].as_os_str().as_bytes(), "archive/résumé.txt".as_bytes());
+        assert_ne!(restored.bungee().path_of("/", a), restored.bungee().path_of("/", b));
+    }
+
+    #[test]
+    fn test_read_truncated() {
+        let mut bungee = BungeeStr::new();
+        let a = bungee.push(None, "a.txt").unwrap();
+        let mut bytes = Vec::new();
+        NamesChunk::new(bungee, vec![a]).write(&mut bytes).unwrap();
+        let read = |bytes: &[u8]| {
+            let mut read = &bytes[64..];
+            let header = NamesHeader::from_array(HashArray::new(bytes[..64].try_into().unwrap())).unwrap();
+            <NamesChunk>::read_body(header, &mut read).map(|_| ())
+        };
+        assert!(read(&bytes).is_ok());
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+        let mut sized = bytes.clone();
+        sized[8..16].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert_eq!(read(&sized).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+        let mut counted = bytes;
+        counted[16..24].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert_eq!(read(&counted).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_entry_links() {
+        let mut bungee = BungeeStr::new();
+        let a = bungee.push(None, "a").unwrap();
+        let b = bungee.push(None, "b").unwrap();
+        let mut chunk = NamesChunk::new(bungee, vec![a, b]);
+        chunk.set_entry_links(vec![1, NO_LINK, 0], SortOrder::SortedByData);
+        assert_eq!(chunk.entry_index(0), Some(b));
+        assert_eq!(chunk.entry_index(1), None);
+        assert_eq!(chunk.entry_index(3), None);
+
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let read = |bytes: &[u8]| {
+            let mut read = bytes;
+            let mut header = StdHashArray::zero();
+            read.read_exact(header.get_mut()).unwrap();
+            NamesChunk::read_body(NamesHeader::from_array(header)?, &mut read)
+        };
+        let restored = read(&bytes).unwrap();
+        assert!(restored == chunk);
+        assert_eq!(restored.links_sort(), SortOrder::SortedByData);
+        //link to position past indexes
+        let mut past = bytes.clone();
+        past.truncate(bytes.len() - size_of::<u64>());
+        past.extend_from_slice(&2u64.to_le_bytes());
+        assert_eq!(read(&past).err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+        //unknown sort order
+        let mut sorted = bytes;
+        sorted[32] = 9;
+        assert_eq!(read(&sorted).err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn test_names_32() {
+        let mut bungee = BungeeStr::new();
+        let dir = bungee.push(None, "dir");
+        let a = bungee.push(dir, "a.txt").unwrap();
+        let b = bungee.push(dir, "b.txt").unwrap();
+        let mut names = NamesChunk::new(bungee, vec![b, a]);
+        names.set_entry_links(vec![1, 0], SortOrder::SortedByName);
+        let compact = NamesChunk32::from_names(names.clone()).unwrap();
+        assert_eq!(compact.bungee().path_of("/", compact.indexes()[0]), "dir/b.txt");
+        assert!(compact.memory_usage() < names.memory_usage());
+
+        let read = |bytes: &[u8]| {
+            let mut read = &bytes[64..];
+            let header = NamesHeader::from_array(HashArray::new(bytes[..64].try_into().unwrap())).unwrap();
+            NamesChunk32::read_body(header, &mut read)
+        };
+        //both chunks are stored the same way
+        let (mut plain, mut written) = (Vec::new(), Vec::new());
+        names.write(&mut plain).unwrap();
+        compact.write(&mut written).unwrap();
+        assert_eq!(plain, written);
+        assert!(read(&plain).unwrap() == compact);
+        let mut shared = Vec::new();
+        SharedNamesChunk::new(&names, 1).unwrap().write(&mut shared).unwrap();
+        assert!(read(&shared).unwrap() == compact);
+        assert!(compact.to_names().unwrap() == names);
+
+        let mut large = plain;
+        large[8..16].copy_from_slice(&(1u64 << 33).to_le_bytes());
+        assert_eq!(read(&large).err().unwrap().kind(), ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_subset_merge() {
+        let mut bungee = BungeeStr::new();
+        let dir = bungee.push(None, "photos");
+        let a = bungee.push(dir, "a.jpg").unwrap();
+        let b = bungee.push(dir, "b.jpg").unwrap();
+        let chunk = NamesChunk::new(bungee, vec![a, b]);
+        let mut other = BungeeStr::new();
+        let dir = other.push(None, "photos");
+        let c = other.push(dir, "c.jpg").unwrap();
+        let other = NamesChunk::new(other, vec![c]);
+
+        let paths = |c: &NamesChunk| c.indexes().iter().map(|i| c.bungee().path_of("/", *i)).collect::<Vec<_>>();
+        assert_eq!(paths(&chunk.subset([1])), ["photos/b.jpg"]);
+        let merged = chunk.merge(&other);
+        assert_eq!(paths(&merged), ["photos/a.jpg", "photos/b.jpg", "photos/c.jpg"]);
+        let positions = merged.positions();
+        assert_eq!(merged.walk().filter(|e| !positions.is_file(e.at)).count(), 1);
+    }
+
+    #[test]
+    fn test_walk() {
+        let mut bungee = BungeeStr::new();
+        let src = bungee.push(None, "src");
+        let main = bungee.push(src, "main.rs").unwrap();
+        let readme = bungee.push(None, "README").unwrap();
+        let utils = bungee.push(src, "utils");
+        let lib = bungee.push(utils, "lib.rs").unwrap();
+        let chunk = NamesChunk::new(bungee, vec![readme, main, lib]);
+
+        let positions = chunk.positions();
+        let walked = chunk
+            .walk()
+            .map(|e| (e.name.to_string(), e.depth, positions.is_file(e.at)))
+            .collect::<Vec<_>>();
+        //reverse order of pushing, entries come before their parents
+        let expected = [
+            ("lib.rs", 2, true),
+            ("utils", 1, false),
+            ("README", 0, true),
+            ("main.rs", 1, true),
+            ("src", 0, false),
+        ];
+        let expected = expected.map(|(n, d, f)| (n.to_string(), d, f));
+        assert_eq!(walked, expected);
+        let parents = chunk.walk().map(|e| e.parent).collect::<Vec<_>>();
+        assert_eq!(parents, [utils, src, None, src, None]);
+        assert_eq!(chunk.parent_of(lib), utils);
+        assert_eq!(chunk.parent_of(readme), None);
+        assert_eq!(positions.get(lib), Some(2));
+        assert_eq!(positions.get(readme), Some(0));
+        assert_eq!(positions.get(utils.unwrap()), None);
+        let children = chunk.lookup().children(src).map(|(_, at)| at).collect::<Vec<_>>();
+        assert_eq!(children, [main, utils.unwrap()]);
     }
 }
@@ -0,0 +1,462 @@
+use crate::file::chunks::names_chunk::{read_entry_links, write_entry_links};
+use crate::file::chunks::{NamesChunk, NamesHeader, SortOrder};
+use crate::utils::{read_sized, read_u64, BungeeIndex, BungeeLookup, BungeeName, BungeeStr, MeasureMemory};
+use crate::HashArray;
+use digest::Digest;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+
+/// Directory of main names table, whose content is stored once in templates table.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Mount {
+    /// Directory entry in main table
+    pub dir: BungeeIndex,
+    /// Root entry of template, its name is not part of paths
+    pub template: BungeeIndex,
+}
+
+/// File of [`SharedNamesChunk`], entry is in templates table when file is under mounted directory.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SharedIndex {
+    pub mount: Option<usize>,
+    pub at: BungeeIndex,
+}
+
+/// Names table where repeated directory subtrees are stored once as templates, and every repetition only references
+/// its template. Files keep order of the original [`NamesChunk`], and resolve to the same paths.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct SharedNamesChunk {
+    main: BungeeStr,
+    templates: BungeeStr,
+    mounts: Vec<Mount>,
+    files: Vec<SharedIndex>,
+    /// See [`NamesChunk::entry_links`]
+    entry_links: Vec<u64>,
+    /// See [`NamesChunk::links_sort`]
+    links_sort: SortOrder,
+}
+
+/// Hash of directory structure and number of entries below it.
+#[derive(Copy, Clone)]
+struct Subtree {
+    hash: HashArray<32>,
+    entries: usize,
+}
+
+fn broken_names() -> Error {
+    Error::new(ErrorKind::InvalidData, "Names table has entry whose parent is not stored before it")
+}
+
+impl SharedNamesChunk {
+    const NO_MOUNT: u64 = u64::MAX;
+
+    /// Share directories that are repeated at least twice and have at least `min_entries` entries below them. Only
+    /// the outermost repeated directories are shared. Fails when names table was read corrupted.
+    pub fn new(names: &NamesChunk, min_entries: usize) -> io::Result<Self> {
+        let bungee = names.bungee();
+        let lookup = BungeeLookup::new(bungee);
+        let files = names.indexes().iter().copied().collect::<HashSet<_>>();
+        //children are always pushed after parents, so reversed order visits children first
+        let mut entries = bungee.reverse_entries().map(|(_, at, parent)| (at, parent)).collect::<Vec<_>>();
+        let mut subtrees = HashMap::<BungeeIndex, Subtree>::new();
+        for &(at, _) in &entries {
+            let mut hasher = Sha256::new();
+            let mut count = 0;
+            for (name, child) in lookup.children(Some(at)) {
+                let sub = subtrees.get(&child).ok_or_else(broken_names)?;
+                hasher.update((name.as_bytes().len() as u64).to_le_bytes());
+                hasher.update(name.as_bytes());
+                hasher.update([name.is_raw() as u8, files.contains(&child) as u8]);
+                hasher.update(sub.hash.get_ref());
+                count += sub.entries + 1;
+            }
+            let hash = HashArray::new(hasher.finalize().into());
+            subtrees.insert(at, Subtree { hash, entries: count });
+        }
+        let mut repeats = HashMap::<HashArray<32>, usize>::new();
+        for sub in subtrees.values().filter(|s| s.entries > 0) {
+            *repeats.entry(sub.hash).or_default() += 1;
+        }
+
+        let mut result = Self::default();
+        //entry of original table to entry in main table, or to mount for mounted directories
+        let mut main_of = HashMap::<BungeeIndex, BungeeIndex>::new();
+        let mut mount_of = HashMap::<BungeeIndex, usize>::new();
+        let mut template_of = HashMap::<HashArray<32>, BungeeIndex>::new();
+        let mut template_children = HashMap::<(BungeeIndex, Vec<u8>), BungeeIndex>::new();
+        entries.reverse();
+        for (at, parent) in entries {
+            let parent_main = match parent {
+                Some(p) if mount_of.contains_key(&p) => continue,
+                Some(p) => match main_of.get(&p) {
+                    Some(main) => Some(*main),
+                    None => continue, //inside mounted directory
+                },
+                None => None,
+            };
+            let (name, _) = bungee.reverse_follow(at);
+            let Some(new) = result.main.push_os(parent_main, &name.to_os_str()) else {
+                continue;
+            };
+            main_of.insert(at, new);
+            let sub = subtrees.get(&at).ok_or_else(broken_names)?;
+            if sub.entries == 0 || sub.entries < min_entries || repeats.get(&sub.hash).copied().unwrap_or(0) < 2 {
+                continue;
+            }
+            let template = *template_of.entry(sub.hash).or_insert_with(|| {
+                let root = result.templates.push_os(None, &name.to_os_str()).unwrap();
+                result.copy_template(&lookup, at, root, &mut template_children);
+                root
+            });
+            mount_of.insert(at, result.mounts.len());
+            result.mounts.push(Mount { dir: new, template });
+        }
+
+        for &file in names.indexes() {
+            let mut components = Vec::new();
+            let mut mount = None;
+            for (name, at) in bungee.reverse_follow_iter(file) {
+                if let Some(m) = mount_of.get(&at) {
+                    mount = Some(*m);
+                    break;
+                }
+                components.push(name.as_bytes());
+            }
+            let index = match mount {
+                Some(m) if !components.is_empty() => {
+                    let mut at = result.mounts[m].template;
+                    for name in components.iter().rev() {
+                        at = *template_children.get(&(at, name.to_vec())).ok_or_else(broken_names)?;
+                    }
+                    SharedIndex { mount: Some(m), at }
+                }
+                //mounted directory itself, or file outside of mounts
+                _ => SharedIndex {
+                    mount: None,
+                    at: *main_of.get(&file).ok_or_else(broken_names)?,
+                },
+            };
+            result.files.push(index);
+        }
+        result.entry_links = names.entry_links().to_vec();
+        result.links_sort = names.links_sort();
+        Ok(result)
+    }
+
+    fn copy_template(
+        &mut self,
+        lookup: &BungeeLookup,
+        from: BungeeIndex,
+        to: BungeeIndex,
+        children: &mut HashMap<(BungeeIndex, Vec<u8>), BungeeIndex>,
+    ) {
+        for (name, child) in lookup.children(Some(from)) {
+            let Some(new) = self.templates.push_os(Some(to), &name.to_os_str()) else {
+                continue;
+            };
+            children.entry((to, name.as_bytes().to_vec())).or_insert(new);
+            self.copy_template(lookup, child, new, children);
+        }
+    }
+
+    pub fn main(&self) -> &BungeeStr {
+        &self.main
+    }
+
+    pub fn templates(&self) -> &BungeeStr {
+        &self.templates
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    pub fn files(&self) -> &[SharedIndex] {
+        &self.files
+    }
+
+    /// Names of template entry up to its root, root is excluded.
+    fn template_parts(&self, at: BungeeIndex) -> impl Iterator<Item = (BungeeName<'_>, BungeeIndex)> {
+        let mut iter = self.templates.reverse_follow_iter(at).peekable();
+        std::iter::from_fn(move || {
+            let item = iter.next()?;
+            iter.peek().is_some().then_some(item)
+        })
+    }
+
+    /// Lossless path, the same as of the file in original names table.
+    pub fn os_path_of(&self, index: SharedIndex) -> PathBuf {
+        match index.mount {
+            None => self.main.os_path_of(index.at),
+            Some(m) => {
+                let mut parts = self.template_parts(index.at).map(|(n, _)| n).collect::<Vec<_>>();
+                parts.reverse();
+                let mut path = self.main.os_path_of(self.mounts[m].dir);
+                path.extend(parts.iter().map(|n| n.to_os_str()));
+                path
+            }
+        }
+    }
+
+    /// Display form of path, see [`BungeeStr::path_of`].
+    pub fn path_of(&self, sep: &str, index: SharedIndex) -> String {
+        match index.mount {
+            None => self.main.path_of(sep, index.at),
+            Some(m) => {
+                let mut parts = self.template_parts(index.at).map(|(n, _)| n.escaped()).collect::<Vec<_>>();
+                parts.reverse();
+                let mut path = self.main.path_of(sep, self.mounts[m].dir);
+                for part in parts {
+                    path.push_str(sep);
+                    path.push_str(&part);
+                }
+                path
+            }
+        }
+    }
+
+    /// Paths of all files, in order of original names table.
+    pub fn paths(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.files.iter().map(|f| self.os_path_of(*f))
+    }
+
+    /// Expand to full names table, every mount gets its own copy of template. Fails when file of mount is not part
+    /// of its template.
+    pub fn to_names(&self) -> io::Result<NamesChunk> {
+        let mut bungee = self.main.clone();
+        let template_lookup = BungeeLookup::new(&self.templates);
+        let mut copies = HashMap::<(usize, BungeeIndex), BungeeIndex>::new();
+        for (m, mount) in self.mounts.iter().enumerate() {
+            let mut stack = vec![(mount.template, mount.dir)];
+            while let Some((from, to)) = stack.pop() {
+                for (name, child) in template_lookup.children(Some(from)) {
+                    if let Some(new) = bungee.push_os(Some(to), &name.to_os_str()) {
+                        copies.insert((m, child), new);
+                        stack.push((child, new));
+                    }
+                }
+            }
+        }
+        let indexes = self
+            .files
+            .iter()
+            .map(|f| match f.mount {
+                None => Ok(f.at),
+                Some(m) => copies
+                    .get(&(m, f.at))
+                    .copied()
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "File is outside of its mount template")),
+            })
+            .collect::<io::Result<_>>()?;
+        let mut names = NamesChunk::new(bungee, indexes);
+        names.set_entry_links(self.entry_links.clone(), self.links_sort);
+        Ok(names)
+    }
+
+    pub fn read_body<R: Read + ?Sized>(header: NamesHeader, read: &mut R) -> io::Result<Self> {
+        let read_bungee = |read: &mut R, size: u64| {
+            usize::try_from(size).map_err(|_| Error::new(ErrorKind::Unsupported, "Names table doesn't fit in memory"))?;
+            BungeeStr::with_ends(read_sized(read, size)?)
+        };
+        let (main, main_ends) = read_bungee(read, header.bungee_size)?;
+        let size = read_u64(read)?;
+        let (templates, template_ends) = read_bungee(read, size)?;
+        let count = read_u64(read)?;
+        let mut mounts = Vec::with_capacity(count.min(1024 * 1024) as usize);
+        for _ in 0..count {
+            let dir = main_ends.index(read_u64(read)?)?;
+            let template = template_ends.index(read_u64(read)?)?;
+            mounts.push(Mount { dir, template });
+        }
+        let mut files = Vec::with_capacity(header.bungee_entry_count.min(1024 * 1024) as usize);
+        for _ in 0..header.bungee_entry_count {
+            let at = read_u64(read)?;
+            let mount = match read_u64(read)? {
+                Self::NO_MOUNT => None,
+                m => Some(
+                    usize::try_from(m)
+                        .ok()
+                        .filter(|m| *m < mounts.len())
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Mount index out of bounds"))?,
+                ),
+            };
+            let at = match mount {
+                Some(_) => template_ends.index(at)?,
+                None => main_ends.index(at)?,
+            };
+            files.push(SharedIndex { mount, at });
+        }
+        let entry_links = read_entry_links(header.link_count, files.len(), read)?;
+        Ok(Self {
+            main,
+            templates,
+            mounts,
+            files,
+            entry_links,
+            links_sort: header.links_sort,
+        })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let header = NamesHeader {
+            bungee_size: self.main.raw_bytes().len() as _,
+            bungee_entry_count: self.files.len() as _,
+            shared: true,
+            link_count: self.entry_links.len() as _,
+            links_sort: self.links_sort,
+        };
+        write.write_all(header.to_array().get_ref())?;
+        write.write_all(self.main.raw_bytes())?;
+        write.write_all(&(self.templates.raw_bytes().len() as u64).to_le_bytes())?;
+        write.write_all(self.templates.raw_bytes())?;
+        write.write_all(&(self.mounts.len() as u64).to_le_bytes())?;
+        for mount in &self.mounts {
+            write.write_all(&(mount.dir.index.get() as u64).to_le_bytes())?;
+            write.write_all(&(mount.template.index.get() as u64).to_le_bytes())?;
+        }
+        for file in &self.files {
+            write.write_all(&(file.at.index.get() as u64).to_le_bytes())?;
+            write.write_all(&file.mount.map_or(Self::NO_MOUNT, |m| m as u64).to_le_bytes())?;
+        }
+        write_entry_links(&self.entry_links, write)
+    }
+}
+
+impl MeasureMemory for SharedNamesChunk {
+    fn memory_usage(&self) -> usize {
+        self.main.memory_usage()
+            + self.templates.memory_usage()
+            + self.mounts.capacity() * size_of::<Mount>()
+            + self.files.capacity() * size_of::<SharedIndex>()
+            + self.entry_links.capacity() * size_of::<u64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::{Snapshot, StdHashArray};
+    use crate::store::tests::snapshot_of;
+    use crate::PathIdentity;
+
+    fn names(paths: &[&str]) -> NamesChunk {
+        let mut bungee = BungeeStr::new();
+        let mut dirs = HashMap::<String, Option<BungeeIndex>>::new();
+        let mut indexes = Vec::new();
+        for path in paths {
+            let mut parent = None;
+            let mut prefix = String::new();
+            for name in path.split('/') {
+                prefix.push('/');
+                prefix.push_str(name);
+                parent = *dirs.entry(prefix.clone()).or_insert_with(|| bungee.push(parent, name));
+            }
+            indexes.push(parent.unwrap());
+        }
+        NamesChunk::new(bungee, indexes)
+    }
+
+    fn read_shared(bytes: &[u8]) -> io::Result<NamesChunk> {
+        let mut read = &bytes[64..];
+        let header = NamesHeader::from_array(HashArray::new(bytes[..64].try_into().unwrap()))?;
+        NamesChunk::read_body(header, &mut read)
+    }
+
+    #[test]
+    fn test_shared_names() {
+        let mut paths = Vec::new();
+        for project in ["a", "b", "c/nested"] {
+            for file in ["target/debug/app", "target/debug/deps/lib.rlib", "target/release/app"] {
+                paths.push(format!("{project}/{file}"));
+            }
+            paths.push(format!("{project}/src/{project}.rs"));
+        }
+        paths.push("target/other".to_string());
+        let paths = paths.iter().map(|p| p.as_str()).collect::<Vec<_>>();
+        let original = names(&paths);
+        let shared = SharedNamesChunk::new(&original, 3).unwrap();
+        assert_eq!(shared.mounts().len(), 3);
+        assert!(shared.main().raw_bytes().len() + shared.templates().raw_bytes().len() < original.bungee().raw_bytes().len());
+        let resolved = shared.paths().collect::<Vec<_>>();
+        let expected = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        assert_eq!(resolved, expected);
+        assert_eq!(shared.path_of("/", shared.files()[1]), "a/target/debug/deps/lib.rlib");
+
+        let mut bytes = Vec::new();
+        shared.write(&mut bytes).unwrap();
+        let mut read = bytes.as_slice();
+        let mut header = StdHashArray::zero();
+        read.read_exact(header.get_mut()).unwrap();
+        let header = NamesHeader::from_array(header).unwrap();
+        let restored = <NamesChunk>::read_body(header, &mut read).unwrap();
+        assert!(read.is_empty());
+        let restored_paths = restored
+            .indexes()
+            .iter()
+            .map(|i| restored.bungee().os_path_of(*i))
+            .collect::<Vec<_>>();
+        assert_eq!(restored_paths, expected);
+
+        //file of mount outside of its template
+        let mut broken = shared.clone();
+        broken.mounts[0].template = broken.files[0].at;
+        assert_eq!(broken.to_names().err().unwrap().kind(), ErrorKind::InvalidData);
+
+        //nothing is shared below threshold
+        assert!(SharedNamesChunk::new(&original, 100).unwrap().mounts().is_empty());
+    }
+
+    #[test]
+    fn test_read_corrupted() {
+        let original = names(&["a/x/1", "a/x/2", "b/x/1", "b/x/2"]);
+        let mut bytes = Vec::new();
+        SharedNamesChunk::new(&original, 2).unwrap().write(&mut bytes).unwrap();
+        assert!(read_shared(&bytes).is_ok());
+        assert_eq!(
+            read_shared(&bytes[..bytes.len() - 1]).err().unwrap().kind(),
+            ErrorKind::UnexpectedEof
+        );
+
+        //huge declared sizes and counts fail on missing data, without allocating them
+        let mut sized = bytes.clone();
+        sized[8..16].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert_eq!(read_shared(&sized).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+        let mut counted = bytes.clone();
+        counted[16..24].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        assert_eq!(read_shared(&counted).err().unwrap().kind(), ErrorKind::UnexpectedEof);
+
+        //mount index of the last file out of bounds
+        let mut mounted = bytes.clone();
+        let at = mounted.len() - 8;
+        mounted[at..].copy_from_slice(&7u64.to_le_bytes());
+        assert_eq!(read_shared(&mounted).err().unwrap().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_snapshot_shared_names() {
+        let mut files = Vec::new();
+        for copy in ["a", "b", "c"] {
+            for file in ["node_modules/x/index.js", "node_modules/x/package.json", "node_modules/y.js"] {
+                files.push(format!("{copy}/{file}"));
+            }
+        }
+        let files = files.iter().map(|f| (f.as_str(), b"js".as_slice())).collect::<Vec<_>>();
+        let snapshot = snapshot_of(PathIdentity::EXACT, &files);
+        let mut shared = Vec::new();
+        snapshot.write_shared(&mut shared, 4).unwrap();
+        let restored = Snapshot::read(&mut shared.as_slice()).unwrap();
+        let paths = |s: &Snapshot| {
+            s.names
+                .indexes()
+                .iter()
+                .map(|i| s.names.bungee().os_path_of(*i))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(paths(&restored), paths(&snapshot));
+        assert_eq!(restored.hashes.data, snapshot.hashes.data);
+    }
+}
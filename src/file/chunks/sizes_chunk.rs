@@ -0,0 +1,130 @@
+use crate::file::chunks::BlockType;
+use crate::file::StdHashArray;
+use crate::utils::MeasureMemory;
+use crate::HashArray;
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::mem::size_of;
+
+/// Size of file content for name hashes, sorted by name.
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
+pub struct SizeEntry {
+    pub id: HashArray<32>,
+    pub size: u64,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct SizesChunk {
+    entries: Vec<SizeEntry>,
+}
+
+pub struct SizesHeader {
+    count: u64,
+}
+
+impl SizesHeader {
+    pub fn to_array(&self) -> StdHashArray {
+        let mut array = HashArray::zero();
+        array.set_slice(0, BlockType::Sizes.magic());
+        array.set_u32(4, 0); //flags
+        array.set_u64(8, self.count);
+        //bytes 16..64 are zeroed
+        array
+    }
+
+    pub fn from_array(array: StdHashArray) -> io::Result<Self> {
+        BlockType::Sizes.require_magic(array.get_slice(0))?;
+        let count = array.get_u64(8);
+        Ok(Self { count })
+    }
+}
+
+impl SizesChunk {
+    const ENTRY_SIZE: usize = 32 + size_of::<u64>();
+
+    pub fn new(mut entries: Vec<SizeEntry>) -> Self {
+        entries.sort_unstable();
+        entries.dedup_by_key(|e| e.id);
+        Self { entries }
+    }
+
+    pub fn entries(&self) -> &[SizeEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, id: &HashArray<32>) -> Option<u64> {
+        let index = self.entries.binary_search_by_key(id, |e| e.id).ok()?;
+        Some(self.entries[index].size)
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size).sum()
+    }
+
+    pub fn read_body<R: Read + ?Sized>(header: SizesHeader, read: &mut R) -> io::Result<Self> {
+        let count = usize::try_from(header.count)
+            .ok()
+            .filter(|v| v.checked_mul(Self::ENTRY_SIZE).is_some())
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Too many size entries"))?;
+        let mut entries = Vec::with_capacity(count.min(1024 * 1024));
+        let mut buf = [0u8; Self::ENTRY_SIZE];
+        for _ in 0..count {
+            read.read_exact(&mut buf)?;
+            let mut id = HashArray::zero();
+            id.get_mut().copy_from_slice(&buf[..32]);
+            let size = u64::from_le_bytes(buf[32..].try_into().unwrap());
+            entries.push(SizeEntry { id, size });
+        }
+        if !entries.windows(2).all(|w| w[0].id < w[1].id) {
+            return Err(Error::new(ErrorKind::InvalidData, "Size entries are not sorted by name"));
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let header = SizesHeader {
+            count: self.entries.len() as _,
+        };
+        write.write_all(header.to_array().get_ref())?;
+        for e in &self.entries {
+            write.write_all(e.id.get_ref())?;
+            write.write_all(&e.size.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl MeasureMemory for SizesChunk {
+    fn memory_usage(&self) -> usize {
+        self.entries.capacity() * size_of::<SizeEntry>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_read() {
+        let entry = |id: &str, size| SizeEntry {
+            id: HashArray::parse_fill_zero(id),
+            size,
+        };
+        let chunk = SizesChunk::new(vec![entry("03", 7), entry("01", 0), entry("02", u64::MAX)]);
+        assert_eq!(chunk.get(&HashArray::parse_fill_zero("03")), Some(7));
+        assert_eq!(chunk.get(&HashArray::parse_fill_zero("04")), None);
+
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 64 + 3 * 40);
+        let mut read = bytes.as_slice();
+        let mut header = StdHashArray::zero();
+        read.read_exact(header.get_mut()).unwrap();
+        let restored = SizesChunk::read_body(SizesHeader::from_array(header).unwrap(), &mut read).unwrap();
+        assert_eq!(restored, chunk);
+    }
+}
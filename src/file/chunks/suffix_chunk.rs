@@ -0,0 +1,225 @@
+use crate::file::chunks::{BlockType, NamesChunk};
+use crate::file::StdHashArray;
+use crate::utils::{read_sized, MeasureMemory};
+use crate::HashArray;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::mem::size_of;
+use suffix_array::SuffixArray;
+
+/// Suffix array over display paths of all files of names chunk, in order of names chunk indexes. Paths are stored
+/// as text, each path is terminated by zero byte, so substring searches never cross paths.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct SuffixChunk {
+    /// Id of names chunk paths were taken from, see [`SuffixChunk::is_built_for`]
+    names_id: u64,
+    text: Vec<u8>,
+    suffixes: Vec<u32>,
+    /// Start of every path in text
+    starts: Vec<u32>,
+}
+
+pub struct SuffixHeader {
+    text_size: u64,
+    path_count: u64,
+    names_id: u64,
+}
+
+impl SuffixHeader {
+    pub fn to_array(&self) -> StdHashArray {
+        let mut array = HashArray::zero();
+        array.set_slice(0, BlockType::Suffixes.magic());
+        array.set_u32(4, 0); //flags
+        array.set_u64(8, self.text_size);
+        array.set_u64(16, self.path_count);
+        array.set_u64(24, self.names_id);
+        //bytes 32..64 are zeroed
+        array
+    }
+
+    pub fn from_array(array: StdHashArray) -> io::Result<Self> {
+        BlockType::Suffixes.require_magic(array.get_slice(0))?;
+        Ok(Self {
+            text_size: array.get_u64(8),
+            path_count: array.get_u64(16),
+            names_id: array.get_u64(24),
+        })
+    }
+}
+
+impl SuffixChunk {
+    const SEPARATOR: u8 = 0;
+
+    pub fn new(names: &NamesChunk) -> io::Result<Self> {
+        let mut text = Vec::new();
+        for &at in names.indexes() {
+            text.extend_from_slice(names.bungee().path_of("/", at).as_bytes());
+            text.push(Self::SEPARATOR);
+        }
+        if u32::try_from(text.len()).is_err() {
+            return Err(Error::new(ErrorKind::Unsupported, "Names are too big for suffix array"));
+        }
+        let (_, suffixes) = SuffixArray::new(&text).into_parts();
+        let starts = Self::path_starts(&text);
+        Ok(Self {
+            names_id: Self::names_id(names),
+            text,
+            suffixes,
+            starts,
+        })
+    }
+
+    /// Short digest of names table and indexes, changes whenever paths of names chunk could change.
+    fn names_id(names: &NamesChunk) -> u64 {
+        let mut hasher = Sha256::new();
+        hasher.update(names.bungee().raw_bytes());
+        for at in names.indexes() {
+            hasher.update((at.index.get() as u64).to_le_bytes());
+        }
+        u64::from_le_bytes(hasher.finalize()[..8].try_into().unwrap())
+    }
+
+    /// Whether paths were taken from given names chunk, suffixes of other chunk give wrong paths and must not be used.
+    pub fn is_built_for(&self, names: &NamesChunk) -> bool {
+        !self.is_empty() && self.names_id == Self::names_id(names)
+    }
+
+    fn path_starts(text: &[u8]) -> Vec<u32> {
+        let ends = text
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == Self::SEPARATOR)
+            .map(|(i, _)| i as u32 + 1);
+        [0].into_iter().chain(ends).take_while(|v| (*v as usize) < text.len()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Number of paths.
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    /// Display path at position of names chunk indexes.
+    pub fn path(&self, ordinal: usize) -> &str {
+        let start = self.starts[ordinal] as usize;
+        let end = self.starts.get(ordinal + 1).map(|v| *v as usize).unwrap_or(self.text.len()) - 1;
+        //text is created from strings and validated on read
+        std::str::from_utf8(&self.text[start..end]).unwrap_or_default()
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = &str> + '_ {
+        (0..self.len()).map(|i| self.path(i))
+    }
+
+    /// Sorted and deduplicated ordinals of paths containing given text.
+    pub fn find(&self, pattern: &str) -> Vec<usize> {
+        let pattern = pattern.as_bytes();
+        if pattern.is_empty() {
+            return (0..self.len()).collect();
+        }
+        if pattern.contains(&Self::SEPARATOR) {
+            return Vec::new();
+        }
+        let suffix = |v: &u32| &self.text[*v as usize..];
+        let start = self.suffixes.partition_point(|v| suffix(v) < pattern);
+        let end = start + self.suffixes[start..].partition_point(|v| suffix(v).starts_with(pattern));
+        let mut found = self.suffixes[start..end]
+            .iter()
+            .map(|&v| self.starts.partition_point(|s| *s <= v) - 1)
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+    pub fn read_body<R: Read + ?Sized>(header: SuffixHeader, read: &mut R) -> io::Result<Self> {
+        let size = u32::try_from(header.text_size).map_err(|_| Error::new(ErrorKind::Unsupported, "Suffix text is too big"))?;
+        let text = read_sized(read, size as u64)?;
+        if std::str::from_utf8(&text).is_err() || text.last().is_some_and(|v| *v != Self::SEPARATOR) {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid suffix text"));
+        }
+        let mut suffixes = Vec::with_capacity(text.len() + 1);
+        let mut buf = [0u8; size_of::<u32>()];
+        for _ in 0..=text.len() {
+            read.read_exact(&mut buf)?;
+            let value = u32::from_le_bytes(buf);
+            if value > size {
+                return Err(Error::new(ErrorKind::InvalidData, "Suffix out of range"));
+            }
+            suffixes.push(value);
+        }
+        let starts = Self::path_starts(&text);
+        if starts.len() as u64 != header.path_count {
+            return Err(Error::new(ErrorKind::InvalidData, "Suffix path count mismatch"));
+        }
+        Ok(Self {
+            names_id: header.names_id,
+            text,
+            suffixes,
+            starts,
+        })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let header = SuffixHeader {
+            text_size: self.text.len() as _,
+            path_count: self.starts.len() as _,
+            names_id: self.names_id,
+        };
+        write.write_all(header.to_array().get_ref())?;
+        write.write_all(&self.text)?;
+        for v in &self.suffixes {
+            write.write_all(&v.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl MeasureMemory for SuffixChunk {
+    fn memory_usage(&self) -> usize {
+        self.text.capacity() + (self.suffixes.capacity() + self.starts.capacity()) * size_of::<u32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::BungeeStr;
+
+    #[test]
+    fn test_find() {
+        let mut bungee = BungeeStr::new();
+        let dir = bungee.push(None, "art");
+        let a = bungee.push(dir, "final.psd").unwrap();
+        let b = bungee.push(dir, "draft.psd").unwrap();
+        let c = bungee.push(None, "final_final.txt").unwrap();
+        let names = NamesChunk::new(bungee, vec![a, b, c]);
+        let chunk = SuffixChunk::new(&names).unwrap();
+        assert_eq!(chunk.len(), 3);
+        assert_eq!(chunk.path(1), "art/draft.psd");
+        assert_eq!(chunk.find("final"), [0, 2]);
+        assert_eq!(chunk.find(".psd"), [0, 1]);
+        assert!(chunk.find("psd\0").is_empty());
+        assert!(chunk.find("missing").is_empty());
+        assert_eq!(chunk.find(""), [0, 1, 2]);
+        assert!(SuffixChunk::default().find("").is_empty());
+
+        //paths of other names, or the same names in other order, are stale
+        assert!(chunk.is_built_for(&names));
+        assert!(!chunk.is_built_for(&NamesChunk::new(names.bungee().clone(), vec![a, c, b])));
+        assert!(!chunk.is_built_for(&NamesChunk::new(BungeeStr::new(), Vec::new())));
+
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let mut read = bytes.as_slice();
+        let mut header = StdHashArray::zero();
+        read.read_exact(header.get_mut()).unwrap();
+        let restored = SuffixChunk::read_body(SuffixHeader::from_array(header).unwrap(), &mut read).unwrap();
+        assert_eq!(restored, chunk);
+        assert!(restored.is_built_for(&names));
+    }
+}
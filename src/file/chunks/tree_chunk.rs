@@ -0,0 +1,171 @@
+use crate::file::chunks::BlockType;
+use crate::file::StdHashArray;
+use crate::utils::MeasureMemory;
+use crate::{DataEntry, HashArray, HashEntry, PathIdentity};
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::mem::size_of;
+
+/// Aggregate hash of every directory, computed over sorted names and hashes of its children. Entries are sorted by
+/// directory name hash, id of the scanned root is stored separately, its aggregate is fingerprint of whole tree.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TreeChunk {
+    pub root: HashArray<32>,
+    pub dirs: Vec<DataEntry>,
+    /// Policy used to normalize names of directories before hashing them
+    pub identity: PathIdentity,
+    /// Id of prefix prepended to directory paths before hashing them, see [`PathIdentity::prefix_id`]
+    pub prefix_id: Option<u64>,
+}
+
+impl Default for TreeChunk {
+    fn default() -> Self {
+        Self::new(HashArray::zero(), Vec::new())
+    }
+}
+
+pub struct TreeHeader {
+    count: u64,
+    root: HashArray<32>,
+    identity: PathIdentity,
+    prefix_id: Option<u64>,
+}
+
+impl TreeHeader {
+    const FLAG_PREFIXED: u32 = 1;
+
+    pub fn to_array(&self) -> StdHashArray {
+        let mut array = HashArray::zero();
+        array.set_slice(0, BlockType::Tree.magic());
+        let flags = if self.prefix_id.is_some() { Self::FLAG_PREFIXED } else { 0 };
+        array.set_u32(4, flags);
+        array.set_u64(8, self.count);
+        array.set_slice(16, *self.root.get_ref());
+        array.set_slice(48, [self.identity.to_bits()]);
+        //bytes 49..56 are zeroed
+        array.set_u64(56, self.prefix_id.unwrap_or(0));
+        array
+    }
+
+    pub fn from_array(array: StdHashArray) -> io::Result<Self> {
+        BlockType::Tree.require_magic(array.get_slice(0))?;
+        let flags = array.get_u32(4);
+        if flags & !Self::FLAG_PREFIXED != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "Unknown tree header flags"));
+        }
+        let count = array.get_u64(8);
+        let root = HashArray::new(array.get_slice(16));
+        let [identity] = array.get_slice(48);
+        let identity =
+            PathIdentity::from_bits(identity).ok_or_else(|| Error::new(ErrorKind::Unsupported, "Unknown path identity policy"))?;
+        let prefix_id = (flags & Self::FLAG_PREFIXED != 0).then(|| array.get_u64(56));
+        Ok(Self {
+            count,
+            root,
+            identity,
+            prefix_id,
+        })
+    }
+}
+
+impl TreeChunk {
+    pub fn new(root: HashArray<32>, mut dirs: Vec<DataEntry>) -> Self {
+        dirs.sort_unstable();
+        Self {
+            root,
+            dirs,
+            identity: PathIdentity::EXACT,
+            prefix_id: None,
+        }
+    }
+
+    pub fn with_identity(mut self, identity: PathIdentity, prefix: &str) -> Self {
+        self.identity = identity;
+        self.prefix_id = identity.prefix_id(prefix);
+        self
+    }
+
+    /// Whether directory ids of both trees were derived the same way, so they can be compared.
+    pub fn is_comparable(&self, other: &Self) -> bool {
+        self.identity == other.identity && self.prefix_id == other.prefix_id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty()
+    }
+
+    pub fn get(&self, id: &HashArray<32>) -> Option<&HashArray<32>> {
+        let index = self.dirs.binary_search_by_key(id, |e| e.id).ok()?;
+        Some(&self.dirs[index].data)
+    }
+
+    /// Aggregate hash of scanned root directory, the same for any two identical trees.
+    pub fn root_hash(&self) -> Option<&HashArray<32>> {
+        self.get(&self.root)
+    }
+
+    pub fn read_body<R: Read + ?Sized>(header: TreeHeader, read: &mut R) -> io::Result<Self> {
+        let count = usize::try_from(header.count)
+            .ok()
+            .filter(|v| v.checked_mul(size_of::<DataEntry>()).is_some())
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Too many directory entries"))?;
+        let mut dirs = Vec::with_capacity(count.min(1024 * 1024));
+        for _ in 0..count {
+            let mut entry = HashEntry::zero();
+            read.read_exact(entry.as_mut_buf())?;
+            dirs.push(entry);
+        }
+        if !dirs.windows(2).all(|w| w[0].id < w[1].id) {
+            return Err(Error::new(ErrorKind::InvalidData, "Directory entries are not sorted by name"));
+        }
+        Ok(Self {
+            root: header.root,
+            dirs,
+            identity: header.identity,
+            prefix_id: header.prefix_id,
+        })
+    }
+
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let header = TreeHeader {
+            count: self.dirs.len() as _,
+            root: self.root,
+            identity: self.identity,
+            prefix_id: self.prefix_id,
+        };
+        write.write_all(header.to_array().get_ref())?;
+        self.dirs.iter().try_for_each(|e| write.write_all(e.as_buf()))
+    }
+}
+
+impl MeasureMemory for TreeChunk {
+    fn memory_usage(&self) -> usize {
+        self.dirs.capacity() * size_of::<DataEntry>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnicodeForm;
+
+    #[test]
+    fn test_write_read() {
+        let entry = |id, data| HashEntry {
+            id: HashArray::parse_fill_zero(id),
+            data: HashArray::parse_fill_zero(data),
+        };
+        let chunk = TreeChunk::new(HashArray::parse_fill_zero("02"), vec![entry("02", "aa"), entry("01", "bb")])
+            .with_identity(PathIdentity::new(UnicodeForm::Nfc, true), "Backup");
+        assert_eq!(chunk.root_hash(), Some(&HashArray::parse_fill_zero("aa")));
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let mut read = bytes.as_slice();
+        let mut header = StdHashArray::zero();
+        read.read_exact(header.get_mut()).unwrap();
+        let restored = TreeChunk::read_body(TreeHeader::from_array(header).unwrap(), &mut read).unwrap();
+        assert_eq!(restored, chunk);
+        assert!(restored.is_comparable(&chunk.clone().with_identity(chunk.identity, "backup")));
+        assert!(!restored.is_comparable(&chunk.clone().with_identity(chunk.identity, "")));
+    }
+}
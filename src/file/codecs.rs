@@ -1,6 +1,9 @@
-use crate::file::chunks::{AnyBlock, BlockType, HashesChunk, HashesHeader, NamesChunk, NamesHeader};
+use crate::file::chunks::{
+    AnyBlock, BlockType, FilterChunk, FilterHeader, HashesChunk, HashesHeader, LinksChunk, LinksHeader, NamesChunk, NamesHeader,
+    SizesChunk, SizesHeader, SuffixChunk, SuffixHeader, TreeChunk, TreeHeader,
+};
 use crate::file::codec_utils::read_first_data_chunk;
-use crate::file::{BlockError, MainHeader, StdHashArray, VersionCodec};
+use crate::file::{BlockError, EncryptionHeader, MainHeader, StdHashArray, VersionCodec};
 use crate::HashArray;
 use std::io;
 use std::io::{BufReader, ErrorKind, Read};
@@ -15,10 +18,16 @@ impl Codec0_0_1 {
 
 impl VersionCodec for Codec0_0_1 {
     fn decode_header_fields(&self, array: HashArray<57>, header: &mut MainHeader) -> io::Result<()> {
-        Ok(())
+        let [flags] = array.get_slice::<1>(0);
+        header.set_flags(flags)
     }
 
     fn decode_additional_header(&self, read: &mut dyn Read, header: &mut MainHeader) -> io::Result<()> {
+        if header.flags() & MainHeader::FLAG_ENCRYPTED != 0 {
+            let mut array = StdHashArray::zero();
+            read.read_exact(array.as_bytes_mut())?;
+            header.set_encryption(EncryptionHeader::from_array(array)?);
+        }
         Ok(())
     }
 
@@ -36,6 +45,31 @@ impl VersionCodec for Codec0_0_1 {
                 let chunk = NamesChunk::read_body(header, read)?;
                 Ok(AnyBlock::Names(chunk))
             }
+            BlockType::Links => {
+                let header = LinksHeader::from_array(first_block)?;
+                let chunk = LinksChunk::read_body(header, read)?;
+                Ok(AnyBlock::Links(chunk))
+            }
+            BlockType::Sizes => {
+                let header = SizesHeader::from_array(first_block)?;
+                let chunk = SizesChunk::read_body(header, read)?;
+                Ok(AnyBlock::Sizes(chunk))
+            }
+            BlockType::Tree => {
+                let header = TreeHeader::from_array(first_block)?;
+                let chunk = TreeChunk::read_body(header, read)?;
+                Ok(AnyBlock::Tree(chunk))
+            }
+            BlockType::Filter => {
+                let header = FilterHeader::from_array(first_block)?;
+                let chunk = FilterChunk::read_body(header, read)?;
+                Ok(AnyBlock::Filter(chunk))
+            }
+            BlockType::Suffixes => {
+                let header = SuffixHeader::from_array(first_block)?;
+                let chunk = SuffixChunk::read_body(header, read)?;
+                Ok(AnyBlock::Suffixes(chunk))
+            }
 
             _ => Err(BlockError::UnknownBlockType),
         }
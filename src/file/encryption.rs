@@ -0,0 +1,452 @@
+use crate::file::StdHashArray;
+use crate::HashArray;
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use std::fs;
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::Path;
+
+pub const ENCRYPTION_HEADER_MAGIC: [u8; 4] = *b"HsEn";
+
+/// Secret that fingerprint files are encrypted with.
+#[derive(Clone, Eq, PartialEq)]
+pub enum EncryptionKey {
+    /// Text remembered by user, stretched with Argon2id
+    Passphrase(Vec<u8>),
+    /// Contents of random key file
+    KeyFile(Vec<u8>),
+}
+
+impl EncryptionKey {
+    const KEY_FILE_CONTEXT: &'static str = "hashsummer fingerprint key file v1";
+    const MIN_KEY_FILE: usize = 16;
+
+    pub fn passphrase(text: &str) -> Self {
+        Self::Passphrase(text.as_bytes().to_vec())
+    }
+
+    /// Key file has to have at least 16 random bytes.
+    pub fn from_key_file(path: &Path) -> io::Result<Self> {
+        Self::from_key_bytes(fs::read(path)?)
+    }
+
+    pub fn from_key_bytes(bytes: Vec<u8>) -> io::Result<Self> {
+        if bytes.len() < Self::MIN_KEY_FILE {
+            return Err(Error::new(ErrorKind::InvalidInput, "Key file is too short"));
+        }
+        Ok(Self::KeyFile(bytes))
+    }
+
+    fn derive(&self, header: &EncryptionHeader) -> io::Result<Key> {
+        let mut key = Key::default();
+        match (self, header.kdf) {
+            (Self::Passphrase(text), KeyDerivation::Argon2id) => {
+                let params = Params::new(header.m_cost, header.t_cost, header.p_cost, Some(key.len()))
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid key derivation parameters: {e}")))?;
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(text, &header.salt, &mut key)
+                    .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Key derivation failed: {e}")))?;
+            }
+            (Self::KeyFile(bytes), KeyDerivation::KeyFile) => {
+                let mut material = header.salt.to_vec();
+                material.extend_from_slice(bytes);
+                key.copy_from_slice(&blake3::derive_key(Self::KEY_FILE_CONTEXT, &material));
+            }
+            (Self::Passphrase(_), KeyDerivation::KeyFile) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "File is encrypted with key file, not passphrase",
+                ));
+            }
+            (Self::KeyFile(_), KeyDerivation::Argon2id) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "File is encrypted with passphrase, not key file",
+                ));
+            }
+        }
+        Ok(key)
+    }
+}
+
+impl std::fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //secret is never printed
+        match self {
+            Self::Passphrase(_) => f.write_str("Passphrase(..)"),
+            Self::KeyFile(_) => f.write_str("KeyFile(..)"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[repr(u8)]
+pub enum KeyDerivation {
+    Argon2id = 1,
+    KeyFile = 2,
+}
+
+/// Parameters of encryption, written right after main header of encrypted file. Everything after it is stream of
+/// ChaCha20-Poly1305 segments, each authenticated together with both headers.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct EncryptionHeader {
+    kdf: KeyDerivation,
+    /// Plaintext bytes of every segment but the last one, which is always shorter
+    segment_size: u32,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    salt: [u8; 16],
+    /// Random part of nonce, the rest is segment counter and last segment flag
+    nonce: [u8; 7],
+}
+
+impl EncryptionHeader {
+    const SEGMENT_SIZE: u32 = 64 * 1024;
+    const MAX_SEGMENT_SIZE: u32 = 16 * 1024 * 1024;
+    /// Limits of Argon2id cost read from untrusted files, memory cost is in KiB. They are a few times the default cost,
+    /// so that opening a forged file takes at most a fraction of second and 64 MiB of memory.
+    const MAX_M_COST: u32 = 64 * 1024;
+    const MAX_T_COST: u32 = 4;
+    const MAX_P_COST: u32 = 4;
+
+    /// Header with fresh random salt and nonce, passphrases use recommended Argon2id cost.
+    pub fn new(key: &EncryptionKey) -> Self {
+        let kdf = match key {
+            EncryptionKey::Passphrase(_) => KeyDerivation::Argon2id,
+            EncryptionKey::KeyFile(_) => KeyDerivation::KeyFile,
+        };
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 7];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        Self {
+            kdf,
+            segment_size: Self::SEGMENT_SIZE,
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+            salt,
+            nonce,
+        }
+    }
+
+    /// Argon2id memory cost in KiB and number of passes, limited to 64 MiB and 4 passes, so that the file can be read
+    /// back.
+    pub fn with_cost(mut self, m_cost: u32, t_cost: u32) -> Self {
+        self.m_cost = m_cost.min(Self::MAX_M_COST);
+        self.t_cost = t_cost.min(Self::MAX_T_COST);
+        self
+    }
+
+    pub fn with_segment_size(mut self, segment_size: u32) -> Self {
+        self.segment_size = segment_size.clamp(1, Self::MAX_SEGMENT_SIZE);
+        self
+    }
+
+    pub fn kdf(&self) -> KeyDerivation {
+        self.kdf
+    }
+
+    pub fn to_array(&self) -> StdHashArray {
+        let mut array = HashArray::zero();
+        array.set_slice(0, ENCRYPTION_HEADER_MAGIC);
+        array.set_slice(4, [self.kdf as u8]);
+        //bytes 5..8 are zeroed
+        array.set_u32(8, self.segment_size);
+        array.set_u32(12, self.m_cost);
+        array.set_u32(16, self.t_cost);
+        array.set_u32(20, self.p_cost);
+        array.set_slice(24, self.salt);
+        array.set_slice(40, self.nonce);
+        //bytes 47..64 are zeroed
+        array
+    }
+
+    pub fn from_array(array: StdHashArray) -> io::Result<Self> {
+        if array.get_slice::<4>(0) != ENCRYPTION_HEADER_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid encryption header magic bytes"));
+        }
+        let kdf = match array.get_slice::<1>(4) {
+            [1] => KeyDerivation::Argon2id,
+            [2] => KeyDerivation::KeyFile,
+            _ => return Err(Error::new(ErrorKind::Unsupported, "Unknown key derivation")),
+        };
+        let segment_size = array.get_u32(8);
+        if segment_size == 0 || segment_size > Self::MAX_SEGMENT_SIZE {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid encryption segment size"));
+        }
+        let (m_cost, t_cost, p_cost) = (array.get_u32(12), array.get_u32(16), array.get_u32(20));
+        if m_cost > Self::MAX_M_COST || t_cost > Self::MAX_T_COST || p_cost > Self::MAX_P_COST {
+            return Err(Error::new(ErrorKind::InvalidData, "Key derivation cost is too high"));
+        }
+        Ok(Self {
+            kdf,
+            segment_size,
+            m_cost,
+            t_cost,
+            p_cost,
+            salt: array.get_slice(24),
+            nonce: array.get_slice(40),
+        })
+    }
+}
+
+/// Cipher with nonce and associated data shared by writer and reader.
+struct SegmentCipher {
+    cipher: ChaCha20Poly1305,
+    nonce: [u8; 7],
+    /// Headers that every segment is authenticated with
+    aad: Vec<u8>,
+    segment_size: usize,
+    counter: u32,
+}
+
+impl SegmentCipher {
+    const TAG_SIZE: usize = 16;
+
+    fn new(key: &EncryptionKey, header: &EncryptionHeader, aad: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            cipher: ChaCha20Poly1305::new(&key.derive(header)?),
+            nonce: header.nonce,
+            aad: aad.to_vec(),
+            segment_size: header.segment_size as usize,
+            counter: 0,
+        })
+    }
+
+    /// Nonce of next segment, last segment has different nonce, so that stream can't be truncated at segment border.
+    fn next_nonce(&mut self, last: bool) -> io::Result<Nonce> {
+        let mut nonce = Nonce::default();
+        nonce[..7].copy_from_slice(&self.nonce);
+        nonce[7..11].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[11] = last as u8;
+        self.counter = self
+            .counter
+            .checked_add(1)
+            .ok_or_else(|| Error::new(ErrorKind::Unsupported, "Too many encrypted segments"))?;
+        Ok(nonce)
+    }
+
+    fn seal(&mut self, plain: &[u8], last: bool) -> io::Result<Vec<u8>> {
+        let nonce = self.next_nonce(last)?;
+        let payload = Payload {
+            msg: plain,
+            aad: &self.aad,
+        };
+        self.cipher.encrypt(&nonce, payload).map_err(|_| Error::other("Encryption failed"))
+    }
+
+    fn open(&mut self, sealed: &[u8], last: bool) -> io::Result<Vec<u8>> {
+        let nonce = self.next_nonce(last)?;
+        let payload = Payload {
+            msg: sealed,
+            aad: &self.aad,
+        };
+        self.cipher
+            .decrypt(&nonce, payload)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Decryption failed, wrong key or damaged file"))
+    }
+}
+
+/// Encrypts everything written to it, [`Self::finish`] has to be called to write the last segment, otherwise the
+/// stream is rejected as truncated.
+pub struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: SegmentCipher,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// `aad` are headers written before encrypted stream.
+    pub fn new(inner: W, key: &EncryptionKey, header: &EncryptionHeader, aad: &[u8]) -> io::Result<Self> {
+        let cipher = SegmentCipher::new(key, header, aad)?;
+        Ok(Self {
+            inner,
+            buffer: Vec::with_capacity(cipher.segment_size),
+            cipher,
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let sealed = self.cipher.seal(&self.buffer, true)?;
+        self.inner.write_all(&sealed)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let count = buf.len().min(self.cipher.segment_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..count]);
+        //full segment is never the last one
+        if self.buffer.len() == self.cipher.segment_size {
+            let sealed = self.cipher.seal(&self.buffer, false)?;
+            self.inner.write_all(&sealed)?;
+            self.buffer.clear();
+        }
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts stream written by [`EncryptingWriter`], fails on wrong key, modified or truncated data.
+pub struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: SegmentCipher,
+    plain: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    pub fn new(inner: R, key: &EncryptionKey, header: &EncryptionHeader, aad: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            inner,
+            cipher: SegmentCipher::new(key, header, aad)?,
+            plain: Vec::new(),
+            pos: 0,
+            done: false,
+        })
+    }
+
+    fn next_segment(&mut self) -> io::Result<()> {
+        let full = self.cipher.segment_size + SegmentCipher::TAG_SIZE;
+        let mut sealed = Vec::with_capacity(full);
+        (&mut self.inner).take(full as u64).read_to_end(&mut sealed)?;
+        let last = sealed.len() < full;
+        self.plain = self.cipher.open(&sealed, last)?;
+        self.pos = 0;
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plain.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_segment()?;
+        }
+        let count = buf.len().min(self.plain.len() - self.pos);
+        buf[..count].copy_from_slice(&self.plain[self.pos..self.pos + count]);
+        self.pos += count;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::Snapshot;
+    use crate::store::tests::snapshot_of;
+    use crate::PathIdentity;
+
+    fn round_trip(key: &EncryptionKey, header: &EncryptionHeader, data: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptingWriter::new(Vec::new(), key, header, b"aad").unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(key: &EncryptionKey, header: &EncryptionHeader, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let mut plain = Vec::new();
+        DecryptingReader::new(sealed, key, header, b"aad")?.read_to_end(&mut plain)?;
+        Ok(plain)
+    }
+
+    #[test]
+    fn test_encrypt_segments() {
+        let key = EncryptionKey::from_key_bytes(vec![7; 32]).unwrap();
+        let header = EncryptionHeader::new(&key).with_segment_size(10);
+        assert_eq!(EncryptionHeader::from_array(header.to_array()).unwrap(), header);
+        for size in [0, 9, 10, 25, 30] {
+            let data = (0..size as u8).collect::<Vec<_>>();
+            let sealed = round_trip(&key, &header, &data);
+            assert_eq!(sealed.len(), size + (size / 10 + 1) * 16);
+            assert_eq!(decrypt(&key, &header, &sealed).unwrap(), data);
+            //dropped last segment
+            if size >= 10 {
+                assert!(decrypt(&key, &header, &sealed[..26]).is_err());
+            }
+        }
+
+        let sealed = round_trip(&key, &header, b"customer folder");
+        let mut modified = sealed.clone();
+        modified[3] ^= 1;
+        assert!(decrypt(&key, &header, &modified).is_err());
+        let other = EncryptionKey::from_key_bytes(vec![8; 32]).unwrap();
+        assert!(decrypt(&other, &header, &sealed).is_err());
+        let mut plain = Vec::new();
+        let mut reader = DecryptingReader::new(sealed.as_slice(), &key, &header, b"other aad").unwrap();
+        assert!(reader.read_to_end(&mut plain).is_err());
+    }
+
+    #[test]
+    fn test_passphrase() {
+        let key = EncryptionKey::passphrase("correct horse battery staple");
+        let header = EncryptionHeader::new(&key).with_cost(64, 1);
+        assert_eq!(header.kdf(), KeyDerivation::Argon2id);
+        let sealed = round_trip(&key, &header, b"names");
+        assert_eq!(decrypt(&key, &header, &sealed).unwrap(), b"names");
+        assert!(decrypt(&EncryptionKey::passphrase("wrong"), &header, &sealed).is_err());
+        let key_file = EncryptionKey::from_key_bytes(vec![1; 16]).unwrap();
+        assert_eq!(decrypt(&key_file, &header, &sealed).unwrap_err().kind(), ErrorKind::InvalidInput);
+        assert!(EncryptionKey::from_key_bytes(vec![1; 15]).is_err());
+
+        assert_eq!(EncryptionHeader::from_array(header.to_array()).unwrap(), header);
+        let max = EncryptionHeader::new(&EncryptionKey::passphrase("")).with_cost(u32::MAX, u32::MAX);
+        assert_eq!(EncryptionHeader::from_array(max.to_array()).unwrap(), max);
+        for (at, cost) in [(12, 64 * 1024 + 1), (16, 5), (20, 5)] {
+            let mut array = header.to_array();
+            array.set_u32(at, cost);
+            assert_eq!(EncryptionHeader::from_array(array).unwrap_err().kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_encrypted() {
+        let files: [(&str, &[u8]); 2] = [("customer/contract.txt", b"contract"), ("notes.txt", b"notes")];
+        let plain = snapshot_of(PathIdentity::EXACT, &files).with_filters(0.01);
+        let key = EncryptionKey::from_key_bytes(b"0123456789abcdef0123456789abcdef".to_vec()).unwrap();
+        let mut bytes = Vec::new();
+        plain.write_encrypted(&mut bytes, &key).unwrap();
+        assert!(!bytes.windows(8).any(|w| w == b"customer"));
+
+        let restored = Snapshot::read_encrypted(&mut bytes.as_slice(), &key).unwrap();
+        assert_eq!(restored.hashes.data, plain.hashes.data);
+        assert!(restored.names == plain.names);
+        assert_eq!(restored.filters.len(), 2);
+        let err = Snapshot::read(&mut bytes.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert!(Snapshot::read_filters(&mut bytes.as_slice()).is_err());
+        let other = EncryptionKey::from_key_bytes(vec![0; 32]).unwrap();
+        assert!(Snapshot::read_encrypted(&mut bytes.as_slice(), &other).is_err());
+        //flipped flag is detected, as headers are authenticated
+        let mut modified = bytes.clone();
+        modified[64 + 9] ^= 1;
+        assert!(Snapshot::read_encrypted(&mut modified.as_slice(), &key).is_err());
+
+        let passphrase = EncryptionKey::passphrase("correct horse battery staple");
+        let mut bytes = Vec::new();
+        let encryption = EncryptionHeader::new(&passphrase).with_cost(64, 1);
+        plain.write_encrypted_with(&mut bytes, &passphrase, encryption).unwrap();
+        let restored = Snapshot::read_encrypted(&mut bytes.as_slice(), &passphrase).unwrap();
+        assert_eq!(restored.hashes.data, plain.hashes.data);
+        assert!(Snapshot::read_encrypted(&mut bytes.as_slice(), &key).is_err());
+
+        //plain snapshots are not accepted in place of encrypted ones
+        let mut bytes = Vec::new();
+        plain.write(&mut bytes).unwrap();
+        let err = Snapshot::read_encrypted(&mut bytes.as_slice(), &key).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -1,8 +1,12 @@
 pub mod chunks;
 mod codec_utils;
 mod codecs;
+mod encryption;
+mod snapshot;
 mod sum_file;
 
+pub use encryption::*;
+pub use snapshot::*;
 pub use sum_file::*;
 
 use std::io::{BufReader, Read, Seek, Write};
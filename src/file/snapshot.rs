@@ -0,0 +1,332 @@
+use crate::file::chunks::{
+    AnyBlock, BlockType, FilterChunk, FilterKind, HashesChunk, LinksChunk, NamesChunk, SharedNamesChunk, SizesChunk, SuffixChunk, TreeChunk,
+};
+use crate::file::codec_utils::read_first_data_chunk;
+use crate::file::{DecryptingReader, EncryptingWriter, EncryptionHeader, EncryptionKey, MainHeader};
+use crate::utils::{BungeeLookup, MeasureMemory};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// All chunks describing single scan of a directory tree.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub hashes: HashesChunk,
+    /// Names of files, empty when keyed snapshot is read, see [`Snapshot::write`]
+    pub names: NamesChunk,
+    pub links: LinksChunk,
+    pub sizes: SizesChunk,
+    pub tree: TreeChunk,
+    /// Optional filters, written before all other blocks, so they can be loaded alone, see [`Snapshot::read_filters`]
+    pub filters: Vec<FilterChunk>,
+    /// Optional suffix array over paths, see [`crate::store::NameSearch`]
+    pub suffixes: SuffixChunk,
+}
+
+impl Snapshot {
+    /// Write all chunks, names, tree and suffixes are left out when hashes are keyed.
+    pub fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        self.write_with(write, None)
+    }
+
+    /// Write with names table sharing repeated directories of at least `min_entries` entries, see
+    /// [`SharedNamesChunk`]. Names are expanded back to plain table when read.
+    pub fn write_shared<W: Write>(&self, write: &mut W, min_entries: usize) -> io::Result<()> {
+        self.write_with(write, Some(min_entries))
+    }
+
+    /// Write with all blocks encrypted, so names can't be read without the key, see [`EncryptingWriter`].
+    pub fn write_encrypted<W: Write>(&self, write: &mut W, key: &EncryptionKey) -> io::Result<()> {
+        self.write_encrypted_with(write, key, EncryptionHeader::new(key))
+    }
+
+    /// Write encrypted with given parameters, e.g. with lower key derivation cost.
+    pub fn write_encrypted_with<W: Write>(&self, write: &mut W, key: &EncryptionKey, encryption: EncryptionHeader) -> io::Result<()> {
+        let header = MainHeader::new().with_encryption(encryption);
+        header.write(write)?;
+        let mut encrypted = EncryptingWriter::new(write, key, &encryption, &header.header_bytes())?;
+        self.write_blocks(&mut encrypted, None)?;
+        encrypted.finish()?;
+        Ok(())
+    }
+
+    fn write_with<W: Write>(&self, write: &mut W, shared: Option<usize>) -> io::Result<()> {
+        MainHeader::new().write(write)?;
+        self.write_blocks(write, shared)
+    }
+
+    fn write_blocks<W: Write>(&self, write: &mut W, shared: Option<usize>) -> io::Result<()> {
+        for filter in &self.filters {
+            filter.write(write)?;
+        }
+        self.hashes.write(write)?;
+        //keyed fingerprints must not reveal names, so nothing derived from plain names is written
+        let keyed = self.hashes.key_id.is_some();
+        match shared {
+            _ if keyed => {}
+            Some(min_entries) => SharedNamesChunk::new(&self.names, min_entries)?.write(write)?,
+            None => self.names.write(write)?,
+        }
+        if !self.links.groups.is_empty() {
+            self.links.write(write)?;
+        }
+        if !self.sizes.is_empty() {
+            self.sizes.write(write)?;
+        }
+        if !self.tree.is_empty() && !keyed {
+            self.tree.write(write)?;
+        }
+        if !self.suffixes.is_empty() && !keyed {
+            self.suffixes.write(write)?;
+        }
+        Ok(())
+    }
+
+    /// Read plain snapshot, encrypted ones have to be read with [`Self::read_encrypted`].
+    pub fn read<R: Read>(read: &mut R) -> io::Result<Self> {
+        let (header, _) = MainHeader::read(read)?;
+        Self::check_plain(&header)?;
+        Self::read_blocks(&header, read)
+    }
+
+    /// Read snapshot encrypted with given key, plain snapshots are rejected, so that encrypted file can't be swapped
+    /// for plain one without notice.
+    pub fn read_encrypted<R: Read>(read: &mut R, key: &EncryptionKey) -> io::Result<Self> {
+        let (header, _) = MainHeader::read(read)?;
+        let encryption = header
+            .encryption()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Snapshot is not encrypted"))?;
+        let mut decrypted = DecryptingReader::new(read, key, encryption, &header.header_bytes())?;
+        Self::read_blocks(&header, &mut decrypted)
+    }
+
+    fn check_plain(header: &MainHeader) -> io::Result<()> {
+        match header.encryption() {
+            Some(_) => Err(Error::new(ErrorKind::InvalidInput, "Snapshot is encrypted, key is required")),
+            None => Ok(()),
+        }
+    }
+
+    fn read_blocks<R: Read>(header: &MainHeader, read: &mut R) -> io::Result<Self> {
+        let mut hashes = None;
+        let mut names = None;
+        let mut links = None;
+        let mut sizes = None;
+        let mut tree = None;
+        let mut filters = Vec::new();
+        let mut suffixes = None;
+        while let Some(first) = read_first_data_chunk(read)? {
+            match header.decode_block(first, read)? {
+                AnyBlock::Hashes(c) => hashes = Some(c),
+                AnyBlock::Names(c) => names = Some(c),
+                AnyBlock::Links(c) => links = Some(c),
+                AnyBlock::Sizes(c) => sizes = Some(c),
+                AnyBlock::Tree(c) => tree = Some(c),
+                AnyBlock::Filter(c) => filters.push(c),
+                AnyBlock::Suffixes(c) => suffixes = Some(c),
+                _ => {} //other blocks are not part of snapshot
+            }
+        }
+        let hashes = hashes.ok_or_else(|| Error::new(ErrorKind::InvalidData, "Snapshot has no hashes block"))?;
+        let names = match names {
+            Some(names) => names,
+            None if hashes.key_id.is_some() => NamesChunk::default(),
+            None => return Err(Error::new(ErrorKind::InvalidData, "Snapshot has no names block")),
+        };
+        Ok(Self {
+            hashes,
+            names,
+            links: links.unwrap_or_default(),
+            sizes: sizes.unwrap_or_default(),
+            tree: tree.unwrap_or_default(),
+            filters,
+            suffixes: suffixes.unwrap_or_default(),
+        })
+    }
+
+    /// Read only filters at the start of snapshot, reading stops at the first block of other type.
+    pub fn read_filters<R: Read>(read: &mut R) -> io::Result<Vec<FilterChunk>> {
+        let (header, _) = MainHeader::read(read)?;
+        Self::check_plain(&header)?;
+        let mut filters = Vec::new();
+        while let Some(first) = read_first_data_chunk(read)? {
+            if BlockType::decode_magic(first.get_slice(0))? != Some(BlockType::Filter) {
+                break;
+            }
+            if let AnyBlock::Filter(c) = header.decode_block(first, read)? {
+                filters.push(c);
+            }
+        }
+        Ok(filters)
+    }
+
+    pub fn load_filters(path: &Path) -> io::Result<Vec<FilterChunk>> {
+        Self::read_filters(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Add filters over content and name hashes, replacing existing ones.
+    pub fn with_filters(mut self, false_positive_rate: f64) -> Self {
+        self.filters = [FilterKind::Data, FilterKind::Names]
+            .map(|kind| FilterChunk::from_hashes(kind, &self.hashes, false_positive_rate))
+            .into();
+        self
+    }
+
+    /// Add suffix array over paths of names chunk, for faster substring search.
+    pub fn with_suffixes(mut self) -> io::Result<Self> {
+        self.suffixes = SuffixChunk::new(&self.names)?;
+        Ok(self)
+    }
+
+    /// Path of hashes entry at given position, when names chunk has entry links for current order of hashes.
+    pub fn entry_path(&self, entry: usize) -> Option<PathBuf> {
+        if !self.names.links_match(&self.hashes) {
+            return None;
+        }
+        Some(self.names.bungee().os_path_of(self.names.entry_index(entry)?))
+    }
+
+    pub fn filter(&self, kind: FilterKind) -> Option<&FilterChunk> {
+        self.filters.iter().find(|f| f.kind() == kind)
+    }
+
+    /// Index of names for resolving paths, names are compared the same way as name ids of snapshot were derived.
+    pub fn lookup(&self) -> BungeeLookup<'_> {
+        self.names.lookup().with_identity(self.hashes.identity)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn save_encrypted(&self, path: &Path, key: &EncryptionKey) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_encrypted(&mut file, key)?;
+        file.flush()
+    }
+
+    pub fn load_encrypted(path: &Path, key: &EncryptionKey) -> io::Result<Self> {
+        Self::read_encrypted(&mut BufReader::new(File::open(path)?), key)
+    }
+}
+
+impl MeasureMemory for Snapshot {
+    fn memory_usage(&self) -> usize {
+        self.hashes.memory_usage()
+            + self.names.memory_usage()
+            + self.links.memory_usage()
+            + self.sizes.memory_usage()
+            + self.tree.memory_usage()
+            + self.filters.iter().map(|f| f.memory_usage()).sum::<usize>()
+            + self.suffixes.memory_usage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::chunks::SizeEntry;
+    use crate::store::tests::snapshot_of;
+    use crate::utils::BungeeStr;
+    use crate::{HashArray, HashEntry, PathIdentity, UnicodeForm};
+
+    #[test]
+    #[cfg(unix)]
+    fn test_save_load_raw_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let mut bungee = BungeeStr::new();
+        let tree = bungee.push(None, "tree");
+        let latin = bungee.push_os(tree, OsStr::from_bytes(b"caf\xe9")).unwrap();
+        let utf = bungee.push_os(tree, OsStr::new("café")).unwrap();
+        let entries = vec![
+            HashEntry {
+                id: HashArray::parse_fill_zero("01"),
+                data: HashArray::parse_fill_zero("0a"),
+            },
+            HashEntry {
+                id: HashArray::parse_fill_zero("02"),
+                data: HashArray::parse_fill_zero("0b"),
+            },
+        ];
+        let snapshot = Snapshot {
+            hashes: HashesChunk::new_sha256(entries, true),
+            names: NamesChunk::new(bungee, vec![latin, utf]),
+            links: LinksChunk::new(vec![vec![HashArray::parse_fill_zero("01"), HashArray::parse_fill_zero("02")]]),
+            sizes: SizesChunk::new(vec![SizeEntry {
+                id: HashArray::parse_fill_zero("01"),
+                size: 3,
+            }]),
+            tree: Default::default(),
+            filters: Vec::new(),
+            suffixes: Default::default(),
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("snapshot.hsum");
+        snapshot.save(&file).unwrap();
+        let loaded = Snapshot::load(&file).unwrap();
+        assert!(loaded.hashes == snapshot.hashes);
+        assert!(loaded.names == snapshot.names);
+        assert_eq!(loaded.links, snapshot.links);
+        assert_eq!(loaded.sizes, snapshot.sizes);
+
+        let paths = loaded
+            .names
+            .indexes()
+            .iter()
+            .map(|&i| loaded.names.bungee().os_path_of(i))
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [Path::new(OsStr::from_bytes(b"tree/caf\xe9")), Path::new("tree/café")]);
+    }
+
+    #[test]
+    fn test_write_keyed() {
+        let mut snapshot = snapshot_of(PathIdentity::EXACT, &[("secret/plan.txt", b"plan"), ("notes.txt", b"notes")]);
+        snapshot.hashes.key_id = Some(42);
+        assert!(!snapshot.tree.is_empty());
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        assert!(!bytes.windows(4).any(|w| w == b"plan"));
+        let restored = Snapshot::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.hashes.key_id, Some(42));
+        assert_eq!(restored.hashes.data, snapshot.hashes.data);
+        assert_eq!(restored.sizes, snapshot.sizes);
+        assert!(restored.names.indexes().is_empty());
+        assert!(restored.tree.is_empty());
+
+        //names block is still required without key
+        snapshot.hashes.key_id = None;
+        let mut plain = Vec::new();
+        MainHeader::new().write(&mut plain).unwrap();
+        snapshot.hashes.write(&mut plain).unwrap();
+        let err = Snapshot::read(&mut plain.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_snapshot_lookup() {
+        let snapshot = snapshot_of(
+            PathIdentity::new(UnicodeForm::Nfc, true),
+            &[("A/B/c.txt", b"1"), ("A/B/d.txt", b"2"), ("e", b"3")],
+        );
+        let lookup = snapshot.lookup();
+        assert_eq!(lookup.lookup(Path::new("a/B/C.TXT")), Some(snapshot.names.indexes()[0]));
+        assert_eq!(lookup.lookup(Path::new("E")), Some(snapshot.names.indexes()[2]));
+        assert_eq!(snapshot.names.lookup().lookup(Path::new("A/B/c.txt")), None);
+        let names = lookup
+            .list(Path::new("A/b"))
+            .unwrap()
+            .map(|(n, _)| n.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["c.txt", "d.txt"]);
+    }
+}
@@ -1,5 +1,6 @@
 use super::codecs::*;
 use crate::file::chunks::{AnyBlock, BlockType, HashesChunk, InfoChunk, NamesChunk};
+use crate::file::EncryptionHeader;
 use crate::utils::with_counted_read;
 use crate::{HashArray, SumFileHeader};
 use std::fs::File;
@@ -41,7 +42,10 @@ pub struct SumFile<T: Read + Write + Seek> {
 
 pub struct MainHeader {
     codec: &'static dyn VersionCodec,
+    version: [u8; 3],
     flags: u8,
+    /// Present when all blocks after headers are encrypted, see [`crate::file::EncryptingWriter`]
+    encryption: Option<EncryptionHeader>,
 }
 
 impl Default for MainHeader {
@@ -51,11 +55,71 @@ impl Default for MainHeader {
 }
 
 impl MainHeader {
+    pub const FLAG_ENCRYPTED: u8 = 1;
+    const KNOWN_FLAGS: u8 = Self::FLAG_ENCRYPTED;
+
     pub fn new() -> Self {
+        let (version, codec) = get_latest_codec();
         Self {
             flags: 0,
-            codec: get_latest_codec().1,
+            version,
+            codec,
+            encryption: None,
+        }
+    }
+
+    /// Header of file whose blocks are encrypted, encryption header is written right after main header.
+    pub fn with_encryption(mut self, encryption: EncryptionHeader) -> Self {
+        self.flags |= Self::FLAG_ENCRYPTED;
+        self.encryption = Some(encryption);
+        self
+    }
+
+    pub fn encryption(&self) -> Option<&EncryptionHeader> {
+        self.encryption.as_ref()
+    }
+
+    pub(super) fn set_flags(&mut self, flags: u8) -> io::Result<()> {
+        if flags & !Self::KNOWN_FLAGS != 0 {
+            return Err(Error::new(ErrorKind::Unsupported, "Unknown fingerprint file flags"));
+        }
+        self.flags = flags;
+        Ok(())
+    }
+
+    pub(super) fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    pub(super) fn set_encryption(&mut self, encryption: EncryptionHeader) {
+        self.encryption = Some(encryption);
+    }
+
+    /// Bytes of main header followed by encryption header, if any, exactly as written to file.
+    pub fn header_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.to_array().as_bytes().to_vec();
+        if let Some(encryption) = &self.encryption {
+            bytes.extend_from_slice(encryption.to_array().as_bytes());
         }
+        bytes
+    }
+
+    pub fn to_array(&self) -> HashArray<64> {
+        let mut array = HashArray::zero();
+        array.set_slice(0, MAIN_HEADER_MAGIC);
+        array.set_slice(4, self.version);
+        array.set_slice(7, [self.flags]);
+        //rest of bytes are zeroed
+        array
+    }
+
+    pub fn write<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(&self.header_bytes())
+    }
+
+    /// Decode block with codec of this file version.
+    pub fn decode_block(&self, first_block: StdHashArray, read: &mut dyn Read) -> Result<AnyBlock, BlockError> {
+        self.codec.decode_block(first_block, read, self)
     }
     pub fn read<R: Read>(stream: &mut R) -> io::Result<(Self, u64)> {
         let mut main_header = HashArray::<64>::zero();
@@ -70,7 +134,12 @@ impl MainHeader {
             let m = format!("Unknown fingerprint file version v{maj}.{min}.{pat}, latest supported version is v{lma}.{lmi}.{lpa}");
             io::Error::new(io::ErrorKind::InvalidData, m)
         })?;
-        let mut header = Self { codec, flags: 0 };
+        let mut header = Self {
+            codec,
+            version,
+            flags: 0,
+            encryption: None,
+        };
         let rest = main_header.get_slice::<57>(7);
         codec.decode_header_fields(HashArray::new(rest), &mut header)?;
 
@@ -82,6 +151,11 @@ impl MainHeader {
 }
 
 impl SumFile<File> {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = File::options().read(true).write(true).truncate(true).create(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
     pub fn open(path: &Path) -> io::Result<Self> {
         let mut file = File::open(path)?;
         let (main_header, pos) = MainHeader::read(&mut file)?;
@@ -149,6 +223,19 @@ where
     }
 
     pub fn write_next_block(&mut self, block: &AnyBlock) -> io::Result<()> {
-        Ok(())
+        if !self.initialized {
+            self.main_header.write(&mut self.file)?;
+            self.initialized = true;
+        }
+        match block {
+            AnyBlock::Hashes(chunk) => chunk.write(&mut self.file),
+            AnyBlock::Names(chunk) => chunk.write(&mut self.file),
+            AnyBlock::Links(chunk) => chunk.write(&mut self.file),
+            AnyBlock::Sizes(chunk) => chunk.write(&mut self.file),
+            AnyBlock::Tree(chunk) => chunk.write(&mut self.file),
+            AnyBlock::Filter(chunk) => chunk.write(&mut self.file),
+            AnyBlock::Suffixes(chunk) => chunk.write(&mut self.file),
+            _ => Err(Error::new(ErrorKind::Unsupported, "Writing of this block type is not supported")),
+        }
     }
 }
@@ -1,4 +1,5 @@
-use crate::utils::{BungeeIndex, BungeeStr};
+use crate::utils::{escape_name, os_str_bytes, BungeeIndex, BungeeStr, NameTable};
+use crate::vfs::{DirIter, FileKind, FileSystem, RealFs, VfsEntry};
 use compress::bwt::*;
 use flate2::Compression;
 use rayon::vec::IntoIter;
@@ -6,14 +7,15 @@ use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::ffi::{OsStr, OsString};
 use std::fmt::{Display, Formatter};
-use std::fs::{read_dir, DirEntry, FileType, ReadDir};
 use std::io;
 use std::io::Write;
 use std::iter::once;
 use std::path::{Path, PathBuf};
 use std::ptr::null;
+use std::sync::Arc;
 
 pub struct DepthFileScanner {
+    fs: Arc<dyn FileSystem>,
     root: PathBuf,
     current: Vec<OsString>,
     stack: StackVariant,
@@ -30,9 +32,9 @@ enum SortType {
 }
 
 enum StackVariant {
-    Fresh(Vec<ReadDir>),
+    Fresh(Vec<DirIter>),
     Cached {
-        stack: Vec<Vec<io::Result<DirEntry>>>,
+        stack: Vec<Vec<io::Result<VfsEntry>>>,
         sort: SortType,
     },
 }
@@ -40,12 +42,18 @@ enum StackVariant {
 impl DepthFileScanner {
     //todo multi root
     pub fn from_dir<P: AsRef<Path>>(path: P, keep_dir_open: bool) -> Self {
+        Self::from_fs(Arc::new(RealFs), path, keep_dir_open)
+    }
+
+    /// Scan directory of given file system, eg. in memory tree or archive.
+    pub fn from_fs<P: AsRef<Path>>(fs: Arc<dyn FileSystem>, path: P, keep_dir_open: bool) -> Self {
         let root = path.as_ref().to_path_buf();
         let mut stack = StackVariant::new(keep_dir_open);
-        if let Ok(iter) = read_dir(path) {
+        if let Ok(iter) = fs.read_dir(&root) {
             stack.push(iter);
         }
         Self {
+            fs,
             root,
             stack,
             current: Vec::new(),
@@ -56,15 +64,19 @@ impl DepthFileScanner {
         self.root = path.as_ref().to_path_buf();
         self.current.clear();
         self.stack.clear(keep_dir_open);
-        if let Ok(iter) = read_dir(path) {
+        if let Ok(iter) = self.fs.read_dir(&self.root) {
             self.stack.push(iter);
         }
     }
 
-    pub fn iter(&mut self) -> impl Iterator<Item = (DirEntry, FileType)> + '_ {
+    pub fn file_system(&self) -> &Arc<dyn FileSystem> {
+        &self.fs
+    }
+
+    pub fn iter(&mut self) -> impl Iterator<Item = (VfsEntry, FileKind)> + '_ {
         struct Iter<'a>(&'a mut DepthFileScanner);
         impl Iterator for Iter<'_> {
-            type Item = (DirEntry, FileType);
+            type Item = (VfsEntry, FileKind);
 
             fn next(&mut self) -> Option<Self::Item> {
                 self.0.next_file().map(|f| (f.entry, f.file_type))
@@ -73,10 +85,11 @@ impl DepthFileScanner {
         Iter(self)
     }
 
-    pub fn save_to_bungee<F, S>(self, bungee_push: S, conv: F) -> SaveToBungee<F, S>
+    pub fn save_to_bungee<F, S, I>(self, bungee_push: S, conv: F) -> SaveToBungee<F, S, I>
     where
-        F: FnMut(&OsStr, FileType) -> Option<Cow<'_, str>>,
-        S: FnMut(Option<BungeeIndex>, &str) -> Option<BungeeIndex>,
+        F: FnMut(&OsStr, FileKind) -> Option<Cow<'_, OsStr>>,
+        S: FnMut(Option<I>, &OsStr) -> Option<I>,
+        I: Copy,
     {
         SaveToBungee {
             it: self,
@@ -85,11 +98,21 @@ impl DepthFileScanner {
             name_convert: conv,
         }
     }
+
+    /// Same as [`Self::save_to_bungee`] with names pushed to any names table, entries whose names can't be stored
+    /// have no index.
+    pub fn save_to_table<F, T>(self, table: &mut T, conv: F) -> SaveToBungee<F, impl PushName<T::Index> + '_, T::Index>
+    where
+        F: FnMut(&OsStr, FileKind) -> Option<Cow<'_, OsStr>>,
+        T: NameTable,
+    {
+        self.save_to_bungee(move |parent, name| table.try_push_os(parent, name).ok().flatten(), conv)
+    }
 }
 
 pub struct IterDepthFileScanner(DepthFileScanner);
 impl Iterator for IterDepthFileScanner {
-    type Item = (DirEntry, FileType);
+    type Item = (VfsEntry, FileKind);
 
     fn next(&mut self) -> Option<Self::Item> {
         self.0.next_file().map(|f| (f.entry, f.file_type))
@@ -97,7 +120,7 @@ impl Iterator for IterDepthFileScanner {
 }
 
 impl IntoIterator for DepthFileScanner {
-    type Item = (DirEntry, FileType);
+    type Item = (VfsEntry, FileKind);
     type IntoIter = IterDepthFileScanner;
     fn into_iter(self) -> IterDepthFileScanner {
         IterDepthFileScanner(self)
@@ -123,7 +146,7 @@ impl StackVariant {
             Self::Cached { stack, .. } => _ = stack.pop(),
         }
     }
-    pub fn push(&mut self, iter: ReadDir) {
+    pub fn push(&mut self, iter: DirIter) {
         match self {
             Self::Fresh(v) => v.push(iter),
             Self::Cached { stack, sort } => {
@@ -137,9 +160,9 @@ impl StackVariant {
             }
         }
     }
-    fn compare_entries(a: &io::Result<DirEntry>, b: &io::Result<DirEntry>) -> Ordering {
+    fn compare_entries(a: &io::Result<VfsEntry>, b: &io::Result<VfsEntry>) -> Ordering {
         match (a, b) {
-            (Ok(a), Ok(b)) => a.path().cmp(&b.path()),
+            (Ok(a), Ok(b)) => a.as_path().cmp(b.as_path()),
             (Ok(_), Err(_)) => Ordering::Less,    // all ok should be before any errors
             (Err(_), Ok(_)) => Ordering::Greater, // all ok should be before any errors
             (Err(a), Err(b)) => a.kind().cmp(&b.kind()).then_with(|| {
@@ -163,7 +186,7 @@ impl StackVariant {
             Self::Cached { stack, .. } => *self = Self::Fresh(Vec::new()),
         }
     }
-    pub fn last_iter(&mut self) -> Option<TempIter> {
+    pub fn last_iter(&mut self) -> Option<TempIter<'_>> {
         match self {
             Self::Fresh(v) => v.last_mut().map(TempIter::Fresh),
             Self::Cached { stack, .. } => stack.last_mut().map(TempIter::Cached),
@@ -172,11 +195,11 @@ impl StackVariant {
 }
 
 enum TempIter<'a> {
-    Fresh(&'a mut ReadDir),
-    Cached(&'a mut Vec<io::Result<DirEntry>>),
+    Fresh(&'a mut DirIter),
+    Cached(&'a mut Vec<io::Result<VfsEntry>>),
 }
 impl Iterator for TempIter<'_> {
-    type Item = io::Result<DirEntry>;
+    type Item = io::Result<VfsEntry>;
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
@@ -194,10 +217,10 @@ pub struct FileEntry<'a> {
     pub before_name: &'a [OsString],
     /// if this entry is directory, then this field is a name of that directory
     pub dir_name: Option<&'a OsStr>,
-    /// Os file type
-    pub file_type: FileType,
-    /// Os directory entry
-    pub entry: DirEntry,
+    /// File type
+    pub file_type: FileKind,
+    /// Directory entry
+    pub entry: VfsEntry,
 }
 
 impl Display for FileEntry<'_> {
@@ -206,7 +229,7 @@ impl Display for FileEntry<'_> {
         if f.alternate() {
             write!(f, "{}", if self.is_dir() { "D:" } else { "F:" })?;
         }
-        write!(f, "{}", path.to_string_lossy())
+        write!(f, "{}", escape_name(os_str_bytes(&path)))
     }
 }
 
@@ -214,7 +237,7 @@ impl FileEntry<'_> {
     pub fn get_name(&self) -> Cow<'_, OsStr> {
         match self.dir_name {
             Some(v) => Cow::Borrowed(v),
-            None => Cow::Owned(self.entry.file_name()),
+            None => Cow::Borrowed(self.entry.file_name()),
         }
     }
 
@@ -238,24 +261,22 @@ impl FileEntry<'_> {
 }
 
 pub trait FileScanner {
-    fn next_file(&mut self) -> Option<FileEntry>;
+    fn next_file(&mut self) -> Option<FileEntry<'_>>;
 }
 
 impl FileScanner for DepthFileScanner {
-    fn next_file(&mut self) -> Option<FileEntry> {
+    fn next_file(&mut self) -> Option<FileEntry<'_>> {
         loop {
             let iter = self.stack.last_iter()?;
             for entry in iter {
                 let Ok(entry) = entry else {
                     continue;
                 };
-                let Ok(file_type) = entry.file_type() else {
-                    continue;
-                };
+                let file_type = entry.kind();
                 let mut dir_name = None;
                 let before_name = if file_type.is_dir() {
-                    if let Ok(iter) = read_dir(entry.path()) {
-                        self.current.push(entry.file_name());
+                    if let Ok(iter) = self.fs.read_dir(entry.as_path()) {
+                        self.current.push(entry.file_name().to_os_string());
                         self.stack.push(iter);
                         dir_name = self.current.last().map(|v| v.as_os_str());
                         &self.current[..(self.current.len() - 1)]
@@ -284,19 +305,25 @@ pub fn depth_first_files<P: AsRef<Path>>(path: P, keep_dir_open: bool) -> DepthF
     DepthFileScanner::from_dir(path, keep_dir_open)
 }
 
-pub struct SaveToBungee<F, S> {
+/// Function pushing name under parent entry of names table.
+pub trait PushName<I>: FnMut(Option<I>, &OsStr) -> Option<I> {}
+
+impl<I, S: FnMut(Option<I>, &OsStr) -> Option<I>> PushName<I> for S {}
+
+pub struct SaveToBungee<F, S, I = BungeeIndex> {
     it: DepthFileScanner,
-    dirs: Vec<Option<BungeeIndex>>,
+    dirs: Vec<Option<I>>,
     bungee_push: S,
     name_convert: F,
 }
 
-impl<F, S> Iterator for SaveToBungee<F, S>
+impl<F, S, I> Iterator for SaveToBungee<F, S, I>
 where
-    F: FnMut(&OsStr, FileType) -> Option<Cow<'_, str>>,
-    S: FnMut(Option<BungeeIndex>, &str) -> Option<BungeeIndex>,
+    F: FnMut(&OsStr, FileKind) -> Option<Cow<'_, OsStr>>,
+    S: FnMut(Option<I>, &OsStr) -> Option<I>,
+    I: Copy,
 {
-    type Item = (Option<BungeeIndex>, DirEntry, FileType);
+    type Item = (Option<I>, VfsEntry, FileKind);
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
@@ -306,16 +333,13 @@ where
                 let Ok(elem) = elem else {
                     continue;
                 };
-                let Ok(fty) = elem.file_type() else {
-                    continue;
-                };
-                let name = elem.file_name();
-                let Some(name) = (self.name_convert)(&name, fty) else {
+                let fty = elem.kind();
+                let Some(name) = (self.name_convert)(elem.file_name(), fty) else {
                     continue;
                 };
                 let value = (self.bungee_push)(prev, name.as_ref());
                 if fty.is_dir() {
-                    if let Ok(iter) = read_dir(elem.path()) {
+                    if let Ok(iter) = self.it.fs.read_dir(elem.as_path()) {
                         self.it.stack.push(iter);
                         self.dirs.push(value);
                     }
@@ -346,7 +370,7 @@ fn compress_text(text: &[u8], use_burrows_wheeler: bool) -> Vec<u8> {
 mod tests {
     use super::*;
     use crate::file::chunks::{HashesChunk, HashesIterChunk, SortOrder};
-    use crate::store::{compress_sorted_entries, DiffResult, DiffType, DiffingIter};
+    use crate::store::{compress_sorted_entries, DupeFinder, SnapshotDiff};
     use crate::utils::{AveragePerTick, ByteSize, MeasureMemory};
     use crate::*;
     use digest::Digest;
@@ -359,13 +383,10 @@ mod tests {
     use std::fs::File;
     use std::io::BufWriter;
     use std::mem::{replace, size_of_val};
+    use std::path::Path;
     use std::sync::Arc;
     use std::thread::sleep;
     use std::time::{Duration, Instant};
-    use std::{
-        mem::size_of,
-        path::{Path, PathBuf},
-    };
 
     use super::*;
 
@@ -379,7 +400,7 @@ mod tests {
             .filter(|(_, ty)| ty.is_file())
             .map(|(d, _)| d.path().to_string_lossy().into_owned());
         let mut names = FlatedFileNames::new(Compression::best());
-        let ids = names.with_collected(paths).collect::<Vec<_>>();
+        let ids = paths.map(|p| names.push(&p)).collect::<Vec<_>>();
         println!("Count: {}", ids.len());
         //println!("Paths: {paths:?}");
         // let bytes = paths.iter().map(|v| v.capacity()).sum::<usize>();
@@ -404,6 +425,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore] //requires local directory to scan
     fn test_runner() {
         let path = Path::new("D:\\dev");
 
@@ -481,6 +503,7 @@ mod tests {
     }
 
     #[test]
+    #[ignore] //requires local fingerprint files
     fn test_read_sum_file() {
         let path = Path::new("tdev1.raw.hsum");
         //let path = Path::new("tmf1.raw.hsum");
@@ -504,14 +527,7 @@ mod tests {
         println!("last:  {:?}", vals.data.last().unwrap());
 
         let start = Instant::now();
-        let mut dupes = HashMap::new();
-        let mut empty = Vec::new();
-        for e in &vals.data {
-            if e.data == EMPTY_SHA256 {
-                empty.push(e);
-            }
-            dupes.entry(e.data).or_insert_with(Vec::new).push(e.id);
-        }
+        let dupes = DupeFinder::new().add_hashes(&vals).finish();
 
         let mut top_bits = HashMap::new();
         for a in &vals.data {
@@ -522,15 +538,10 @@ mod tests {
         let same_top = top_bits.into_iter().filter(|v| v.1 > 1).map(|v| v.0).collect::<Vec<_>>();
         println!("Same top bits: {:?}", same_top);
 
-        let mut dupes = dupes.into_iter().filter(|(_, v)| v.len() > 1).collect::<Vec<_>>();
-        dupes.sort_unstable();
-        println!("Empty files: {}", empty.len());
-        println!("Duplicates: {}", dupes.len());
-        let dc = dupes.iter().map(|(_, v)| v.len()).collect::<Vec<_>>();
-        println!("Sizes: [max: {}] {:?}", dc.iter().reduce(|a, b| a.max(b)).unwrap_or(&0), dc);
-        // for (data, names) in dupes {
-        //     println!("data: {data:?} names: {names:?}");
-        // }
+        println!("Empty files: {}", dupes.empty_files);
+        println!("Duplicates: {}", dupes.groups.len());
+        let dc = dupes.groups.iter().map(|g| g.files.len()).collect::<Vec<_>>();
+        println!("Sizes: [max: {}] {:?}", dc.iter().max().unwrap_or(&0), dc);
 
         println!("Calc time {:.3?}", start.elapsed());
         let mut compressed = Vec::new();
@@ -547,7 +558,7 @@ mod tests {
         let mut bungee = BungeeStr::new();
         let mut path_len = 0;
         let paths = DepthFileScanner::from_dir(path, true)
-            .save_to_bungee(|a, b| bungee.push(a, b), |n, _| Some(n.to_string_lossy()))
+            .save_to_bungee(|a, b| bungee.push_os(a, b), |n, _| Some(Cow::Borrowed(n)))
             .inspect(|v| path_len += v.1.path().as_os_str().len())
             .filter_map(|(i, _, ty)| ty.is_file().then_some(i).flatten())
             .collect::<Vec<_>>();
@@ -580,14 +591,35 @@ mod tests {
         println!("Average path distances: {:.3}", avg_sum / paths.len() as f64);
     }
 
+    #[test]
+    fn test_save_to_table() {
+        let fs = crate::vfs::MemFs::new()
+            .with_file("root/a/b.txt", b"b".as_slice())
+            .with_file("root/c.txt", b"c".as_slice());
+        let mut table = crate::utils::BungeeStr32::new();
+        let mut files = DepthFileScanner::from_fs(Arc::new(fs), Path::new("root"), true)
+            .save_to_table(&mut table, |n, _| Some(Cow::Borrowed(n)))
+            .filter_map(|(i, _, ty)| ty.is_file().then_some(i).flatten())
+            .collect::<Vec<_>>();
+        files.sort_by_key(|i| table.path_of("/", *i));
+        assert_eq!(table.path_of("/", files[0]), "a/b.txt");
+
+        let names = crate::file::chunks::NamesChunk::new(table, files).to_names().unwrap();
+        let paths = names.indexes().iter().map(|i| names.bungee().path_of("/", *i)).collect::<Vec<_>>();
+        assert_eq!(paths, ["a/b.txt", "c.txt"]);
+    }
+
     fn file_names_hashed(path: impl AsRef<Path>) -> (BungeeStr, Vec<(BungeeIndex, HashArray<32>)>) {
         let mut bungee = BungeeStr::new();
+        let root = path.as_ref().to_path_buf();
         let files = depth_first_files(path, true)
-            .save_to_bungee(|a, b| bungee.push(a, b), |n, _| Some(n.to_string_lossy()))
+            .save_to_bungee(|a, b| bungee.push_os(a, b), |n, _| Some(Cow::Borrowed(n)))
             .filter_map(|(i, e, ty)| Some((ty.is_file().then_some(i).flatten()?, e)))
             .map(|(i, entry)| {
                 let mut array = HashArray::zero();
-                let mut hasher = Sha256::new_with_prefix(entry.path().to_string_lossy().as_bytes());
+                let mut hasher = Sha256::new();
+                let path = entry.path();
+                PathIdentity::EXACT.visit_name_key("", relative_components(Some(&root), &path), |b| hasher.update(b));
                 hasher.finalize_into(GenericArray::from_mut_slice(array.get_mut()));
                 (i, array)
             })
@@ -597,39 +629,31 @@ mod tests {
     }
 
     #[test]
+    #[ignore] //requires local snapshot files
     pub fn test_diff() {
-        let f1 = Path::new("tmf1.raw.hsum");
+        let f1 = Path::new("tmf1.hsum");
         let f2 = Path::new("new_tmf1.hsum");
-        let org_path = Path::new(".");
-
-        let h1 = HashesChunk::read(&mut File::open(f1).unwrap()).unwrap();
-        let h2 = HashesChunk::read(&mut File::open(f2).unwrap()).unwrap();
-        //let h2 = HashesIterChunk::new(File::open(f2).unwrap()).unwrap();
-
-        let (bungee, mut files) = file_names_hashed(org_path);
-        let files = files.into_iter().map(|(a, b)| (b, a)).collect::<HashMap<_, _>>();
-
-        let old = h1.data.iter();
-        let new = h2.data.iter();
-        println!("old size: {}, new size: {}, files len: {}", old.len(), new.len(), files.len());
-        let diff = DiffingIter::new(old, new);
-        let changed = diff.filter(|v| !matches!(v, DiffResult::Same(..))).collect::<Vec<_>>();
-        println!("Changes: {}", changed.len());
 
-        //println!("{changed:#?}");
-
-        for ch in changed {
-            let name_hash = ch.get_name();
-
-            let Some(index) = files.get(name_hash) else {
-                println!("{:?} file not found {:?}", ch.diff_type(), name_hash);
-                continue;
-            };
-            let recovered_path = bungee.path_of("/", *index);
-            println!("Path for {:?} file: {}", ch.diff_type(), recovered_path);
+        let old = crate::file::Snapshot::load(f1).unwrap();
+        let new = crate::file::Snapshot::load(f2).unwrap();
+        println!("old size: {}, new size: {}", old.hashes.data.len(), new.hashes.data.len());
+
+        let diff = SnapshotDiff::new::<Sha256>(&old, &new, "").unwrap();
+        let changes = diff.changes();
+        println!("Changes: {}", changes.len());
+        for ch in &changes {
+            match (&ch.path, &ch.old_path) {
+                (Some(path), Some(old_path)) => println!("{:?}: {} -> {}", ch.diff.diff_type(), old_path.display(), path.display()),
+                (Some(path), None) => println!("{:?}: {}", ch.diff.diff_type(), path.display()),
+                (None, _) => println!("{:?} file not found {:?}", ch.diff.diff_type(), ch.diff.get_name()),
+            }
+        }
+        for dir in diff.rollup(&changes, Some(2)) {
+            println!("{dir}");
         }
 
-        let mem = h1.memory_usage() + h2.memory_usage() + bungee.memory_usage() + files.len() * size_of::<(BungeeIndex, HashArray<32>)>();
+        let mem =
+            old.hashes.memory_usage() + new.hashes.memory_usage() + old.names.bungee().memory_usage() + new.names.bungee().memory_usage();
         println!("Approx mem: {:.03}Mb", mem as f64 / (1024.0 * 1024.0));
     }
 }
@@ -0,0 +1,207 @@
+use crate::utils::os_str_bytes;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::path::{Component, Path};
+use unicode_normalization::{is_nfc_quick, is_nfd_quick, IsNormalized, UnicodeNormalization};
+
+/// Separator used between path components when deriving name ids, the same on every platform.
+pub const NAME_SEPARATOR: &[u8] = b"/";
+
+/// Unicode normalization form applied to names before hashing and storing them.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+#[repr(u8)]
+pub enum UnicodeForm {
+    /// Names are used exactly as returned by os
+    #[default]
+    Exact = 0,
+    /// Composed form, used by most of linux and windows file systems
+    Nfc = 1,
+    /// Decomposed form, used by macOS file systems
+    Nfd = 2,
+}
+
+/// Policy deciding when two paths are considered the same file, eg. when the same tree is copied between
+/// file systems that store names in different unicode forms, or that are case-insensitive.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct PathIdentity {
+    pub form: UnicodeForm,
+    pub case_fold: bool,
+}
+
+impl PathIdentity {
+    pub const EXACT: Self = Self {
+        form: UnicodeForm::Exact,
+        case_fold: false,
+    };
+
+    const FORM_MASK: u8 = 0x3;
+    const CASE_FOLD_BIT: u8 = 0x4;
+
+    pub const fn new(form: UnicodeForm, case_fold: bool) -> Self {
+        Self { form, case_fold }
+    }
+
+    pub fn is_exact(&self) -> bool {
+        *self == Self::EXACT
+    }
+
+    /// Encode policy as flag bits, used in block headers.
+    pub fn to_bits(&self) -> u8 {
+        self.form as u8 | if self.case_fold { Self::CASE_FOLD_BIT } else { 0 }
+    }
+
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        if bits & !(Self::FORM_MASK | Self::CASE_FOLD_BIT) != 0 {
+            return None;
+        }
+        let form = match bits & Self::FORM_MASK {
+            0 => UnicodeForm::Exact,
+            1 => UnicodeForm::Nfc,
+            2 => UnicodeForm::Nfd,
+            _ => return None,
+        };
+        Some(Self {
+            form,
+            case_fold: bits & Self::CASE_FOLD_BIT != 0,
+        })
+    }
+
+    pub fn normalize_str<'a>(&self, name: &'a str) -> Cow<'a, str> {
+        let name = if self.case_fold {
+            //folding is done first, because it may produce sequences that are not normalized
+            match caseless::default_case_fold_str(name) {
+                folded if folded == name => Cow::Borrowed(name),
+                folded => Cow::Owned(folded),
+            }
+        } else {
+            Cow::Borrowed(name)
+        };
+        match self.form {
+            UnicodeForm::Exact => name,
+            UnicodeForm::Nfc if is_nfc_quick(name.chars()) == IsNormalized::Yes => name,
+            UnicodeForm::Nfd if is_nfd_quick(name.chars()) == IsNormalized::Yes => name,
+            UnicodeForm::Nfc => Cow::Owned(name.nfc().collect()),
+            UnicodeForm::Nfd => Cow::Owned(name.nfd().collect()),
+        }
+    }
+
+    /// Normalize os name, names that are not valid UTF-8 are returned unchanged.
+    pub fn normalize<'a>(&self, name: &'a OsStr) -> Cow<'a, OsStr> {
+        if self.is_exact() {
+            return Cow::Borrowed(name);
+        }
+        let Some(s) = name.to_str() else {
+            return Cow::Borrowed(name);
+        };
+        match self.normalize_str(s) {
+            Cow::Borrowed(s) => Cow::Borrowed(OsStr::new(s)),
+            Cow::Owned(s) => Cow::Owned(s.into()),
+        }
+    }
+
+    /// Feed bytes identifying path to sink: optional prefix and all normalized components, joined with
+    /// [`NAME_SEPARATOR`].
+    pub fn visit_name_key<'a>(&self, prefix: &str, components: impl IntoIterator<Item = &'a OsStr>, mut sink: impl FnMut(&[u8])) {
+        let mut first = prefix.is_empty();
+        if !first {
+            sink(self.normalize_str(prefix).as_bytes());
+        }
+        for c in components {
+            if !first {
+                sink(NAME_SEPARATOR);
+            }
+            first = false;
+            sink(os_str_bytes(&self.normalize(c)));
+        }
+    }
+
+    /// Short id of name prefix, recorded in block headers so that readers can tell which prefix name ids were
+    /// derived with. Empty prefix has no id.
+    pub fn prefix_id(&self, prefix: &str) -> Option<u64> {
+        if prefix.is_empty() {
+            return None;
+        }
+        let digest = Sha256::digest(self.normalize_str(prefix).as_bytes());
+        Some(u64::from_le_bytes(digest[..8].try_into().unwrap()))
+    }
+
+    /// Bytes identifying path, see [`Self::visit_name_key`].
+    pub fn name_key<'a>(&self, prefix: &str, components: impl IntoIterator<Item = &'a OsStr>) -> Vec<u8> {
+        let mut key = Vec::new();
+        self.visit_name_key(prefix, components, |b| key.extend_from_slice(b));
+        key
+    }
+}
+
+/// Components of path with root stripped, when path is not inside root, all of its components are used.
+/// Root directory and current directory markers are skipped, so the result doesn't depend on platform separator.
+pub fn relative_components<'a>(root: Option<&Path>, path: &'a Path) -> impl Iterator<Item = &'a OsStr> + 'a {
+    let path = match root {
+        Some(root) => path.strip_prefix(root).unwrap_or(path),
+        None => path,
+    };
+    path.components().filter_map(|c| match c {
+        Component::Prefix(p) => Some(p.as_os_str()),
+        Component::RootDir | Component::CurDir => None,
+        Component::ParentDir => Some(OsStr::new("..")),
+        Component::Normal(n) => Some(n),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        let nfd = "Cafe\u{301}.TXT";
+        let nfc = "Caf\u{e9}.TXT";
+        assert_eq!(PathIdentity::EXACT.normalize_str(nfd), nfd);
+        assert_eq!(PathIdentity::new(UnicodeForm::Nfc, false).normalize_str(nfd), nfc);
+        assert_eq!(PathIdentity::new(UnicodeForm::Nfd, false).normalize_str(nfc), nfd);
+        assert_eq!(PathIdentity::new(UnicodeForm::Nfc, true).normalize_str(nfd), "caf\u{e9}.txt");
+        assert_eq!(PathIdentity::new(UnicodeForm::Exact, true).normalize_str("STRASSE"), "strasse");
+        assert_eq!(PathIdentity::new(UnicodeForm::Exact, true).normalize_str("Straße"), "strasse");
+        assert!(matches!(
+            PathIdentity::new(UnicodeForm::Nfc, false).normalize_str(nfc),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn test_name_key() {
+        let exact = PathIdentity::EXACT;
+        let root = Path::new("/mnt/a/data");
+        let key = |root, path: &str, prefix| exact.name_key(prefix, relative_components(root, Path::new(path)));
+        assert_eq!(key(Some(root), "/mnt/a/data/x/y.txt", ""), b"x/y.txt");
+        assert_eq!(key(Some(Path::new("/mnt/b/data")), "/mnt/b/data/x/y.txt", ""), b"x/y.txt");
+        assert_eq!(key(Some(root), "/mnt/a/data/x/y.txt", "backup"), b"backup/x/y.txt");
+        assert_eq!(key(None, "./x//y.txt", ""), b"x/y.txt");
+        assert_eq!(key(Some(root), "/other/x", ""), b"other/x");
+        let nfc = PathIdentity::new(UnicodeForm::Nfc, true);
+        let key = nfc.name_key("Root", relative_components(None, Path::new("Cafe\u{301}/X")));
+        assert_eq!(key, "root/caf\u{e9}/x".as_bytes());
+    }
+
+    #[test]
+    fn test_prefix_id() {
+        let folded = PathIdentity::new(UnicodeForm::Nfc, true);
+        assert_eq!(folded.prefix_id(""), None);
+        assert_eq!(folded.prefix_id("Backup"), folded.prefix_id("backup"));
+        assert_ne!(PathIdentity::EXACT.prefix_id("Backup"), PathIdentity::EXACT.prefix_id("backup"));
+        assert_ne!(folded.prefix_id("backup"), folded.prefix_id("backup2"));
+    }
+
+    #[test]
+    fn test_bits() {
+        for form in [UnicodeForm::Exact, UnicodeForm::Nfc, UnicodeForm::Nfd] {
+            for case_fold in [false, true] {
+                let id = PathIdentity::new(form, case_fold);
+                assert_eq!(PathIdentity::from_bits(id.to_bits()), Some(id));
+            }
+        }
+        assert_eq!(PathIdentity::from_bits(3), None);
+        assert_eq!(PathIdentity::from_bits(0x10), None);
+    }
+}
@@ -0,0 +1,135 @@
+use digest::core_api::BlockSizeUser;
+use digest::Digest;
+use generic_array::GenericArray;
+use sha2::Sha256;
+
+/// Secret key of keyed hashing, name ids and content hashes made with it can't be confirmed by guessing paths or
+/// contents without the key. Key has to be 32 random bytes, keys derived from passwords could be brute forced with
+/// the fingerprint.
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub struct HashKey {
+    key: [u8; 32],
+}
+
+impl HashKey {
+    const ID_CONTEXT: &'static [u8] = b"hashsummer key id\0";
+
+    pub fn new(key: [u8; 32]) -> Self {
+        Self { key }
+    }
+
+    /// Public identifier of key, recorded in fingerprints, so that fingerprints made with the same key can be
+    /// recognized. Key can't be recovered from it.
+    pub fn id(&self) -> u64 {
+        let hash = Sha256::new().chain_update(Self::ID_CONTEXT).chain_update(self.key).finalize();
+        u64::from_le_bytes(hash[..8].try_into().unwrap())
+    }
+}
+
+impl std::fmt::Debug for HashKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        //key itself is never printed
+        write!(f, "HashKey({:016x})", self.id())
+    }
+}
+
+/// HMAC of any digest, with padded key already hashed into both inner and outer states, so that every hashing only
+/// clones them.
+#[derive(Clone)]
+pub struct KeyedDigest<D> {
+    inner: D,
+    outer: D,
+    id: u64,
+}
+
+impl<D: Digest + BlockSizeUser + Clone> KeyedDigest<D> {
+    const IPAD: u8 = 0x36;
+    const OPAD: u8 = 0x5c;
+
+    pub fn new(key: &HashKey) -> Self {
+        let mut block = GenericArray::<u8, D::BlockSize>::default();
+        if key.key.len() > block.len() {
+            let hashed = D::digest(key.key);
+            block[..hashed.len()].copy_from_slice(&hashed);
+        } else {
+            block[..key.key.len()].copy_from_slice(&key.key);
+        }
+        let pad = |value: u8| block.iter().map(|b| b ^ value).collect::<Vec<_>>();
+        Self {
+            inner: D::new().chain_update(pad(Self::IPAD)),
+            outer: D::new().chain_update(pad(Self::OPAD)),
+            id: key.id(),
+        }
+    }
+}
+
+impl<D: Digest + Clone> KeyedDigest<D> {
+    /// Hasher to be updated with message.
+    pub fn start(&self) -> D {
+        self.inner.clone()
+    }
+
+    /// Finish hasher returned from [`Self::start`].
+    pub fn finalize_into(&self, started: D, out: &mut digest::Output<D>) {
+        self.outer.clone().chain_update(started.finalize()).finalize_into(out);
+    }
+
+    /// Identifier of key, see [`HashKey::id`].
+    pub fn key_id(&self) -> u64 {
+        self.id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Consumer, DigestConsumer};
+    use parking_lot::Mutex;
+    use std::path::Path;
+
+    #[test]
+    fn test_hmac_sha256() {
+        //RFC 4231 test case 2 uses short key, which is zero padded the same way as our 32 bytes keys
+        let mut key = [0u8; 32];
+        key[..4].copy_from_slice(b"Jefe");
+        let keyed = KeyedDigest::<Sha256>::new(&HashKey::new(key));
+        let mut hasher = keyed.start();
+        hasher.update(b"what do ya want for nothing?");
+        let mut out = Default::default();
+        keyed.finalize_into(hasher, &mut out);
+        let expected = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+        assert_eq!(format!("{out:x}"), expected);
+
+        let other = HashKey::new([7; 32]);
+        assert_ne!(other.id(), HashKey::new(key).id());
+        assert!(!format!("{other:?}").contains("0707"));
+    }
+
+    #[test]
+    fn test_keyed_consumer() {
+        let hash = |key: Option<&HashKey>| {
+            let entries = Mutex::new(Vec::new());
+            let cons = DigestConsumer::<32, 32, Sha256, _>::new(|e| entries.lock().push(e)).with_root("root");
+            let cons = match key {
+                Some(key) => cons.with_key(key),
+                None => cons,
+            };
+            let name = cons.consume_name(Path::new("root/secret/plan.txt"));
+            let mut file = cons.start_file();
+            cons.update_file(&mut file, b"plan");
+            cons.finish_consume(name, file);
+            (cons.key_id(), entries.into_inner()[0])
+        };
+        let key = HashKey::new([42; 32]);
+        let (plain_id, plain) = hash(None);
+        let (key_id, keyed) = hash(Some(&key));
+        assert_eq!(plain_id, None);
+        assert_eq!(key_id, Some(key.id()));
+        assert_eq!(hash(Some(&key)).1, keyed);
+        assert_ne!(keyed.id, plain.id);
+        assert_ne!(keyed.data, plain.data);
+        let (_, other) = hash(Some(&HashKey::new([43; 32])));
+        assert_ne!(other.id, keyed.id);
+        assert_ne!(other.data, keyed.data);
+    }
+}
@@ -0,0 +1,145 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Identity of file content on a device, shared by all hard links of the same file.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct FileId {
+    pub device: u64,
+    pub inode: u64,
+}
+
+impl FileId {
+    /// Returns id of the file only when it has more than one hard link, files with single link don't need tracking.
+    pub fn of_linked(meta: &Metadata) -> Option<Self> {
+        cfg_if::cfg_if! {
+            if #[cfg(unix)] {
+                use std::os::unix::fs::MetadataExt;
+                if meta.nlink() < 2 {
+                    return None;
+                }
+                Some(Self {
+                    device: meta.dev(),
+                    inode: meta.ino(),
+                })
+            } else {
+                //file index on windows is not available on stable, treat every file as a separate copy
+                None
+            }
+        }
+    }
+}
+
+/// Group of paths that point to the same file content, first path was scheduled for hashing, the rest was skipped.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct LinkGroup {
+    pub id: FileId,
+    pub primary: PathBuf,
+    pub links: Vec<PathBuf>,
+}
+
+/// Tracks hard links encountered during scan, so that content shared by many links is hashed only once.
+#[derive(Default)]
+pub struct HardLinks {
+    inner: Mutex<LinksInner>,
+}
+
+#[derive(Default)]
+struct LinksInner {
+    seen: HashMap<FileId, usize>,
+    groups: Vec<LinkGroup>,
+}
+
+impl HardLinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register path in tracker, returns primary path if this path is another link to already registered file,
+    /// in such case file doesn't need to be read again.
+    pub fn register(&self, path: &Path) -> io::Result<Option<PathBuf>> {
+        let Some(id) = FileId::of_linked(&path.metadata()?) else {
+            return Ok(None);
+        };
+        Ok(self.register_id(id, path))
+    }
+
+    pub fn register_id(&self, id: FileId, path: &Path) -> Option<PathBuf> {
+        let mut lock = self.inner.lock();
+        let inner = &mut *lock;
+        match inner.seen.get(&id) {
+            Some(&index) => {
+                let group = &mut inner.groups[index];
+                group.links.push(path.to_path_buf());
+                Some(group.primary.clone())
+            }
+            None => {
+                inner.seen.insert(id, inner.groups.len());
+                inner.groups.push(LinkGroup {
+                    id,
+                    primary: path.to_path_buf(),
+                    links: Vec::new(),
+                });
+                None
+            }
+        }
+    }
+
+    /// Number of paths that were skipped, because their content was already scheduled.
+    pub fn skipped_count(&self) -> usize {
+        self.inner.lock().groups.iter().map(|g| g.links.len()).sum()
+    }
+
+    /// All groups with at least two paths pointing to the same content.
+    pub fn into_groups(self) -> Vec<LinkGroup> {
+        let mut groups = self.inner.into_inner().groups;
+        groups.retain(|g| !g.links.is_empty());
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{hard_link, write};
+
+    #[test]
+    fn test_register_ids() {
+        let links = HardLinks::new();
+        let a = FileId { device: 1, inode: 10 };
+        let b = FileId { device: 2, inode: 10 };
+        assert_eq!(links.register_id(a, Path::new("a")), None);
+        assert_eq!(links.register_id(b, Path::new("b")), None);
+        assert_eq!(links.register_id(a, Path::new("c")), Some(PathBuf::from("a")));
+        assert_eq!(links.register_id(a, Path::new("d")), Some(PathBuf::from("a")));
+        assert_eq!(links.skipped_count(), 2);
+
+        let groups = links.into_groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].primary, Path::new("a"));
+        assert_eq!(groups[0].links, [Path::new("c"), Path::new("d")]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_register_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("original");
+        let link = dir.path().join("link");
+        let copy = dir.path().join("copy");
+        write(&original, b"data").unwrap();
+        write(&copy, b"data").unwrap();
+        hard_link(&original, &link).unwrap();
+
+        let links = HardLinks::new();
+        assert_eq!(links.register(&original).unwrap(), None);
+        assert_eq!(links.register(&copy).unwrap(), None);
+        assert_eq!(links.register(&link).unwrap(), Some(original.clone()));
+        let groups = links.into_groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].primary, original);
+        assert_eq!(groups[0].links, [link]);
+    }
+}
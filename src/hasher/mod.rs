@@ -1,8 +1,12 @@
 mod file_iter;
+mod identity;
+mod keyed;
+mod links;
 mod names;
 mod runner;
 mod sum_file;
 
+use digest::core_api::BlockSizeUser;
 use digest::{Digest, FixedOutputReset};
 use generic_array::GenericArray;
 use parking_lot::Mutex;
@@ -20,6 +24,9 @@ use std::{
 };
 
 pub use file_iter::*;
+pub use identity::*;
+pub use keyed::*;
+pub use links::*;
 pub use names::*;
 pub use runner::*;
 pub use sum_file::*;
@@ -42,7 +49,7 @@ const _: () = {
     let mut a = HashEntry::<32, 32>::zero();
     a.id.array[0] = 1;
     a.data.array[0] = 2;
-    let array = unsafe { transmute::<_, [u8; 64]>(a) };
+    let array = unsafe { transmute::<HashEntry<32, 32>, [u8; 64]>(a) };
     assert!(array[0] == 1);
     assert!(array[32] == 2);
     assert!(array[63] == 0);
@@ -396,6 +403,11 @@ pub trait Consumer {
 
 pub struct DigestConsumer<const ID: usize, const DATA: usize, D: Digest, F: Fn(HashEntry<ID, DATA>)> {
     consume: F,
+    identity: PathIdentity,
+    root: Option<PathBuf>,
+    prefix: String,
+    /// Both names and contents are hashed with key, when set
+    keyed: Option<KeyedDigest<D>>,
     total_bytes: AtomicU64,
     _phantom: PhantomData<D>,
 }
@@ -404,30 +416,91 @@ impl<const ID: usize, const DATA: usize, D: Digest, F: Fn(HashEntry<ID, DATA>)>
     pub fn new(consume: F) -> Self {
         Self {
             consume,
+            identity: PathIdentity::EXACT,
+            root: None,
+            prefix: String::new(),
+            keyed: None,
             total_bytes: AtomicU64::new(0),
             _phantom: PhantomData,
         }
     }
+    /// Set policy of normalizing paths before hashing them into name ids.
+    pub fn with_identity(mut self, identity: PathIdentity) -> Self {
+        self.identity = identity;
+        self
+    }
+    pub fn identity(&self) -> PathIdentity {
+        self.identity
+    }
+    /// Name ids will be derived from paths relative to this root, so that the same tree scanned from different
+    /// mount points gets the same ids.
+    pub fn with_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.root = Some(root.into());
+        self
+    }
+    /// Prefix prepended as first component of every relative path before hashing.
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+    /// Id of name prefix to record in hashes header, see [`PathIdentity::prefix_id`].
+    pub fn prefix_id(&self) -> Option<u64> {
+        self.identity.prefix_id(&self.prefix)
+    }
+    /// Hash names and contents with HMAC of given key, so that fingerprint doesn't reveal names or contents to
+    /// anyone without the key.
+    pub fn with_key(mut self, key: &HashKey) -> Self
+    where
+        D: BlockSizeUser + Clone,
+    {
+        self.keyed = Some(KeyedDigest::new(key));
+        self
+    }
+    /// Identifier of key used for hashing, see [`HashKey::id`].
+    pub fn key_id(&self) -> Option<u64>
+    where
+        D: Clone,
+    {
+        self.keyed.as_ref().map(|k| k.key_id())
+    }
+    fn start_hasher(&self) -> D
+    where
+        D: Clone,
+    {
+        self.keyed.as_ref().map(|k| k.start()).unwrap_or_else(D::new)
+    }
+    fn finish_hasher(&self, hasher: D, out: &mut [u8])
+    where
+        D: Clone,
+    {
+        let out = GenericArray::from_mut_slice(out);
+        match &self.keyed {
+            Some(keyed) => keyed.finalize_into(hasher, out),
+            None => hasher.finalize_into(out),
+        }
+    }
     pub fn get_total_bytes(&self) -> u64 {
         self.total_bytes.load(std::sync::atomic::Ordering::Relaxed)
     }
 }
 
-impl<const ID: usize, const DATA: usize, D: Digest, F: Fn(HashEntry<ID, DATA>)> Consumer for DigestConsumer<ID, DATA, D, F> {
+impl<const ID: usize, const DATA: usize, D: Digest + Clone, F: Fn(HashEntry<ID, DATA>)> Consumer for DigestConsumer<ID, DATA, D, F> {
     type NameState<'a> = HashArray<ID>;
     type FileState<'a> = D;
 
     fn consume_name<'a>(&self, path: &'a Path) -> Self::NameState<'a> {
-        //todo review file name hashing
-        let mut hasher = D::new_with_prefix(path.to_string_lossy().as_bytes());
+        //raw os bytes of components are used, so that names which are not valid UTF-8 don't collide
+        let mut hasher = self.start_hasher();
+        let components = relative_components(self.root.as_deref(), path);
+        self.identity.visit_name_key(&self.prefix, components, |b| hasher.update(b));
 
         let mut name = HashArray::zero();
-        hasher.finalize_into(GenericArray::from_mut_slice(name.get_mut()));
+        self.finish_hasher(hasher, name.get_mut());
         name
     }
 
     fn start_file(&self) -> Self::FileState<'_> {
-        D::new()
+        self.start_hasher()
     }
     fn update_file(&self, state: &mut Self::FileState<'_>, data: &[u8]) {
         self.total_bytes.fetch_add(data.len() as _, std::sync::atomic::Ordering::Relaxed);
@@ -439,7 +512,7 @@ impl<const ID: usize, const DATA: usize, D: Digest, F: Fn(HashEntry<ID, DATA>)>
             id: name,
             data: HashArray::zero(),
         };
-        file.finalize_into(GenericArray::from_mut_slice(entry.data.get_mut()));
+        self.finish_hasher(file, entry.data.get_mut());
         (self.consume)(entry);
     }
 }
@@ -559,6 +632,34 @@ mod tests {
         assert!(a > b);
     }
 
+    #[test]
+    fn test_consume_name_identity() {
+        let exact = DigestConsumer::<32, 32, sha2::Sha256, _>::new(|_| {});
+        let nfc = DigestConsumer::<32, 32, sha2::Sha256, _>::new(|_| {}).with_identity(PathIdentity::new(UnicodeForm::Nfc, true));
+        let composed = Path::new("dir/Caf\u{e9}");
+        let decomposed = Path::new("DIR/cafe\u{301}");
+        assert_ne!(exact.consume_name(composed), exact.consume_name(decomposed));
+        assert_eq!(nfc.consume_name(composed), nfc.consume_name(decomposed));
+        assert_eq!(exact.consume_name(Path::new("dir/caf\u{e9}")), nfc.consume_name(decomposed));
+    }
+
+    #[test]
+    fn test_consume_name_root() {
+        let a = DigestConsumer::<32, 32, sha2::Sha256, _>::new(|_| {}).with_root("/mnt/a/data");
+        let b = DigestConsumer::<32, 32, sha2::Sha256, _>::new(|_| {}).with_root("/mnt/b/data");
+        let name = a.consume_name(Path::new("/mnt/a/data/dir/file.txt"));
+        assert_eq!(name, b.consume_name(Path::new("/mnt/b/data/dir/file.txt")));
+        assert_ne!(name, a.consume_name(Path::new("/mnt/a/data/dir/other.txt")));
+
+        let prefixed = DigestConsumer::<32, 32, sha2::Sha256, _>::new(|_| {})
+            .with_root("/mnt/b/data")
+            .with_prefix("dir");
+        assert_eq!(name, prefixed.consume_name(Path::new("/mnt/b/data/file.txt")));
+
+        let expected = sha2::Sha256::digest(b"dir/file.txt");
+        assert_eq!(name.get_ref(), expected.as_slice());
+    }
+
     #[test]
     fn test_zero_find() {
         let test = HashZeroChunksFinder {
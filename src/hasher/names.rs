@@ -1,15 +1,19 @@
-use flate2::read::DeflateDecoder;
+use crate::utils::{read_table_bytes, read_u64, BungeeName, MeasureMemory, NameTable};
 use flate2::write::DeflateEncoder;
-use flate2::Compression;
+use flate2::{Compression, Decompress, FlushDecompress};
+use std::cell::{OnceCell, RefCell};
 use std::collections::TryReserveError;
+use std::ffi::OsStr;
 use std::io;
-use std::io::{BufWriter, Read, Write};
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use std::mem::size_of;
 
 pub struct FileNames {
     string: String,
+    tree: NameTree,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub struct NameRefId {
     pos: u32,
     len: u32,
@@ -22,27 +26,121 @@ pub enum PushError {
     Mem(TryReserveError),
 }
 
-pub trait NamesStorage {
-    fn try_push(&mut self, name: &str) -> Result<NameRefId, PushError>;
+impl From<PushError> for Error {
+    fn from(value: PushError) -> Self {
+        match value {
+            PushError::LengthOverflow => Error::new(ErrorKind::InvalidInput, "Name is too long"),
+            PushError::SizeOverflow => Error::new(ErrorKind::Unsupported, "Names exceed 4 GiB"),
+            PushError::Mem(e) => Error::new(ErrorKind::OutOfMemory, e),
+        }
+    }
+}
 
-    fn push(&mut self, name: &str) -> NameRefId {
-        self.try_push(name).unwrap()
+impl NameRefId {
+    fn new(pos: usize, name: &str) -> Result<Self, PushError> {
+        let len = name.len().try_into().map_err(|_| PushError::LengthOverflow)?;
+        let pos = pos.try_into().map_err(|_| PushError::SizeOverflow)?;
+        //end of name has to fit too, so names can be sliced from positions
+        u32::checked_add(pos, len).ok_or(PushError::SizeOverflow)?;
+        Ok(Self { pos, len })
+    }
+}
+
+/// Names pushed as part of tree, see [`NameTable`], with ordinal of their parent. Names are pushed with increasing
+/// positions, so entries are found by binary search.
+#[derive(Default)]
+struct NameTree {
+    entries: Vec<(NameRefId, Option<u32>)>,
+}
+
+impl NameTree {
+    fn ordinal_of(&self, id: NameRefId) -> Option<usize> {
+        self.entries.binary_search_by_key(&id.pos, |(e, _)| e.pos).ok()
+    }
+
+    fn parent_ordinal(&self, parent: Option<NameRefId>) -> io::Result<Option<u32>> {
+        match parent {
+            Some(p) => {
+                let ordinal = self
+                    .ordinal_of(p)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Parent is not a tree entry"))?;
+                Ok(Some(ordinal as u32))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn parent_of(&self, at: NameRefId) -> Option<NameRefId> {
+        let parent = self.ordinal_of(at).and_then(|i| self.entries[i].1);
+        parent.map(|p| self.entries[p as usize].0)
+    }
+
+    fn skip(&self, at: NameRefId) -> Option<NameRefId> {
+        let i = self.ordinal_of(at)?;
+        i.checked_sub(1).map(|i| self.entries[i].0)
+    }
+
+    fn last(&self) -> Option<NameRefId> {
+        self.entries.last().map(|(e, _)| *e)
+    }
+
+    fn write<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (id, parent) in &self.entries {
+            write.write_all(&id.pos.to_le_bytes())?;
+            write.write_all(&id.len.to_le_bytes())?;
+            write.write_all(&parent.unwrap_or(u32::MAX).to_le_bytes())?;
+        }
+        Ok(())
     }
-    fn total_len(&self) -> usize;
-
-    fn with_collected<I>(&mut self, it: I) -> WithCollected<I::IntoIter, Self>
-    where
-        Self: Sized,
-        I: IntoIterator,
-        I::Item: AsRef<str>,
-    {
-        WithCollected {
-            parent: self,
-            it: it.into_iter(),
+
+    /// Read entries of names in given string, entries have to be ordered and link to parents pushed before them.
+    fn read<R: Read>(read: &mut R, string: &str) -> io::Result<Self> {
+        let count = read_u64(read)?;
+        let mut entries = Vec::with_capacity(count.min(1024 * 1024) as usize);
+        let mut read_u32 = || -> io::Result<u32> {
+            let mut buf = [0u8; size_of::<u32>()];
+            read.read_exact(&mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        };
+        for i in 0..count {
+            let id = NameRefId {
+                pos: read_u32()?,
+                len: read_u32()?,
+            };
+            let parent = Some(read_u32()?).filter(|p| *p != u32::MAX);
+            let end = id.pos as usize + id.len as usize;
+            let ordered = entries.last().is_none_or(|(e, _): &(NameRefId, _)| e.pos < id.pos);
+            if id.len == 0
+                || end > string.len()
+                || !string.is_char_boundary(id.pos as usize)
+                || !string.is_char_boundary(end)
+                || !ordered
+                || parent.is_some_and(|p| p as u64 >= i)
+            {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid name entry"));
+            }
+            entries.push((id, parent));
         }
+        Ok(Self { entries })
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.entries.capacity() * size_of::<(NameRefId, Option<u32>)>()
     }
 }
 
+/// Name of tree entry, names of entries are never empty.
+fn name_in(string: &str, id: NameRefId) -> BungeeName<'_> {
+    let start = id.pos as usize;
+    BungeeName::new(string.get(start..(start + id.len as usize)).unwrap_or_default().as_bytes(), false)
+}
+
+fn utf8_name(name: &OsStr) -> io::Result<&str> {
+    name.to_str()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "File names support only UTF-8 names"))
+}
+
 impl Default for FileNames {
     fn default() -> Self {
         Self::new()
@@ -51,7 +149,10 @@ impl Default for FileNames {
 
 impl FileNames {
     pub fn new() -> Self {
-        Self { string: String::new() }
+        Self {
+            string: String::new(),
+            tree: NameTree::default(),
+        }
     }
     pub fn total_capacity(&self) -> usize {
         self.string.capacity()
@@ -66,85 +167,221 @@ impl FileNames {
     pub fn total_str(&self) -> &str {
         &self.string
     }
-}
 
-impl NamesStorage for FileNames {
-    fn try_push(&mut self, name: &str) -> Result<NameRefId, PushError> {
-        let len = name.len().try_into().map_err(|_| PushError::LengthOverflow)?;
-        let pos = self.string.len().try_into().map_err(|_| PushError::SizeOverflow)?;
-        let result = NameRefId { len, pos };
+    /// Push name that is not part of tree.
+    pub fn try_push(&mut self, name: &str) -> Result<NameRefId, PushError> {
+        let result = NameRefId::new(self.string.len(), name)?;
         self.string.try_reserve(name.len()).map_err(PushError::Mem)?;
         self.string.push_str(name);
         Ok(result)
     }
 
-    fn total_len(&self) -> usize {
+    pub fn push(&mut self, name: &str) -> NameRefId {
+        self.try_push(name).unwrap()
+    }
+
+    pub fn total_len(&self) -> usize {
         self.string.len()
     }
 }
 
+impl MeasureMemory for FileNames {
+    fn memory_usage(&self) -> usize {
+        self.string.capacity() + self.tree.memory_usage()
+    }
+}
+
+/// Tree of names stored as text, only names that are valid UTF-8 are supported.
+impl NameTable for FileNames {
+    type Index = NameRefId;
+
+    fn try_push_os(&mut self, parent: Option<NameRefId>, name: &OsStr) -> io::Result<Option<NameRefId>> {
+        let name = utf8_name(name)?;
+        if name.is_empty() {
+            return Ok(parent);
+        }
+        let parent = self.tree.parent_ordinal(parent)?;
+        let id = self.try_push(name)?;
+        self.tree.entries.push((id, parent));
+        Ok(Some(id))
+    }
+
+    fn reverse_follow(&self, at: NameRefId) -> (BungeeName<'_>, Option<NameRefId>) {
+        (name_in(&self.string, at), self.tree.parent_of(at))
+    }
+
+    fn reverse_skip(&self, at: NameRefId) -> Option<NameRefId> {
+        self.tree.skip(at)
+    }
+
+    fn last_index(&self) -> Option<NameRefId> {
+        self.tree.last()
+    }
+
+    fn write_table<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_all(&(self.string.len() as u64).to_le_bytes())?;
+        write.write_all(self.string.as_bytes())?;
+        self.tree.write(write)
+    }
+
+    fn read_table<R: Read>(read: &mut R) -> io::Result<Self> {
+        let string = String::from_utf8(read_table_bytes(read, u32::MAX as _)?)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Names are not UTF-8"))?;
+        let tree = NameTree::read(read, &string)?;
+        Ok(Self { string, tree })
+    }
+}
+
+/// Names that are deflated as they are pushed, so they take a fraction of memory while tree is scanned. The first
+/// lookup after push decompresses all names, and decompressed copy is kept until the next push.
 pub struct FlatedFileNames {
-    data: BufWriter<DeflateEncoder<Vec<u8>>>,
+    data: RefCell<BufWriter<DeflateEncoder<Vec<u8>>>>,
     pos: usize,
+    tree: NameTree,
+    /// Decompressed names for lookups
+    names: OnceCell<String>,
+}
+
+impl Default for FlatedFileNames {
+    fn default() -> Self {
+        Self::new(Compression::default())
+    }
 }
 
 impl FlatedFileNames {
     pub fn new(level: Compression) -> Self {
         Self {
-            data: BufWriter::new(DeflateEncoder::new(Vec::new(), level)),
+            data: RefCell::new(BufWriter::new(DeflateEncoder::new(Vec::new(), level))),
             pos: 0,
+            tree: NameTree::default(),
+            names: OnceCell::new(),
         }
     }
 
     pub fn current_compressed_len(&self) -> usize {
-        self.data.get_ref().total_out() as _
+        self.data.borrow().get_ref().total_out() as _
     }
 
     pub fn finish(self) -> Vec<u8> {
-        self.data.into_inner().unwrap().flush_finish().unwrap()
+        self.data.into_inner().into_inner().unwrap().flush_finish().unwrap()
     }
 
-    pub fn decompress(mut data: &[u8]) -> io::Result<FileNames> {
-        let mut string = String::with_capacity(data.len() * 4); //assume some starting capacity
-        let mut v = DeflateDecoder::new(&mut data);
-        v.read_to_string(&mut string)?;
-        Ok(FileNames { string })
+    /// Decompressed names, with tree entries kept.
+    pub fn decompress(self) -> io::Result<FileNames> {
+        let string = match self.names.into_inner() {
+            Some(string) => string,
+            None => inflate(&self.data, self.pos)?,
+        };
+        Ok(FileNames { string, tree: self.tree })
     }
-}
 
-impl NamesStorage for FlatedFileNames {
-    fn try_push(&mut self, name: &str) -> Result<NameRefId, PushError> {
-        let len = name.len().try_into().map_err(|_| PushError::LengthOverflow)?;
-        let pos = self.pos.try_into().map_err(|_| PushError::SizeOverflow)?;
-        let result = NameRefId { len, pos };
-        self.pos = self.pos.checked_add(name.len()).ok_or(PushError::SizeOverflow)?;
-        self.data.write_all(name.as_bytes()).unwrap();
+    /// Push name that is not part of tree.
+    pub fn try_push(&mut self, name: &str) -> Result<NameRefId, PushError> {
+        let result = NameRefId::new(self.pos, name)?;
+        self.pos += name.len();
+        self.names.take();
+        self.data.get_mut().write_all(name.as_bytes()).unwrap();
         Ok(result)
     }
 
-    fn total_len(&self) -> usize {
-        self.data.buffer().len() + self.data.get_ref().total_in() as usize
+    pub fn push(&mut self, name: &str) -> NameRefId {
+        self.try_push(name).unwrap()
+    }
+
+    pub fn total_len(&self) -> usize {
+        self.pos
     }
+
+    fn names(&self) -> &str {
+        //compressed data is only in memory, it can't fail to be decompressed
+        self.names
+            .get_or_init(|| inflate(&self.data, self.pos).expect("Deflated names are corrupted"))
+    }
+}
+
+/// Sync flush of all pushed names, and decompression of whole stream.
+fn inflate(data: &RefCell<BufWriter<DeflateEncoder<Vec<u8>>>>, size: usize) -> io::Result<String> {
+    let mut data = data.borrow_mut();
+    data.flush()?;
+    inflate_bytes(data.get_ref().get_ref(), size)
+}
+
+/// Decompress stream of given size, stream doesn't have to be finished.
+fn inflate_bytes(compressed: &[u8], size: usize) -> io::Result<String> {
+    let corrupted = || Error::new(ErrorKind::InvalidData, "Deflated names are corrupted");
+    let mut inflate = Decompress::new(false);
+    let mut bytes = Vec::with_capacity(size);
+    while bytes.len() < size {
+        let before = (inflate.total_in(), bytes.len());
+        let input = compressed.get(inflate.total_in() as usize..).ok_or_else(corrupted)?;
+        inflate
+            .decompress_vec(input, &mut bytes, FlushDecompress::Sync)
+            .map_err(|_| corrupted())?;
+        if (inflate.total_in(), bytes.len()) == before {
+            return Err(corrupted());
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| Error::new(ErrorKind::InvalidData, "Names are not UTF-8"))
 }
 
-pub struct WithCollected<'a, I, N: NamesStorage> {
-    parent: &'a mut N,
-    it: I,
+impl MeasureMemory for FlatedFileNames {
+    fn memory_usage(&self) -> usize {
+        let data = self.data.borrow();
+        data.capacity() + data.get_ref().get_ref().capacity() + self.tree.memory_usage() + self.names.get().map_or(0, |s| s.capacity())
+    }
 }
 
-impl<'a, I, N> Iterator for WithCollected<'a, I, N>
-where
-    I: Iterator,
-    N: NamesStorage,
-    I::Item: AsRef<str>,
-{
-    type Item = NameRefId;
-    fn next(&mut self) -> Option<Self::Item> {
-        let elem = self.it.next()?;
-        Some(self.parent.push(elem.as_ref()))
+/// Tree of deflated names, see [`FileNames`].
+impl NameTable for FlatedFileNames {
+    type Index = NameRefId;
+
+    fn try_push_os(&mut self, parent: Option<NameRefId>, name: &OsStr) -> io::Result<Option<NameRefId>> {
+        let name = utf8_name(name)?;
+        if name.is_empty() {
+            return Ok(parent);
+        }
+        let parent = self.tree.parent_ordinal(parent)?;
+        let id = self.try_push(name)?;
+        self.tree.entries.push((id, parent));
+        Ok(Some(id))
+    }
+
+    fn reverse_follow(&self, at: NameRefId) -> (BungeeName<'_>, Option<NameRefId>) {
+        (name_in(self.names(), at), self.tree.parent_of(at))
+    }
+
+    fn reverse_skip(&self, at: NameRefId) -> Option<NameRefId> {
+        self.tree.skip(at)
     }
 
-    fn size_hint(&self) -> (usize, Option<usize>) {
-        self.it.size_hint()
+    fn last_index(&self) -> Option<NameRefId> {
+        self.tree.last()
+    }
+
+    /// Names are written deflated, followed by tree entries.
+    fn write_table<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        let mut data = self.data.borrow_mut();
+        data.flush()?;
+        let compressed = data.get_ref().get_ref();
+        write.write_all(&(self.pos as u64).to_le_bytes())?;
+        write.write_all(&(compressed.len() as u64).to_le_bytes())?;
+        write.write_all(compressed)?;
+        self.tree.write(write)
+    }
+
+    /// Names are compressed again, as deflate stream can't be continued.
+    fn read_table<R: Read>(read: &mut R) -> io::Result<Self> {
+        let size = read_u64(read)?;
+        if size > u32::MAX as u64 {
+            return Err(Error::new(ErrorKind::Unsupported, "Names table is too big"));
+        }
+        let string = inflate_bytes(&read_table_bytes(read, u32::MAX as _)?, size as usize)?;
+        let tree = NameTree::read(read, &string)?;
+        let mut names = Self::default();
+        names.data.get_mut().write_all(string.as_bytes())?;
+        names.pos = string.len();
+        names.tree = tree;
+        names.names = OnceCell::from(string);
+        Ok(names)
     }
 }
@@ -1,6 +1,5 @@
 use crossbeam::channel::{bounded, Receiver, Sender};
 use std::any::Any;
-use std::fs::File;
 use std::iter::repeat_with;
 use std::mem::size_of_val;
 use std::path::Path;
@@ -14,8 +13,9 @@ use std::{
     sync::Arc,
 };
 
-use crate::hasher::{Consumer, DataChunk, HashArray, HashEntry};
+use crate::hasher::{Consumer, DataChunk, HardLinks, HashArray, HashEntry};
 use crate::utils::{AveragePerTick, LendingStack, MeasureMemory};
+use crate::vfs::{FileSystem, RealFs};
 use crossbeam::queue::ArrayQueue;
 use digest::{Digest, FixedOutputReset};
 use generic_array::GenericArray;
@@ -42,6 +42,8 @@ struct InnerConfig {
     permits: Arc<Permits>,
     max_permits: usize,
     read_bytes: Arc<AveragePerTick>,
+    hard_links: Option<Arc<HardLinks>>,
+    fs: Arc<dyn FileSystem>,
     chan_bound: usize,
     chunk_size: usize, //init chunk size
 }
@@ -68,6 +70,10 @@ pub struct RunnerConfig {
     pub buffer_chunk_size: usize,
     pub max_buffer_chunks: usize,
     pub max_buffer_chunks_per_file: usize,
+    /// When set, files with many hard links are read only once, other links are recorded in tracker and skipped.
+    pub hard_links: Option<Arc<HardLinks>>,
+    /// File system to read files from, real file system is used when not set.
+    pub fs: Option<Arc<dyn FileSystem>>,
 }
 
 // todo, checking at runtime if file is on hdd or ssd
@@ -95,6 +101,8 @@ impl RunnerConfig {
             buffer_chunk_size: 1024 * 256,
             max_buffer_chunks: 1024,
             max_buffer_chunks_per_file: 32,
+            hard_links: None,
+            fs: None,
         }
     }
     pub fn hdd(mut self) -> Self {
@@ -139,6 +147,8 @@ impl ScanRunner {
             chan_bound: cfg.max_buffer_chunks_per_file,
             flag: AtomicBool::new(true),
             read_bytes: cfg.read_bytes_stats.unwrap_or_default(),
+            hard_links: cfg.hard_links,
+            fs: cfg.fs.unwrap_or_else(|| Arc::new(RealFs)),
             permits: Arc::new(Permits::new(cfg.permits)),
             max_permits: cfg.permits,
             data_chunks: LendingStack::new(repeat_with(ChunkData::zero).take(cfg.max_buffer_chunks.max(1)).collect()),
@@ -188,6 +198,16 @@ impl ScanRunner {
             let Some(file) = cfg.iter.next() else {
                 break;
             };
+            if let Some(links) = &cfg.c.hard_links {
+                match cfg.c.fs.linked_id(&file).map(|id| id.and_then(|id| links.register_id(id, &file))) {
+                    Ok(Some(_)) => continue, //content is already scheduled by other link
+                    Ok(None) => {}
+                    Err(err) => {
+                        cfg.consumer.on_error(err, &file);
+                        continue;
+                    }
+                }
+            }
             let permit = cfg.c.permits.clone();
             permit.wait_for_permit();
 
@@ -197,16 +217,22 @@ impl ScanRunner {
             let stat = cfg.c.read_bytes.clone();
             let file2 = file.clone();
             let consumer = cfg.consumer.clone();
+            let fs = cfg.c.fs.clone();
             cfg.c.reader_pool.spawn_fifo(move || {
-                let res = Self::read_file(&file, supply, tx, size, stat);
+                let res = Self::read_file(&*fs, &file, supply, &tx, size, stat);
                 if let Err(err) = res {
                     consumer.on_error(err, &file);
                 }
+                //release consumer before closing channel, so that it's not used after runner finishes
+                drop(consumer);
+                drop(tx);
             });
             let consumer = cfg.consumer.clone();
             let recycle = cfg.c.data_chunks.clone();
             cfg.c.worker_pool.spawn_fifo(move || {
-                Self::process_file(file2, rx, recycle, &*consumer, permit);
+                Self::process_file(file2, rx, recycle, &*consumer);
+                drop(consumer);
+                permit.add_permit();
             });
         }
         //wait for all permits to finish
@@ -214,13 +240,14 @@ impl ScanRunner {
     }
 
     fn read_file(
+        fs: &dyn FileSystem,
         path: &Path,
         supply: LendingStack<ChunkData>,
-        dout: Sender<ChunkData>,
+        dout: &Sender<ChunkData>,
         chunk_size: usize,
         stats: Arc<AveragePerTick>,
     ) -> io::Result<()> {
-        let mut file = File::open(path)?;
+        let mut file = fs.open(path)?;
         //todo handle `too mutch open files` error
         loop {
             let mut chunk = supply.lend();
@@ -238,7 +265,7 @@ impl ScanRunner {
             }
         }
     }
-    fn process_file<C>(path: PathBuf, din: Receiver<ChunkData>, recycle: LendingStack<ChunkData>, consumer: &C, signal: Arc<Permits>)
+    fn process_file<C>(path: PathBuf, din: Receiver<ChunkData>, recycle: LendingStack<ChunkData>, consumer: &C)
     where
         C: Consumer,
     {
@@ -250,7 +277,6 @@ impl ScanRunner {
             recycle.give_back(chunk);
         }
         consumer.finish_consume(name, hasher);
-        signal.add_permit();
     }
 }
 
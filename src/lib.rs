@@ -8,6 +8,7 @@ pub mod file;
 mod hasher;
 mod store;
 pub mod utils;
+pub mod vfs;
 
 pub use consts::*;
 pub use hasher::*;
@@ -0,0 +1,73 @@
+use crate::file::chunks::{HashesChunk, SortOrder};
+use crate::{DataEntry, HashArray};
+use std::iter::FusedIterator;
+
+/// Entries of hashes chunk in name order. Chunks sorted by name are used as they are, other ones are indexed first,
+/// so lookups and merges by name work for chunks sorted by data too.
+pub struct EntriesByName<'a> {
+    hashes: &'a HashesChunk,
+    index: Option<Vec<&'a DataEntry>>,
+}
+
+impl<'a> EntriesByName<'a> {
+    pub fn new(hashes: &'a HashesChunk) -> Self {
+        let index = (hashes.sort != SortOrder::SortedByName).then(|| {
+            let mut index = hashes.data.iter().collect::<Vec<_>>();
+            index.sort_unstable_by_key(|e| e.id);
+            index
+        });
+        Self { hashes, index }
+    }
+
+    pub fn get(&self, id: &HashArray<32>) -> Option<&'a DataEntry> {
+        match &self.index {
+            Some(index) => index.binary_search_by_key(id, |e| e.id).ok().map(|i| index[i]),
+            None => {
+                let data = &self.hashes.data;
+                data.binary_search_by_key(id, |e| e.id).ok().map(|i| &data[i])
+            }
+        }
+    }
+
+    /// All entries sorted by name hash.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &'a DataEntry> + FusedIterator + '_ {
+        let (sorted, index): (&'a [DataEntry], &[&'a DataEntry]) = match &self.index {
+            Some(index) => (&[], index),
+            None => (&self.hashes.data, &[]),
+        };
+        sorted.iter().chain(index.iter().copied())
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.data.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_by_name() {
+        let entries = [("03", "01"), ("01", "02"), ("02", "00")].map(|(id, data)| DataEntry {
+            id: HashArray::parse_fill_zero(id),
+            data: HashArray::parse_fill_zero(data),
+        });
+        let mut chunk = HashesChunk::new_sha256(entries.to_vec(), false);
+        chunk.sort_by_data();
+        let by_name = EntriesByName::new(&chunk);
+        let ids = by_name.iter().map(|e| e.id).collect::<Vec<_>>();
+        assert_eq!(ids, ["01", "02", "03"].map(HashArray::parse_fill_zero));
+        assert_eq!(by_name.get(&HashArray::parse_fill_zero("03")), Some(&entries[0]));
+        assert_eq!(by_name.get(&HashArray::parse_fill_zero("04")), None);
+
+        chunk.sort();
+        let by_name = EntriesByName::new(&chunk);
+        assert!(by_name.iter().map(|e| e.id).eq(ids));
+        assert_eq!(by_name.get(&HashArray::parse_fill_zero("01")), Some(&entries[1]));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::file::chunks::{HashesChunk, NamesChunk};
+use crate::file::Snapshot;
+use crate::store::name_paths;
+use crate::{HashArray, PathIdentity};
+use digest::consts::U32;
+use digest::Digest;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+/// Copy of content on one source.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CatalogueFile {
+    /// Index of source in order of adding to [`Catalogue`]
+    pub source: usize,
+    pub id: HashArray<32>,
+    /// Path resolved through names chunk of source
+    pub path: Option<PathBuf>,
+}
+
+/// Name present on more sources with different content.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DifferingFile {
+    pub id: HashArray<32>,
+    pub path: Option<PathBuf>,
+    /// Source index and content for every source that has the name, in order of sources
+    pub copies: Vec<(usize, HashArray<32>)>,
+}
+
+/// Union of snapshots from many sources keyed by content, sources need root relative names hashed with the same
+/// identity and prefix, so that the same file has the same name hash on every source.
+#[derive(Default)]
+pub struct Catalogue {
+    labels: Vec<String>,
+    /// Identity and prefix id of the first source
+    naming: Option<(PathIdentity, Option<u64>)>,
+    by_data: BTreeMap<HashArray<32>, Vec<CatalogueFile>>,
+    by_id: HashMap<HashArray<32>, NameCopies>,
+}
+
+/// Content of one name on every source that has it.
+#[derive(Default)]
+struct NameCopies {
+    path: Option<PathBuf>,
+    copies: Vec<(usize, HashArray<32>)>,
+}
+
+impl Catalogue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_snapshot<D: Digest<OutputSize = U32>>(
+        &mut self,
+        label: impl Into<String>,
+        snapshot: &Snapshot,
+        prefix: &str,
+    ) -> io::Result<&mut Self> {
+        self.add_source::<D>(label, &snapshot.hashes, &snapshot.names, prefix)
+    }
+
+    /// Add hashes of one source, names chunk is used to resolve paths and may be empty. Names need to be root relative
+    /// and derived with given prefix, with the same identity and prefix as names of sources added before. Keyed
+    /// hashes can't be matched with other sources, so they are rejected.
+    pub fn add_source<D: Digest<OutputSize = U32>>(
+        &mut self,
+        label: impl Into<String>,
+        hashes: &HashesChunk,
+        names: &NamesChunk,
+        prefix: &str,
+    ) -> io::Result<&mut Self> {
+        if !hashes.root_relative {
+            return Err(Error::new(ErrorKind::InvalidInput, "Catalogue source has no root relative names"));
+        }
+        if hashes.key_id.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Catalogue source was hashed with key"));
+        }
+        if !hashes.matches_prefix(prefix) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Catalogue source names were hashed with other prefix",
+            ));
+        }
+        let naming = (hashes.identity, hashes.prefix_id);
+        if *self.naming.get_or_insert(naming) != naming {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Catalogue source names were hashed with other identity or prefix than previous sources",
+            ));
+        }
+        let source = self.labels.len();
+        self.labels.push(label.into());
+        let mut paths = name_paths::<D>(names, hashes.identity, prefix).collect::<HashMap<_, _>>();
+        for entry in &hashes.data {
+            let path = paths.remove(&entry.id);
+            let name = self.by_id.entry(entry.id).or_default();
+            if name.path.is_none() {
+                name.path.clone_from(&path);
+            }
+            name.copies.push((source, entry.data));
+            self.by_data.entry(entry.data).or_default().push(CatalogueFile {
+                source,
+                id: entry.id,
+                path,
+            });
+        }
+        Ok(self)
+    }
+
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    pub fn label(&self, source: usize) -> Option<&str> {
+        self.labels.get(source).map(|l| l.as_str())
+    }
+
+    /// All files with given content.
+    pub fn get(&self, data: &HashArray<32>) -> Option<&[CatalogueFile]> {
+        self.by_data.get(data).map(|v| v.as_slice())
+    }
+
+    /// All content with its files, sorted by content hash.
+    pub fn entries(&self) -> impl Iterator<Item = (&HashArray<32>, &[CatalogueFile])> + '_ {
+        self.by_data.iter().map(|(k, v)| (k, v.as_slice()))
+    }
+
+    /// Sources that have a copy of content, sorted and without repetition.
+    pub fn sources_of(&self, data: &HashArray<32>) -> Vec<usize> {
+        let mut sources = self.get(data).unwrap_or_default().iter().map(|f| f.source).collect::<Vec<_>>();
+        sources.dedup();
+        sources
+    }
+
+    /// Content present only on one source, with that source.
+    pub fn single_source(&self) -> impl Iterator<Item = (usize, &HashArray<32>, &[CatalogueFile])> + '_ {
+        self.entries().filter_map(|(data, files)| {
+            let source = files.first()?.source;
+            files.iter().all(|f| f.source == source).then_some((source, data, files))
+        })
+    }
+
+    /// Content that is present on some sources, but not on the given one.
+    pub fn missing_on(&self, source: usize) -> impl Iterator<Item = (&HashArray<32>, &[CatalogueFile])> + '_ {
+        self.entries().filter(move |(_, files)| files.iter().all(|f| f.source != source))
+    }
+
+    /// Names whose copies have different content on some sources, sorted by path.
+    pub fn differing(&self) -> Vec<DifferingFile> {
+        let mut result = self
+            .by_id
+            .iter()
+            .filter(|(_, name)| name.copies.iter().any(|(_, data)| *data != name.copies[0].1))
+            .map(|(id, name)| DifferingFile {
+                id: *id,
+                path: name.path.clone(),
+                copies: name.copies.clone(),
+            })
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| a.path.cmp(&b.path).then(a.id.cmp(&b.id)));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::snapshot_of;
+    use crate::UnicodeForm;
+    use sha2::Sha256;
+    use std::path::Path;
+
+    #[test]
+    fn test_snapshot_catalogue() {
+        let alpha = snapshot_of(PathIdentity::EXACT, &[("src/main", b"main"), ("notes", b"alpha notes")]);
+        let beta = snapshot_of(PathIdentity::EXACT, &[("src/main", b"main"), ("notes", b"beta notes")]);
+        let gamma = snapshot_of(PathIdentity::EXACT, &[("src/main", b"main"), ("src/copy", b"main")]);
+
+        let mut catalogue = Catalogue::new();
+        catalogue
+            .add_snapshot::<Sha256>("alpha", &alpha, "")
+            .unwrap()
+            .add_snapshot::<Sha256>("beta", &beta, "")
+            .unwrap()
+            .add_snapshot::<Sha256>("gamma", &gamma, "")
+            .unwrap();
+        assert_eq!(catalogue.labels(), ["alpha", "beta", "gamma"]);
+        assert_eq!(catalogue.label(3), None);
+        assert_eq!(catalogue.entries().count(), 3);
+
+        let main = HashArray::new(Sha256::digest(b"main").into());
+        assert_eq!(catalogue.sources_of(&main), [0, 1, 2]);
+        assert_eq!(catalogue.get(&main).unwrap().len(), 4);
+
+        let mut single = catalogue
+            .single_source()
+            .map(|(source, _, files)| (catalogue.label(source).unwrap(), files[0].path.clone().unwrap()))
+            .collect::<Vec<_>>();
+        single.sort();
+        assert_eq!(single, [("alpha", PathBuf::from("notes")), ("beta", PathBuf::from("notes"))]);
+        assert_eq!(catalogue.missing_on(2).count(), 2);
+
+        let differing = catalogue.differing();
+        assert_eq!(differing.len(), 1);
+        assert_eq!(differing[0].path.as_deref(), Some(Path::new("notes")));
+        assert_eq!(differing[0].copies.iter().map(|c| c.0).collect::<Vec<_>>(), [0, 1]);
+    }
+
+    #[test]
+    fn test_reject_other_naming() {
+        let files: [(&str, &[u8]); 1] = [("notes", b"notes")];
+        let mut catalogue = Catalogue::new();
+        catalogue
+            .add_snapshot::<Sha256>("alpha", &snapshot_of(PathIdentity::EXACT, &files), "")
+            .unwrap();
+
+        let folded = snapshot_of(PathIdentity::new(UnicodeForm::Nfc, true), &files);
+        assert!(catalogue.add_snapshot::<Sha256>("folded", &folded, "").is_err());
+        let alpha = snapshot_of(PathIdentity::EXACT, &files);
+        assert!(catalogue.add_snapshot::<Sha256>("prefixed", &alpha, "backup").is_err());
+        let mut absolute = snapshot_of(PathIdentity::EXACT, &files);
+        absolute.hashes.root_relative = false;
+        assert!(catalogue.add_snapshot::<Sha256>("absolute", &absolute, "").is_err());
+        let mut keyed = snapshot_of(PathIdentity::EXACT, &files);
+        keyed.hashes.key_id = Some(7);
+        let err = catalogue.add_snapshot::<Sha256>("keyed", &keyed, "").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(catalogue.labels(), ["alpha"]);
+    }
+}
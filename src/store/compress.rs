@@ -22,13 +22,13 @@ pub fn compress_sorted_entries(
     writer.write_all(end.data.get_ref())?;
     //then write all other entries
 
-    let count = count.checked_sub(1).ok_or(Error::new(ErrorKind::Other, "Invalid count field"))?;
+    let count = count.checked_sub(1).ok_or(Error::other("Invalid count field"))?;
     //calculate average numeric difference
     let span_num = by(&end).wrapping_sub(*by(&start));
     //divide span by count
     let (average_span, _) = span_num
         .checked_div_rem(count)
-        .ok_or(Error::new(ErrorKind::Other, "invalid count field - value is too low"))?;
+        .ok_or(Error::other("invalid count field - value is too low"))?;
 
     println!("Diff num: {span_num:?}");
     println!("avg span: {average_span:?}");
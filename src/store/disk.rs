@@ -0,0 +1,368 @@
+use crate::file::chunks::{HashesHeader, SortOrder};
+use crate::store::HashStore;
+use crate::utils::merge_sorted_all;
+use crate::DataEntry;
+use std::cell::RefCell;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::iter::{empty, Empty};
+use std::mem::size_of;
+use std::path::PathBuf;
+
+const ENTRY_SIZE: u64 = size_of::<DataEntry>() as u64;
+
+/// Sorts entries that don't fit in memory, entries are collected into runs of limited length, every full run is
+/// sorted and spilled to temporary file, and all runs are merged into single sorted file at the end.
+pub struct ExternalSorter {
+    run_len: usize,
+    buffer: Vec<DataEntry>,
+    runs: Vec<File>,
+    temp_dir: Option<PathBuf>,
+}
+
+impl ExternalSorter {
+    /// 16M entries, 1GiB of memory
+    pub const DEFAULT_RUN_LEN: usize = 16 * 1024 * 1024;
+
+    pub fn new(run_len: usize) -> Self {
+        Self {
+            run_len: run_len.max(1),
+            buffer: Vec::new(),
+            runs: Vec::new(),
+            temp_dir: None,
+        }
+    }
+
+    /// Directory for temporary files, system temp directory is used by default.
+    pub fn with_temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.temp_dir = Some(dir.into());
+        self
+    }
+
+    fn temp_file(&self) -> io::Result<File> {
+        match &self.temp_dir {
+            Some(dir) => tempfile::tempfile_in(dir),
+            None => tempfile::tempfile(),
+        }
+    }
+
+    pub fn push(&mut self, entry: DataEntry) -> io::Result<()> {
+        if self.buffer.capacity() == 0 {
+            self.buffer.reserve_exact(self.run_len);
+        }
+        self.buffer.push(entry);
+        if self.buffer.len() >= self.run_len {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    pub fn extend<I: IntoIterator<Item = DataEntry>>(&mut self, iter: I) -> io::Result<()> {
+        iter.into_iter().try_for_each(|e| self.push(e))
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.buffer.sort_unstable();
+        let mut file = BufWriter::new(self.temp_file()?);
+        self.buffer.iter().try_for_each(|e| file.write_all(e.as_buf()))?;
+        let mut file = file.into_inner()?;
+        file.rewind()?;
+        self.runs.push(file);
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Merge all runs into sorted store, duplicated entries are kept.
+    pub fn finish(mut self) -> io::Result<DiskHashStore> {
+        if self.runs.is_empty() {
+            //everything fits in single run, there is nothing to merge
+            self.spill()?;
+            let count = self.runs[0].metadata()?.len() / ENTRY_SIZE;
+            return Ok(DiskHashStore {
+                file: self.runs.pop().unwrap(),
+                count,
+            });
+        }
+        if !self.buffer.is_empty() {
+            self.spill()?;
+        }
+        let error = RefCell::new(None);
+        let runs = std::mem::take(&mut self.runs)
+            .into_iter()
+            .map(|f| read_entries(BufReader::new(f), &error).fuse())
+            .collect::<Vec<_>>();
+        let mut out = BufWriter::new(self.temp_file()?);
+        let mut count = 0u64;
+        for entry in merge_sorted_all(runs, DataEntry::cmp) {
+            out.write_all(entry.as_buf())?;
+            count += 1;
+        }
+        if let Some(err) = error.into_inner() {
+            return Err(err);
+        }
+        Ok(DiskHashStore {
+            file: out.into_inner()?,
+            count,
+        })
+    }
+}
+
+/// Iterator over entries of reader, first error is saved into `error` and ends iteration.
+fn read_entries<'a, R: Read + 'a>(mut read: R, error: &'a RefCell<Option<io::Error>>) -> impl Iterator<Item = DataEntry> + 'a {
+    std::iter::from_fn(move || {
+        let mut entry = DataEntry::zero();
+        match read.read_exact(entry.as_mut_buf()) {
+            Ok(()) => Some(entry),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => None,
+            Err(err) => {
+                error.borrow_mut().get_or_insert(err);
+                None
+            }
+        }
+    })
+}
+
+/// Hash store kept in temporary file, entries are sorted by id. File is removed when store is dropped.
+pub struct DiskHashStore {
+    file: File,
+    count: u64,
+}
+
+impl DiskHashStore {
+    /// Sort any number of entries, using at most `run_len` entries of memory.
+    pub fn sort_from<I: IntoIterator<Item = DataEntry>>(iter: I, run_len: usize) -> io::Result<Self> {
+        let mut sorter = ExternalSorter::new(run_len);
+        sorter.extend(iter)?;
+        sorter.finish()
+    }
+
+    /// Sort entries of hashes chunk streamed from reader, positioned at chunk header.
+    pub fn from_chunk<R: Read>(read: &mut R, run_len: usize) -> io::Result<Self> {
+        let header = HashesHeader::read(read)?;
+        let mut sorter = ExternalSorter::new(run_len);
+        let error = RefCell::new(None);
+        let bytes = header
+            .size()
+            .checked_mul(ENTRY_SIZE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Hashes chunk size is too big"))?;
+        sorter.extend(read_entries(read.take(bytes), &error))?;
+        if let Some(err) = error.into_inner() {
+            return Err(err);
+        }
+        let store = sorter.finish()?;
+        if store.count != header.size() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "Hashes chunk is shorter than declared",
+            ));
+        }
+        Ok(store)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Write store as hashes chunk sorted by name, entries are streamed, so the chunk can be of any size.
+    pub fn write_chunk<W: Write>(&self, header: HashesHeader, write: &mut W) -> io::Result<()> {
+        let header = header.with_entries(self.count, SortOrder::SortedByName);
+        write.write_all(header.to_array().get_ref())?;
+        let mut read = BufReader::new(&self.file);
+        read.seek(SeekFrom::Start(0))?;
+        let copied = io::copy(&mut read.take(self.count * ENTRY_SIZE), write)?;
+        if copied != self.count * ENTRY_SIZE {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Store file was truncated"));
+        }
+        Ok(())
+    }
+}
+
+/// Iterator over entries of store file, reads from both ends use separate buffers. When reading fails, iteration
+/// ends and error can be taken with [`DiskIter::take_error`].
+pub struct DiskIter<'a> {
+    file: &'a File,
+    front: u64,
+    back: u64,
+    front_buf: Vec<DataEntry>,
+    back_buf: Vec<DataEntry>,
+    error: Option<io::Error>,
+}
+
+impl<'a> DiskIter<'a> {
+    const BUFFER_LEN: u64 = 1024;
+
+    fn new(file: &'a File, count: u64) -> Self {
+        Self {
+            file,
+            front: 0,
+            back: count,
+            front_buf: Vec::new(),
+            back_buf: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn read_range(&mut self, start: u64, len: u64) -> Option<Vec<DataEntry>> {
+        let mut entries = vec![DataEntry::zero(); len as usize];
+        let res = (|| {
+            let mut file = self.file;
+            file.seek(SeekFrom::Start(start * ENTRY_SIZE))?;
+            entries.iter_mut().try_for_each(|e| file.read_exact(e.as_mut_buf()))
+        })();
+        match res {
+            Ok(()) => Some(entries),
+            Err(err) => {
+                self.error = Some(err);
+                self.front = self.back;
+                None
+            }
+        }
+    }
+}
+
+impl Iterator for DiskIter<'_> {
+    type Item = DataEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front_buf.is_empty() {
+            if self.front < self.back {
+                let len = (self.back - self.front).min(Self::BUFFER_LEN);
+                let mut buf = self.read_range(self.front, len)?;
+                self.front += len;
+                buf.reverse();
+                self.front_buf = buf;
+            } else {
+                //remaining entries were already read by back buffer
+                return (!self.back_buf.is_empty()).then(|| self.back_buf.remove(0));
+            }
+        }
+        self.front_buf.pop()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.back - self.front) as usize + self.front_buf.len() + self.back_buf.len();
+        (len, Some(len))
+    }
+}
+
+impl DoubleEndedIterator for DiskIter<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.back_buf.is_empty() {
+            if self.front < self.back {
+                let len = (self.back - self.front).min(Self::BUFFER_LEN);
+                self.back_buf = self.read_range(self.back - len, len)?;
+                self.back -= len;
+            } else {
+                return (!self.front_buf.is_empty()).then(|| self.front_buf.remove(0));
+            }
+        }
+        self.back_buf.pop()
+    }
+}
+
+impl HashStore for DiskHashStore {
+    type OwnIter<'a> = DiskIter<'a>;
+    type RefIter<'a> = Empty<&'a DataEntry>;
+
+    /// Entries are not kept in memory, so references can't be returned, use [`HashStore::sorted_iter`].
+    fn sorted_ref_iter(&self) -> Self::RefIter<'_> {
+        empty()
+    }
+
+    fn sorted_iter(&self) -> Self::OwnIter<'_> {
+        DiskIter::new(&self.file, self.count)
+    }
+
+    fn is_owned_only(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::chunks::HashesChunk;
+    use rand::prelude::*;
+
+    fn random_entries(count: usize) -> Vec<DataEntry> {
+        let mut rng = StdRng::seed_from_u64(77);
+        (0..count)
+            .map(|_| {
+                let mut e = DataEntry::zero();
+                rng.fill_bytes(e.as_mut_buf());
+                e
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_external_sort() {
+        let entries = random_entries(5000);
+        let mut sorted = entries.clone();
+        sorted.sort_unstable();
+        for run_len in [7, 1000, 10000] {
+            let store = DiskHashStore::sort_from(entries.iter().copied(), run_len).unwrap();
+            assert_eq!(store.len(), 5000);
+            assert_eq!(store.sorted_iter().collect::<Vec<_>>(), sorted);
+            assert!(store.sorted_iter().rev().eq(sorted.iter().rev().copied()));
+        }
+        let store = DiskHashStore::sort_from(std::iter::empty(), 10).unwrap();
+        assert!(store.is_empty());
+        assert_eq!(store.sorted_iter().next(), None);
+    }
+
+    #[test]
+    fn test_double_ended() {
+        let entries = random_entries(2500);
+        let store = DiskHashStore::sort_from(entries, 100).unwrap();
+        let mut iter = store.sorted_iter();
+        let mut front = Vec::new();
+        let mut back = Vec::new();
+        loop {
+            match (iter.next(), iter.next_back()) {
+                (Some(a), Some(b)) => {
+                    front.push(a);
+                    back.push(b);
+                }
+                (Some(a), None) => front.push(a),
+                (None, _) => break,
+            }
+        }
+        front.extend(back.into_iter().rev());
+        assert_eq!(front, store.sorted_iter().collect::<Vec<_>>());
+        assert_eq!(front.len(), 2500);
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        let entries = random_entries(3000);
+        let mut chunk = HashesChunk::new_sha256(entries, false);
+        chunk.sort = SortOrder::Unordered;
+        chunk.prefix_id = Some(42);
+        chunk.key_id = Some(7);
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+
+        let store = DiskHashStore::from_chunk(&mut bytes.as_slice(), 500).unwrap();
+        let mut written = Vec::new();
+        store.write_chunk(chunk.header(), &mut written).unwrap();
+        chunk.sort();
+        let restored = HashesChunk::read(&mut written.as_slice()).unwrap();
+        assert!(restored == chunk);
+        assert_eq!(restored.prefix_id, Some(42));
+        assert_eq!(restored.key_id, Some(7));
+        assert!(DiskHashStore::from_chunk(&mut &bytes[..bytes.len() - 1], 500).is_err());
+        bytes[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        let err = DiskHashStore::from_chunk(&mut bytes.as_slice(), 500).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::file::chunks::{HashesChunk, SizesChunk};
+use crate::file::Snapshot;
+use crate::store::name_paths;
+use crate::utils::{escape_name, os_str_bytes};
+use crate::{HashArray, EMPTY_SHA256};
+use digest::consts::U32;
+use digest::Digest;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::{Error, ErrorKind, Write};
+use std::path::PathBuf;
+
+/// Single file of duplicate group.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DupeFile {
+    /// Index of source in order of adding to [`DupeFinder`]
+    pub source: usize,
+    pub id: HashArray<32>,
+    /// Path resolved through names chunk, when source has names
+    pub path: Option<PathBuf>,
+}
+
+/// Files sharing the same content.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct DupeGroup {
+    pub data: HashArray<32>,
+    /// Size of content, when any source knows it
+    pub size: Option<u64>,
+    /// Number of separate copies of content, hard links to the same content are counted once
+    pub copies: usize,
+    pub files: Vec<DupeFile>,
+}
+
+impl DupeGroup {
+    /// Bytes that could be reclaimed by keeping only one copy.
+    pub fn wasted_bytes(&self) -> u64 {
+        self.size.unwrap_or(0).saturating_mul(self.copies.saturating_sub(1) as u64)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct DupesReport {
+    /// Groups sorted by wasted bytes, largest first
+    pub groups: Vec<DupeGroup>,
+    /// Number of empty files, they are never reported as duplicates
+    pub empty_files: usize,
+}
+
+struct Source<'a> {
+    hashes: &'a HashesChunk,
+    sizes: Option<&'a SizesChunk>,
+    paths: HashMap<HashArray<32>, PathBuf>,
+    /// Name to index of its link group
+    links: HashMap<HashArray<32>, usize>,
+}
+
+/// Finds files with the same content in one or more snapshots, without reading any file data.
+#[derive(Default)]
+pub struct DupeFinder<'a> {
+    sources: Vec<Source<'a>>,
+}
+
+impl<'a> DupeFinder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add snapshot, its names, sizes and hard links are used to describe duplicates. Snapshots hashed with key are
+    /// rejected, as their contents can't be compared with other sources.
+    pub fn add_snapshot<D: Digest<OutputSize = U32>>(&mut self, snapshot: &'a Snapshot, prefix: &str) -> io::Result<&mut Self> {
+        if snapshot.hashes.key_id.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Snapshot of duplicates was hashed with key"));
+        }
+        let paths = name_paths::<D>(&snapshot.names, snapshot.hashes.identity, prefix).collect();
+        let links = snapshot
+            .links
+            .groups
+            .iter()
+            .enumerate()
+            .flat_map(|(i, g)| g.iter().map(move |id| (*id, i)))
+            .collect();
+        self.sources.push(Source {
+            hashes: &snapshot.hashes,
+            sizes: Some(&snapshot.sizes),
+            paths,
+            links,
+        });
+        Ok(self)
+    }
+
+    /// Add bare hashes chunk, duplicates from it will have no paths and sizes.
+    pub fn add_hashes(&mut self, hashes: &'a HashesChunk) -> &mut Self {
+        self.sources.push(Source {
+            hashes,
+            sizes: None,
+            paths: HashMap::new(),
+            links: HashMap::new(),
+        });
+        self
+    }
+
+    pub fn finish(&self) -> DupesReport {
+        let mut by_data: HashMap<HashArray<32>, Vec<(usize, HashArray<32>)>> = HashMap::new();
+        let mut report = DupesReport::default();
+        for (index, source) in self.sources.iter().enumerate() {
+            for e in &source.hashes.data {
+                let size = source.sizes.and_then(|s| s.get(&e.id));
+                if e.data == EMPTY_SHA256 || size == Some(0) {
+                    report.empty_files += 1;
+                    continue;
+                }
+                by_data.entry(e.data).or_default().push((index, e.id));
+            }
+        }
+
+        for (data, mut names) in by_data {
+            if names.len() < 2 {
+                continue;
+            }
+            names.sort_unstable();
+            names.dedup();
+            //hard links of the same group in the same source don't take additional space
+            let copies = names
+                .iter()
+                .map(|&(s, id)| (s, self.sources[s].links.get(&id).map_or(Err(id), |&g| Ok(g))))
+                .collect::<HashSet<_>>()
+                .len();
+            if copies < 2 {
+                continue;
+            }
+            let size = names.iter().find_map(|(s, id)| self.sources[*s].sizes?.get(id));
+            let mut files = names
+                .into_iter()
+                .map(|(source, id)| DupeFile {
+                    source,
+                    id,
+                    path: self.sources[source].paths.get(&id).cloned(),
+                })
+                .collect::<Vec<_>>();
+            files.sort_by(|a, b| (a.source, &a.path, a.id).cmp(&(b.source, &b.path, b.id)));
+            report.groups.push(DupeGroup { data, size, copies, files });
+        }
+        report
+            .groups
+            .sort_unstable_by(|a, b| b.wasted_bytes().cmp(&a.wasted_bytes()).then_with(|| a.data.cmp(&b.data)));
+        report
+    }
+}
+
+impl DupesReport {
+    pub fn wasted_bytes(&self) -> u64 {
+        self.groups.iter().map(|g| g.wasted_bytes()).sum()
+    }
+
+    /// Write report as CSV, one row for every file, files of the same group are in consecutive rows.
+    /// File name hash is written in place of path, when path is not known.
+    pub fn write_csv<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        writeln!(write, "wasted,size,copies,hash,source,path")?;
+        for g in &self.groups {
+            let size = g.size.map(|s| s.to_string()).unwrap_or_default();
+            for f in &g.files {
+                let path = match &f.path {
+                    Some(p) => escape_name(os_str_bytes(p.as_os_str())).into_owned(),
+                    None => format!("{:x}", f.id),
+                };
+                let path = if path.contains([',', '"', '\n', '\r']) {
+                    format!("\"{}\"", path.replace('"', "\"\""))
+                } else {
+                    path
+                };
+                writeln!(write, "{},{size},{},{:x},{},{path}", g.wasted_bytes(), g.copies, g.data, f.source)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::snapshot_of;
+    use crate::{HashEntry, PathIdentity};
+    use sha2::Sha256;
+
+    fn entry(id: &str, data: &str) -> HashEntry<32, 32> {
+        HashEntry {
+            id: HashArray::parse_fill_zero(id),
+            data: HashArray::parse_fill_zero(data),
+        }
+    }
+
+    #[test]
+    fn test_dupes_across_sources() {
+        let first = HashesChunk::new_sha256(vec![entry("01", "aa"), entry("02", "bb"), entry("03", "aa")], true);
+        let second = HashesChunk::new_sha256(
+            vec![
+                entry("01", "aa"),
+                entry("04", "cc"),
+                HashEntry {
+                    id: HashArray::parse_fill_zero("05"),
+                    data: EMPTY_SHA256,
+                },
+            ],
+            true,
+        );
+        let report = DupeFinder::new().add_hashes(&first).add_hashes(&second).finish();
+        assert_eq!(report.empty_files, 1);
+        assert_eq!(report.groups.len(), 1);
+        let group = &report.groups[0];
+        assert_eq!(group.copies, 3);
+        assert_eq!(group.size, None);
+        assert_eq!(group.wasted_bytes(), 0);
+        assert_eq!(
+            group.files.iter().map(|f| (f.source, f.id)).collect::<Vec<_>>(),
+            [(0, first.data[0].id), (0, first.data[2].id), (1, second.data[0].id)]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_dupes() {
+        let files: [(&str, &[u8]); 8] = [
+            ("a.txt", b"xx"),
+            ("b/a.txt", b"xx"),
+            ("b/c", b"yyy"),
+            ("d, \"quoted\"", b"yyy"),
+            ("e", b"yyy"),
+            ("empty", b""),
+            ("b/empty", b""),
+            ("unique", b"z"),
+        ];
+        let snapshot = snapshot_of(PathIdentity::EXACT, &files);
+        assert_eq!(snapshot.sizes.total_size(), 14);
+        let mut bytes = Vec::new();
+        snapshot.write(&mut bytes).unwrap();
+        let snapshot = Snapshot::read(&mut bytes.as_slice()).unwrap();
+
+        let report = DupeFinder::new().add_snapshot::<Sha256>(&snapshot, "").unwrap().finish();
+        let mut resorted = snapshot_of(PathIdentity::EXACT, &files);
+        resorted.hashes.sort_by_data();
+        assert_eq!(DupeFinder::new().add_snapshot::<Sha256>(&resorted, "").unwrap().finish(), report);
+        let unresolved = DupeFinder::new().add_snapshot::<Sha256>(&snapshot, "other").unwrap().finish();
+        resorted.hashes.key_id = Some(7);
+        assert!(DupeFinder::new().add_snapshot::<Sha256>(&resorted, "").is_err());
+        assert!(unresolved.groups.iter().flat_map(|g| &g.files).all(|f| f.path.is_none()));
+        assert_eq!(report.empty_files, 2);
+        assert_eq!(report.wasted_bytes(), 8);
+        let groups = report
+            .groups
+            .iter()
+            .map(|g| (g.size, g.copies, g.wasted_bytes()))
+            .collect::<Vec<_>>();
+        assert_eq!(groups, [(Some(3), 3, 6), (Some(2), 2, 2)]);
+
+        let mut csv = Vec::new();
+        report.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let rows = csv.lines().map(|l| l.rsplit_once(',').unwrap().1).collect::<Vec<_>>();
+        assert_eq!(csv.lines().count(), 6);
+        assert!(csv.lines().nth(1).unwrap().starts_with("6,3,3,"));
+        assert!(csv.contains(",\"d, \"\"quoted\"\"\"\n"));
+        assert_eq!(rows[4], "a.txt");
+        assert_eq!(rows[5], "b/a.txt");
+    }
+}
@@ -0,0 +1,276 @@
+use crate::file::chunks::HashesChunk;
+use crate::file::Snapshot;
+use crate::store::{name_paths, DiffType, EntriesByName};
+use crate::utils::merge_sorted_all;
+use crate::{DataEntry, HashArray};
+use digest::consts::U32;
+use digest::Digest;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Single change of file in history, `snapshot` is index of the first snapshot showing the change.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HistoryEvent {
+    pub snapshot: usize,
+    /// One of [`DiffType::Added`], [`DiffType::Changed`] or [`DiffType::Removed`]
+    pub kind: DiffType,
+    /// Content since this event, `None` when file vanished
+    pub data: Option<HashArray<32>>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct FileHistory {
+    pub id: HashArray<32>,
+    /// Path from the last snapshot containing file, when that snapshot has names
+    pub path: Option<PathBuf>,
+    pub events: Vec<HistoryEvent>,
+}
+
+impl FileHistory {
+    /// Content of file in given snapshot, `None` if file was not present there.
+    pub fn data_at(&self, snapshot: usize) -> Option<HashArray<32>> {
+        self.events.iter().take_while(|e| e.snapshot <= snapshot).last()?.data
+    }
+
+    /// Snapshots where file had given content, as ranges of snapshot indexes.
+    pub fn spans_of(&self, data: &HashArray<32>, snapshots: usize) -> Vec<std::ops::Range<usize>> {
+        let ends = self.events.iter().skip(1).map(|e| e.snapshot).chain([snapshots]);
+        self.events
+            .iter()
+            .zip(ends)
+            .filter(|(e, _)| e.data.as_ref() == Some(data))
+            .map(|(e, end)| e.snapshot..end)
+            .collect()
+    }
+}
+
+/// Per file history across many snapshots of the same tree, snapshots are expected in chronological order.
+pub struct History {
+    snapshots: usize,
+    /// Sorted by name hash
+    files: Vec<FileHistory>,
+    by_path: HashMap<PathBuf, usize>,
+    by_data: HashMap<HashArray<32>, Vec<usize>>,
+}
+
+impl History {
+    /// Build history of snapshots, paths are resolved through their names chunks.
+    pub fn build<D: Digest<OutputSize = U32>>(snapshots: &[&Snapshot], prefix: &str) -> Self {
+        let hashes = snapshots.iter().map(|s| &s.hashes).collect::<Vec<_>>();
+        let mut history = Self::from_hashes(&hashes);
+        for snapshot in snapshots.iter().rev() {
+            for (id, path) in name_paths::<D>(&snapshot.names, snapshot.hashes.identity, prefix) {
+                let Ok(at) = history.files.binary_search_by(|f| f.id.cmp(&id)) else {
+                    continue;
+                };
+                //snapshots are visited from the newest, so the first path found is the latest one
+                let file = &mut history.files[at];
+                if file.path.is_none() {
+                    history.by_path.insert(path.clone(), at);
+                    file.path = Some(path);
+                }
+            }
+        }
+        history
+    }
+
+    /// Build history from hashes only, files will have no paths.
+    pub fn from_hashes(hashes: &[&HashesChunk]) -> Self {
+        let entries = hashes.iter().map(|h| EntriesByName::new(h)).collect::<Vec<_>>();
+        let iters = entries
+            .iter()
+            .enumerate()
+            .map(|(i, h)| h.iter().map(move |e| (i, e)))
+            .collect::<Vec<_>>();
+        let merged = merge_sorted_all(iters, |a: &(usize, &DataEntry), b| a.1.id.cmp(&b.1.id).then(a.0.cmp(&b.0)));
+
+        let mut files: Vec<FileHistory> = Vec::new();
+        let mut last_seen = 0;
+        for (i, entry) in merged {
+            match files.last_mut() {
+                Some(file) if file.id == entry.id => {
+                    let prev = file.events.last().and_then(|e| e.data);
+                    if last_seen + 1 != i {
+                        file.events.push(HistoryEvent::removed(last_seen + 1));
+                        file.events.push(HistoryEvent::added(i, entry.data));
+                    } else if prev != Some(entry.data) {
+                        file.events.push(HistoryEvent {
+                            snapshot: i,
+                            kind: DiffType::Changed,
+                            data: Some(entry.data),
+                        });
+                    }
+                }
+                _ => {
+                    Self::finish_file(files.last_mut(), last_seen, hashes.len());
+                    files.push(FileHistory {
+                        id: entry.id,
+                        path: None,
+                        events: vec![HistoryEvent::added(i, entry.data)],
+                    });
+                }
+            }
+            last_seen = i;
+        }
+        Self::finish_file(files.last_mut(), last_seen, hashes.len());
+
+        let mut by_data = HashMap::<_, Vec<_>>::new();
+        for (at, file) in files.iter().enumerate() {
+            for data in file.events.iter().filter_map(|e| e.data) {
+                let list = by_data.entry(data).or_default();
+                if list.last() != Some(&at) {
+                    list.push(at);
+                }
+            }
+        }
+        Self {
+            snapshots: hashes.len(),
+            files,
+            by_path: HashMap::new(),
+            by_data,
+        }
+    }
+
+    fn finish_file(file: Option<&mut FileHistory>, last_seen: usize, snapshots: usize) {
+        if let Some(file) = file {
+            if last_seen + 1 < snapshots {
+                file.events.push(HistoryEvent::removed(last_seen + 1));
+            }
+        }
+    }
+
+    pub fn snapshots(&self) -> usize {
+        self.snapshots
+    }
+
+    pub fn files(&self) -> &[FileHistory] {
+        &self.files
+    }
+
+    pub fn get(&self, id: &HashArray<32>) -> Option<&FileHistory> {
+        let at = self.files.binary_search_by(|f| f.id.cmp(id)).ok()?;
+        Some(&self.files[at])
+    }
+
+    pub fn by_path(&self, path: &Path) -> Option<&FileHistory> {
+        Some(&self.files[*self.by_path.get(path)?])
+    }
+
+    /// All files that had given content at any time.
+    pub fn by_data(&self, data: &HashArray<32>) -> impl Iterator<Item = &FileHistory> + '_ {
+        self.by_data.get(data).into_iter().flatten().map(|&at| &self.files[at])
+    }
+}
+
+impl HistoryEvent {
+    fn added(snapshot: usize, data: HashArray<32>) -> Self {
+        Self {
+            snapshot,
+            kind: DiffType::Added,
+            data: Some(data),
+        }
+    }
+
+    fn removed(snapshot: usize) -> Self {
+        Self {
+            snapshot,
+            kind: DiffType::Removed,
+            data: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::snapshot_of;
+    use crate::{HashEntry, PathIdentity};
+    use sha2::Sha256;
+
+    fn mock_chunk(entries: &[(&str, &str)]) -> HashesChunk {
+        let mut data = entries
+            .iter()
+            .map(|(id, data)| HashEntry {
+                id: HashArray::parse_fill_zero(id),
+                data: HashArray::parse_fill_zero(data),
+            })
+            .collect::<Vec<_>>();
+        data.sort();
+        HashesChunk::new_sha256(data, true)
+    }
+
+    #[test]
+    fn test_history() {
+        let chunks = [
+            mock_chunk(&[("01", "11"), ("02", "12")]),
+            mock_chunk(&[("01", "11"), ("02", "22"), ("03", "13")]),
+            mock_chunk(&[("01", "11"), ("03", "13")]),
+            mock_chunk(&[("01", "21"), ("02", "22")]),
+        ];
+        let history = History::from_hashes(&chunks.iter().collect::<Vec<_>>());
+        assert_eq!(history.files().len(), 3);
+
+        let kinds = |id: &str| {
+            let file = history.get(&HashArray::parse_fill_zero(id)).unwrap();
+            file.events.iter().map(|e| (e.snapshot, e.kind)).collect::<Vec<_>>()
+        };
+        assert_eq!(kinds("01"), [(0, DiffType::Added), (3, DiffType::Changed)]);
+        assert_eq!(
+            kinds("02"),
+            [
+                (0, DiffType::Added),
+                (1, DiffType::Changed),
+                (2, DiffType::Removed),
+                (3, DiffType::Added)
+            ]
+        );
+        assert_eq!(kinds("03"), [(1, DiffType::Added), (3, DiffType::Removed)]);
+
+        let file = history.get(&HashArray::parse_fill_zero("02")).unwrap();
+        assert_eq!(file.data_at(1), Some(HashArray::parse_fill_zero("22")));
+        assert_eq!(file.data_at(2), None);
+        assert_eq!(file.spans_of(&HashArray::parse_fill_zero("22"), 4), [1..2, 3..4]);
+
+        let found = history.by_data(&HashArray::parse_fill_zero("22")).collect::<Vec<_>>();
+        assert_eq!(found, [file]);
+
+        let by_data = chunks.clone().map(|mut c| {
+            c.sort_by_data();
+            c
+        });
+        let resorted = History::from_hashes(&by_data.iter().collect::<Vec<_>>());
+        assert_eq!(resorted.files(), history.files());
+    }
+
+    #[test]
+    fn test_snapshot_history() {
+        let nightly = |report: &'static [u8], extra: bool| {
+            let mut files: Vec<(&str, &[u8])> = vec![("docs/report", report), ("docs/stable", b"stable")];
+            if extra {
+                files.push(("tmp/scratch", b"scratch"));
+            }
+            snapshot_of(PathIdentity::EXACT, &files)
+        };
+        let snapshots = [
+            nightly(b"good", false),
+            nightly(b"good", true),
+            nightly(b"corrupted", true),
+            nightly(b"corrupted", false),
+        ];
+        let history = History::build::<Sha256>(&snapshots.iter().collect::<Vec<_>>(), "");
+        assert_eq!(history.snapshots(), 4);
+        assert_eq!(history.files().len(), 3);
+
+        let report = history.by_path(Path::new("docs/report")).unwrap();
+        let changed = report.events.iter().find(|e| e.kind == DiffType::Changed).unwrap();
+        assert_eq!(changed.snapshot, 2);
+        let good = report.data_at(0).unwrap();
+        assert_eq!(report.spans_of(&good, history.snapshots()), vec![0..2]);
+        assert_eq!(history.by_data(&good).count(), 1);
+
+        let scratch = history.by_path(Path::new("tmp/scratch")).unwrap();
+        let events = scratch.events.iter().map(|e| (e.snapshot, e.kind)).collect::<Vec<_>>();
+        assert_eq!(events, [(1, DiffType::Added), (3, DiffType::Removed)]);
+        assert_eq!(history.by_path(Path::new("docs/stable")).unwrap().events.len(), 1);
+    }
+}
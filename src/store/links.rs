@@ -0,0 +1,88 @@
+use crate::file::chunks::LinksChunk;
+use crate::HashArray;
+use std::collections::{BTreeMap, HashSet};
+
+/// Change of hard link relationship of single path between two snapshots.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum LinkDiff {
+    /// Path was a hard link to other paths, but now it's a separate copy (or all other links are gone)
+    Broken(HashArray<32>),
+    /// Path is now a hard link to other paths, that it wasn't linked with before
+    Linked(HashArray<32>),
+}
+
+impl LinkDiff {
+    pub fn get_name(&self) -> &HashArray<32> {
+        match self {
+            Self::Broken(n) => n,
+            Self::Linked(n) => n,
+        }
+    }
+}
+
+fn group_map(chunk: &LinksChunk) -> BTreeMap<HashArray<32>, usize> {
+    chunk
+        .groups
+        .iter()
+        .enumerate()
+        .flat_map(|(i, g)| g.iter().map(move |n| (*n, i)))
+        .collect()
+}
+
+/// Compare link groups of two snapshots, returns changes sorted by name.
+pub fn diff_links(old: &LinksChunk, new: &LinksChunk) -> Vec<LinkDiff> {
+    let old_map = group_map(old);
+    let new_map = group_map(new);
+    let partners = |chunk: &LinksChunk, group: Option<&usize>, name: &HashArray<32>| -> HashSet<HashArray<32>> {
+        match group {
+            Some(&g) => chunk.groups[g].iter().filter(|n| *n != name).copied().collect(),
+            None => HashSet::new(),
+        }
+    };
+
+    let mut names = old_map.keys().chain(new_map.keys()).copied().collect::<Vec<_>>();
+    names.sort_unstable();
+    names.dedup();
+
+    let mut result = Vec::new();
+    for name in names {
+        let before = partners(old, old_map.get(&name), &name);
+        let after = partners(new, new_map.get(&name), &name);
+        let kept = before.intersection(&after).next().is_some();
+        if !before.is_empty() && !kept {
+            result.push(LinkDiff::Broken(name));
+        }
+        if !after.is_empty() && !kept {
+            result.push(LinkDiff::Linked(name));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(v: &str) -> HashArray<32> {
+        HashArray::parse_fill_zero(v)
+    }
+
+    #[test]
+    fn test_diff_links() {
+        let old = LinksChunk::new(vec![vec![name("01"), name("02"), name("03")], vec![name("04"), name("05")]]);
+        let new = LinksChunk::new(vec![vec![name("01"), name("02")], vec![name("05"), name("06")]]);
+
+        let diff = diff_links(&old, &new);
+        assert_eq!(
+            diff,
+            [
+                LinkDiff::Broken(name("03")),
+                LinkDiff::Broken(name("04")),
+                LinkDiff::Broken(name("05")),
+                LinkDiff::Linked(name("05")),
+                LinkDiff::Linked(name("06")),
+            ]
+        );
+        assert!(diff_links(&old, &old).is_empty());
+    }
+}
@@ -1,11 +1,14 @@
+use crate::file::chunks::{HashesChunk, SortOrder};
 use crate::store::{DiffingIter, HashStore};
 use crate::{DataEntry, HashArray, HashEntry};
 use std::iter::Copied;
 use std::slice::Iter;
 
 pub struct MemHashStore {
+    /// Entries sorted by id
     entries: Vec<DataEntry>,
-    sorted_by_id: bool, //true - by id, false - by data
+    /// Optional index of entries sorted by data, then by id
+    by_data: Option<Vec<usize>>,
 }
 
 impl HashStore for MemHashStore {
@@ -28,10 +31,7 @@ impl FromIterator<DataEntry> for MemHashStore {
     fn from_iter<T: IntoIterator<Item = DataEntry>>(iter: T) -> Self {
         let mut v = iter.into_iter().collect::<Vec<_>>();
         v.sort_unstable();
-        Self {
-            entries: v,
-            sorted_by_id: true,
-        }
+        Self { entries: v, by_data: None }
     }
 }
 
@@ -39,14 +39,26 @@ impl<'a> FromIterator<&'a DataEntry> for MemHashStore {
     fn from_iter<T: IntoIterator<Item = &'a DataEntry>>(iter: T) -> Self {
         let mut v = iter.into_iter().copied().collect::<Vec<_>>();
         v.sort_unstable();
-        Self {
-            entries: v,
-            sorted_by_id: true,
-        }
+        Self { entries: v, by_data: None }
     }
 }
 
 impl MemHashStore {
+    /// Store entries of chunk in any sort order, when chunk is sorted by data, data index is built from it.
+    pub fn from_chunk(chunk: HashesChunk) -> Self {
+        let sort = chunk.sort;
+        let mut entries = chunk.data;
+        if sort == SortOrder::SortedByName {
+            return Self { entries, by_data: None };
+        }
+        entries.sort_unstable();
+        let mut store = Self { entries, by_data: None };
+        if sort == SortOrder::SortedByData {
+            store.build_data_index();
+        }
+        store
+    }
+
     pub fn diff_with_new<'a>(&'a self, new: &'a Self) -> DiffingIter<Iter<'a, DataEntry>, Iter<'a, DataEntry>> {
         DiffingIter::new(self.sorted_ref_iter(), new.sorted_ref_iter())
     }
@@ -57,4 +69,98 @@ impl MemHashStore {
             Err(_) => None,
         }
     }
+
+    pub fn build_data_index(&mut self) {
+        let mut index = (0..self.entries.len()).collect::<Vec<_>>();
+        //entries are sorted by id, so stable sort keeps ids ordered within the same data
+        index.sort_by_key(|&i| self.entries[i].data);
+        self.by_data = Some(index);
+    }
+
+    pub fn with_data_index(mut self) -> Self {
+        self.build_data_index();
+        self
+    }
+
+    pub fn has_data_index(&self) -> bool {
+        self.by_data.is_some()
+    }
+
+    /// All entries with given content, sorted by id. Without data index all entries are scanned.
+    pub fn find_by_data(&self, data: &HashArray<32>) -> Vec<&DataEntry> {
+        match &self.by_data {
+            Some(index) => {
+                let start = index.partition_point(|&i| self.entries[i].data < *data);
+                index[start..]
+                    .iter()
+                    .map(|&i| &self.entries[i])
+                    .take_while(|e| e.data == *data)
+                    .collect()
+            }
+            None => self.entries.iter().filter(|e| e.data == *data).collect(),
+        }
+    }
+
+    /// Entries sorted by data, then by id.
+    pub fn data_sorted_iter(&self) -> impl Iterator<Item = &DataEntry> + '_ {
+        let index = match &self.by_data {
+            Some(index) => index.clone(),
+            None => {
+                let mut index = (0..self.entries.len()).collect::<Vec<_>>();
+                index.sort_by_key(|&i| self.entries[i].data);
+                index
+            }
+        };
+        index.into_iter().map(|i| &self.entries[i])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, data: &str) -> DataEntry {
+        HashEntry {
+            id: HashArray::parse_fill_zero(id),
+            data: HashArray::parse_fill_zero(data),
+        }
+    }
+
+    #[test]
+    fn test_find_by_data() {
+        let entries = [
+            entry("05", "aa"),
+            entry("01", "bb"),
+            entry("03", "aa"),
+            entry("04", "cc"),
+            entry("02", "aa"),
+        ];
+        let plain = entries.iter().collect::<MemHashStore>();
+        let indexed = entries.iter().collect::<MemHashStore>().with_data_index();
+        let aa = HashArray::parse_fill_zero("aa");
+        let expected = [&entries[4], &entries[2], &entries[0]];
+        assert_eq!(plain.find_by_data(&aa), expected);
+        assert_eq!(indexed.find_by_data(&aa), expected);
+        assert!(indexed.find_by_data(&HashArray::parse_fill_zero("dd")).is_empty());
+        assert_eq!(indexed.find_by_id(&HashArray::parse_fill_zero("04")), Some(&entries[3]));
+    }
+
+    #[test]
+    fn test_chunk_sorted_by_data() {
+        let entries = vec![entry("05", "aa"), entry("01", "bb"), entry("03", "aa"), entry("02", "aa")];
+        let mut chunk = HashesChunk::new_sha256(entries, false);
+        chunk.sort_by_data();
+        assert!(chunk.verify_sorted());
+        let mut bytes = Vec::new();
+        chunk.write(&mut bytes).unwrap();
+        let restored = HashesChunk::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.sort, SortOrder::SortedByData);
+        assert!(restored.data == chunk.data);
+
+        let store = MemHashStore::from_chunk(restored);
+        assert!(store.has_data_index());
+        assert_eq!(store.find_by_data(&HashArray::parse_fill_zero("aa")).len(), 3);
+        assert!(store.data_sorted_iter().eq(chunk.data.iter()));
+        assert!(store.sorted_ref_iter().map(|e| e.id).is_sorted());
+    }
 }
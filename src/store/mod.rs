@@ -1,10 +1,34 @@
+mod by_name;
+mod catalogue;
 mod compress;
+mod disk;
+mod dupes;
+mod history;
+mod links;
 mod mem;
+mod moves;
+mod name_ids;
+mod resolved;
+mod search;
 mod str_convert;
+mod three_way;
+mod tree;
 
 pub use self::compress::*;
+pub use by_name::*;
+pub use catalogue::*;
+pub use disk::*;
+pub use dupes::*;
+pub use history::*;
+pub use links::*;
 pub use mem::*;
+pub use moves::*;
+pub use name_ids::*;
+pub use resolved::*;
+pub use search::*;
 pub use str_convert::*;
+pub use three_way::*;
+pub use tree::*;
 
 use crate::store::DiffResult::Removed;
 use crate::store::DiffType::Added;
@@ -37,6 +61,10 @@ pub enum DiffResult<E> {
     Removed(E),
     Changed(E, E),
     Same(E),
+    /// Old entry was removed, and entry with the same content was added under other name, see [`detect_moves`]
+    Moved(E, E),
+    /// Entry was added with content of old entry, that is still present or was moved
+    Copied(E, E),
 }
 
 #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
@@ -45,6 +73,8 @@ pub enum DiffType {
     Removed,
     Changed,
     Same,
+    Moved,
+    Copied,
 }
 
 impl<E> DiffResult<E> {
@@ -54,6 +84,8 @@ impl<E> DiffResult<E> {
             Self::Removed(_) => DiffType::Removed,
             Self::Changed(_, _) => DiffType::Changed,
             Self::Same(_) => DiffType::Same,
+            Self::Moved(_, _) => DiffType::Moved,
+            Self::Copied(_, _) => DiffType::Copied,
         }
     }
 }
@@ -64,6 +96,8 @@ impl<E: NamedValue> DiffResult<E> {
             Self::Removed(a) => a.get_name(),
             Self::Changed(a, _) => a.get_name(), //names should be equal here
             Self::Same(a) => a.get_name(),
+            Self::Moved(_, a) => a.get_name(), //new name, same as in added entry that was replaced
+            Self::Copied(_, a) => a.get_name(),
         }
     }
 }
@@ -192,10 +226,66 @@ where
 }
 
 #[cfg(test)]
-mod tests {
-    use crate::store::{DiffResult, DiffingIter};
-    use crate::{HashArray, HashEntry};
+pub(crate) mod tests {
+    use crate::file::chunks::{HashesChunk, NamesChunk, SizeEntry, SizesChunk};
+    use crate::file::Snapshot;
+    use crate::store::{build_tree, entry_links, DiffResult, DiffingIter};
+    use crate::utils::BungeeStr;
+    use crate::{relative_components, HashArray, HashEntry, PathIdentity};
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
     use std::iter::empty;
+    use std::path::Path;
+
+    /// Snapshot of given files built directly from chunks. Paths are separated with `/`, names are pushed to names
+    /// chunk in given order, and name ids are derived the same way as [`crate::DigestConsumer`] does for root
+    /// relative paths. Sizes are recorded for all files, entries are linked to names and directory tree is built.
+    pub(crate) fn snapshot_of(identity: PathIdentity, files: &[(&str, &[u8])]) -> Snapshot {
+        let mut bungee = BungeeStr::new();
+        let mut dirs = HashMap::new();
+        let mut indexes = Vec::new();
+        let mut entries = Vec::new();
+        let mut sizes = Vec::new();
+        let digest = |bytes: &[u8]| {
+            let mut hash = HashArray::zero();
+            hash.get_mut().copy_from_slice(&Sha256::digest(bytes));
+            hash
+        };
+        for &(path, data) in files {
+            let mut parts = path.split('/').collect::<Vec<_>>();
+            let name = parts.pop().unwrap();
+            let mut parent = None;
+            for depth in 1..=parts.len() {
+                let part = identity.normalize_str(parts[depth - 1]);
+                let at = *dirs
+                    .entry(parts[..depth].join("/"))
+                    .or_insert_with(|| bungee.push(parent, &part).unwrap());
+                parent = Some(at);
+            }
+            indexes.push(bungee.push(parent, &identity.normalize_str(name)).unwrap());
+            let id = digest(&identity.name_key("", relative_components(None, Path::new(path))));
+            entries.push(HashEntry { id, data: digest(data) });
+            sizes.push(SizeEntry {
+                id,
+                size: data.len() as u64,
+            });
+        }
+        let mut hashes = HashesChunk::new_sha256(entries, false);
+        hashes.identity = identity;
+        hashes.root_relative = true;
+        hashes.sort();
+        let mut names = NamesChunk::new(bungee, indexes);
+        names.set_entry_links(entry_links::<Sha256>(&hashes, &names, ""), hashes.sort);
+        Snapshot {
+            tree: build_tree::<Sha256>(&hashes, &names, ""),
+            hashes,
+            names,
+            links: Default::default(),
+            sizes: SizesChunk::new(sizes),
+            filters: Vec::new(),
+            suffixes: Default::default(),
+        }
+    }
 
     fn mock_entry(id: &str, data: &str) -> HashEntry<32, 32> {
         HashEntry {
@@ -0,0 +1,276 @@
+use crate::file::Snapshot;
+use crate::store::{name_paths, DiffResult, NamedValue};
+use crate::{relative_components, HashArray, PathIdentity};
+use digest::consts::U32;
+use digest::Digest;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+/// Place of file in the tree, used to choose the best pair when many files share the same content.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct FileLocation {
+    /// Hash of parent directory path
+    pub dir: u64,
+    /// Hash of file name
+    pub name: u64,
+}
+
+impl FileLocation {
+    pub fn of_path(identity: PathIdentity, prefix: &str, path: &Path) -> Self {
+        let components = relative_components(None, path).collect::<Vec<_>>();
+        let (name, parent) = components.split_last().map(|(n, p)| (Some(*n), p)).unwrap_or((None, &[]));
+        let hash = |bytes: &[u8]| {
+            let mut hasher = DefaultHasher::new();
+            bytes.hash(&mut hasher);
+            hasher.finish()
+        };
+        Self {
+            dir: hash(&identity.name_key(prefix, parent.iter().copied())),
+            name: hash(&identity.name_key("", name)),
+        }
+    }
+}
+
+/// Locations of all names of snapshot, keyed by name hash, see [`name_paths`]. Names of snapshots hashed with key
+/// can't be hashed again, so they are rejected.
+pub fn name_locations<D: Digest<OutputSize = U32>>(snapshot: &Snapshot, prefix: &str) -> io::Result<HashMap<HashArray<32>, FileLocation>> {
+    let identity = snapshot.hashes.identity;
+    if snapshot.hashes.key_id.is_some() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Snapshot names were hashed with key"));
+    }
+    Ok(name_paths::<D>(&snapshot.names, identity, prefix)
+        .map(|(id, path)| (id, FileLocation::of_path(identity, prefix, &path)))
+        .collect())
+}
+
+/// Candidates of given content, stacks are indexed by content and optionally by part of location.
+struct Candidates<K> {
+    by_value: HashMap<K, Vec<usize>>,
+    by_name: HashMap<(K, u64), Vec<usize>>,
+    by_dir: HashMap<(K, u64), Vec<usize>>,
+}
+
+impl<K: Hash + Eq + Clone> Candidates<K> {
+    fn new() -> Self {
+        Self {
+            by_value: HashMap::new(),
+            by_name: HashMap::new(),
+            by_dir: HashMap::new(),
+        }
+    }
+
+    /// Candidates are pushed in reverse, so that the first pushed candidate is picked first.
+    fn push(&mut self, value: K, location: Option<FileLocation>, index: usize) {
+        if let Some(loc) = location {
+            self.by_name.entry((value.clone(), loc.name)).or_default().push(index);
+            self.by_dir.entry((value.clone(), loc.dir)).or_default().push(index);
+        }
+        self.by_value.entry(value).or_default().push(index);
+    }
+
+    fn reverse(&mut self) {
+        let stacks = self
+            .by_value
+            .values_mut()
+            .chain(self.by_name.values_mut())
+            .chain(self.by_dir.values_mut());
+        stacks.for_each(|s| s.reverse());
+    }
+
+    /// First candidate that passes filter and has the same file name (tier 0), the same directory (tier 1), or any
+    /// location (tier 2). Candidates rejected by filter are dropped.
+    fn find(&mut self, value: &K, location: Option<FileLocation>, tier: u8, mut valid: impl FnMut(usize) -> bool) -> Option<usize> {
+        let stack = match (tier, location) {
+            (0, Some(loc)) => self.by_name.get_mut(&(value.clone(), loc.name)),
+            (1, Some(loc)) => self.by_dir.get_mut(&(value.clone(), loc.dir)),
+            (0 | 1, None) => None,
+            _ => self.by_value.get_mut(value),
+        }?;
+        while let Some(&index) = stack.last() {
+            if valid(index) {
+                return Some(index);
+            }
+            stack.pop();
+        }
+        None
+    }
+}
+
+/// Post-pass over diff, that pairs removed and added entries with the same content into [`DiffResult::Moved`], and
+/// turns added entries with content that already existed before into [`DiffResult::Copied`].
+///
+/// When there are many candidates, the one with the same file name, then the one in the same directory is preferred,
+/// `locate` returns location of entry if it's known. Result keeps order of the input, moved and copied entries are
+/// placed where the added entry was, removed entries that were moved are dropped.
+pub fn detect_moves<E, I, L>(diff: I, locate: L) -> Vec<DiffResult<E>>
+where
+    I: IntoIterator<Item = DiffResult<E>>,
+    E: NamedValue + Clone,
+    E::Value: Hash + Eq + Clone,
+    L: Fn(&E) -> Option<FileLocation>,
+{
+    let diff = diff.into_iter().collect::<Vec<_>>();
+    let mut removed = Candidates::new();
+    let mut sources = Candidates::new();
+    let mut added = Vec::new();
+    for (index, entry) in diff.iter().enumerate() {
+        match entry {
+            DiffResult::Removed(e) => {
+                removed.push(e.get_value().clone(), locate(e), index);
+                sources.push(e.get_value().clone(), locate(e), index);
+            }
+            DiffResult::Same(e) | DiffResult::Changed(e, _) => sources.push(e.get_value().clone(), locate(e), index),
+            DiffResult::Added(e) => added.push((index, e.get_value(), locate(e))),
+            _ => {}
+        }
+    }
+    removed.reverse();
+    sources.reverse();
+
+    //pairs of added entry index with old entry index, and flag if it's a move
+    let mut paired = vec![None; diff.len()];
+    let mut moved = vec![false; diff.len()];
+    for tier in 0..3 {
+        for &(index, value, location) in &added {
+            if paired[index].is_some() {
+                continue;
+            }
+            if let Some(old) = removed.find(value, location, tier, |i| !moved[i]) {
+                moved[old] = true;
+                paired[index] = Some((old, true));
+            }
+        }
+    }
+    for tier in 0..3 {
+        for &(index, value, location) in &added {
+            if paired[index].is_none() {
+                paired[index] = sources.find(value, location, tier, |_| true).map(|old| (old, false));
+            }
+        }
+    }
+
+    let old_entry = |index: usize| match &diff[index] {
+        DiffResult::Removed(e) | DiffResult::Same(e) | DiffResult::Changed(e, _) => e.clone(),
+        _ => unreachable!("Only old entries are paired"),
+    };
+    let mut result = Vec::with_capacity(diff.len());
+    for (index, entry) in diff.iter().enumerate() {
+        if moved[index] {
+            continue;
+        }
+        result.push(match (entry, paired[index]) {
+            (DiffResult::Added(new), Some((old, true))) => DiffResult::Moved(old_entry(old), new.clone()),
+            (DiffResult::Added(new), Some((old, false))) => DiffResult::Copied(old_entry(old), new.clone()),
+            (entry, _) => entry.clone(),
+        });
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::snapshot_of;
+    use crate::store::{DiffType, DiffingIter};
+    use crate::HashEntry;
+    use sha2::Sha256;
+
+    fn entry(id: &str, data: &str) -> HashEntry<32, 32> {
+        HashEntry {
+            id: HashArray::parse_fill_zero(id),
+            data: HashArray::parse_fill_zero(data),
+        }
+    }
+
+    #[test]
+    fn test_detect_moves() {
+        let old = [entry("01", "a1"), entry("02", "a2"), entry("03", "a3"), entry("04", "a4")];
+        let new = [
+            entry("02", "a2"),
+            entry("04", "b4"),
+            entry("11", "a1"),
+            entry("12", "a2"),
+            entry("13", "a5"),
+        ];
+        let diff = detect_moves(DiffingIter::new(old.iter(), new.iter()), |_| None);
+        assert_eq!(
+            diff,
+            [
+                DiffResult::Same(&old[1]),
+                DiffResult::Removed(&old[2]),
+                DiffResult::Changed(&old[3], &new[1]),
+                DiffResult::Moved(&old[0], &new[2]),
+                DiffResult::Copied(&old[1], &new[3]),
+                DiffResult::Added(&new[4]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_detect_moves_location() {
+        //two identical files swapped directories, pairs should keep their names
+        let old = [entry("01", "aa"), entry("02", "aa"), entry("03", "aa")];
+        let new = [entry("11", "aa"), entry("12", "aa")];
+        let locations = [(1, 1), (1, 2), (1, 3), (2, 2), (3, 3)];
+        let location = |id: &HashArray<32>| {
+            let index = old.iter().chain(&new).position(|e| e.id == *id)?;
+            let (dir, name) = locations[index];
+            Some(FileLocation { dir, name })
+        };
+        let diff = detect_moves(DiffingIter::new(old.iter(), new.iter()), |e| location(&e.id));
+        assert_eq!(
+            diff,
+            [
+                DiffResult::Removed(&old[0]),
+                DiffResult::Moved(&old[1], &new[0]),
+                DiffResult::Moved(&old[2], &new[1]),
+            ]
+        );
+        let diff = detect_moves(DiffingIter::new(old.iter(), new.iter()), |_| None);
+        assert_eq!(
+            diff.iter().map(|d| d.diff_type()).collect::<Vec<_>>(),
+            [DiffType::Removed, DiffType::Moved, DiffType::Moved]
+        );
+    }
+
+    #[test]
+    fn test_location_of_path() {
+        let exact = PathIdentity::EXACT;
+        let a = FileLocation::of_path(exact, "", Path::new("dir/photo.jpg"));
+        let b = FileLocation::of_path(exact, "", Path::new("other/photo.jpg"));
+        let c = FileLocation::of_path(exact, "", Path::new("dir/scan.jpg"));
+        assert_eq!(a.name, b.name);
+        assert_ne!(a.dir, b.dir);
+        assert_eq!(a.dir, c.dir);
+        assert_ne!(a.name, c.name);
+    }
+
+    #[test]
+    fn test_snapshot_moves() {
+        let exact = PathIdentity::EXACT;
+        let before = snapshot_of(exact, &[("2020/a.jpg", b"a"), ("2020/b.jpg", b"b"), ("c.jpg", b"b")]);
+        let after = snapshot_of(exact, &[("2020/a.jpg", b"a"), ("sorted/c.jpg", b"b")]);
+
+        let mut locations = name_locations::<Sha256>(&before, "").unwrap();
+        locations.extend(name_locations::<Sha256>(&after, "").unwrap());
+        assert!(before.hashes.data.iter().all(|e| locations.contains_key(&e.id)));
+        let diff = DiffingIter::new(before.hashes.data.iter(), after.hashes.data.iter());
+        let diff = detect_moves(diff, |e| locations.get(&e.id).copied());
+        let types = diff.iter().map(|d| d.diff_type()).collect::<Vec<_>>();
+        assert_eq!(types.iter().filter(|t| **t == DiffType::Same).count(), 1);
+        assert_eq!(types.iter().filter(|t| **t == DiffType::Removed).count(), 1);
+        let moved = diff.iter().filter(|d| d.diff_type() == DiffType::Moved).collect::<Vec<_>>();
+        assert_eq!(moved.len(), 1);
+        let DiffResult::Moved(old, _) = moved[0] else { unreachable!() };
+        //both removed files have the same content, the one with the same name is preferred
+        assert_eq!(locations[&old.id], FileLocation::of_path(exact, "", Path::new("c.jpg")));
+
+        let mut keyed = after;
+        keyed.hashes.key_id = Some(7);
+        assert!(name_locations::<Sha256>(&keyed, "").is_err());
+    }
+}
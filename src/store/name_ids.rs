@@ -0,0 +1,105 @@
+use crate::file::chunks::{HashesChunk, NamesChunk, NO_LINK};
+use crate::utils::BungeeIndex;
+use crate::{relative_components, HashArray, PathIdentity};
+use digest::consts::U32;
+use digest::Digest;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+pub(crate) fn hash_of<D: Digest<OutputSize = U32>>(feed: impl FnOnce(&mut D)) -> HashArray<32> {
+    let mut hasher = D::new();
+    feed(&mut hasher);
+    let mut hash = HashArray::zero();
+    hash.get_mut().copy_from_slice(&hasher.finalize());
+    hash
+}
+
+/// Name hash of root relative path given by its components, computed the same way as [`crate::DigestConsumer`]
+/// does.
+pub(crate) fn name_id<'a, D: Digest<OutputSize = U32>>(
+    identity: PathIdentity,
+    prefix: &str,
+    components: impl IntoIterator<Item = &'a OsStr>,
+) -> HashArray<32> {
+    hash_of::<D>(|h| identity.visit_name_key(prefix, components, |b| h.update(b)))
+}
+
+/// Name hash of root relative path, see [`name_id`].
+pub(crate) fn path_id<D: Digest<OutputSize = U32>>(identity: PathIdentity, prefix: &str, path: &Path) -> HashArray<32> {
+    name_id::<D>(identity, prefix, relative_components(None, path))
+}
+
+/// Bungee indexes of all names in names chunk with their name hashes, computed the same way as
+/// [`crate::DigestConsumer`] does for root relative paths.
+pub fn name_indexes<'a, D: Digest<OutputSize = U32>>(
+    names: &'a NamesChunk,
+    identity: PathIdentity,
+    prefix: &'a str,
+) -> impl Iterator<Item = (HashArray<32>, BungeeIndex)> + 'a {
+    names.indexes().iter().map(move |&at| {
+        let path = names.bungee().os_path_of(at);
+        (path_id::<D>(identity, prefix, &path), at)
+    })
+}
+
+/// Paths of all names in names chunk with their name hashes, see [`name_indexes`].
+pub fn name_paths<'a, D: Digest<OutputSize = U32>>(
+    names: &'a NamesChunk,
+    identity: PathIdentity,
+    prefix: &'a str,
+) -> impl Iterator<Item = (HashArray<32>, PathBuf)> + 'a {
+    names.indexes().iter().map(move |&at| {
+        let path = names.bungee().os_path_of(at);
+        (path_id::<D>(identity, prefix, &path), path)
+    })
+}
+
+/// Position in names chunk indexes of every hashes entry, see [`NamesChunk::set_entry_links`]. Entries without name
+/// are linked to [`NO_LINK`].
+pub fn entry_links<D: Digest<OutputSize = U32>>(hashes: &HashesChunk, names: &NamesChunk, prefix: &str) -> Vec<u64> {
+    let positions = name_indexes::<D>(names, hashes.identity, prefix)
+        .enumerate()
+        .map(|(i, (id, _))| (id, i as u64))
+        .collect::<HashMap<_, _>>();
+    hashes
+        .data
+        .iter()
+        .map(|e| positions.get(&e.id).copied().unwrap_or(NO_LINK))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::Snapshot;
+    use crate::store::tests::snapshot_of;
+    use crate::store::SnapshotNames;
+    use sha2::Sha256;
+
+    #[test]
+    fn test_snapshot_entry_links() {
+        let snapshot = snapshot_of(PathIdentity::EXACT, &[("z.txt", b"z"), ("a/b.txt", b"b"), ("m.txt", b"m")]);
+        assert_eq!(snapshot.names.entry_links().len(), snapshot.hashes.data.len());
+        let by_path = name_paths::<Sha256>(&snapshot.names, PathIdentity::EXACT, "").collect::<HashMap<_, _>>();
+
+        let (mut plain, mut shared) = (Vec::new(), Vec::new());
+        snapshot.write(&mut plain).unwrap();
+        snapshot.write_shared(&mut shared, 1).unwrap();
+        for bytes in [plain, shared] {
+            let restored = Snapshot::read(&mut bytes.as_slice()).unwrap();
+            for (i, entry) in restored.hashes.data.iter().enumerate() {
+                assert_eq!(restored.entry_path(i).as_ref(), by_path.get(&entry.id));
+            }
+            //links were built for hashes sorted by name
+            let mut resorted = restored.clone();
+            resorted.hashes.sort_by_data();
+            assert_eq!(resorted.entry_path(0), None);
+            let names = SnapshotNames::new::<Sha256>(&resorted, "").unwrap();
+            for entry in &resorted.hashes.data {
+                let at = names.index_of(&entry.id).unwrap();
+                assert_eq!(Some(&resorted.names.bungee().os_path_of(at)), by_path.get(&entry.id));
+            }
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use crate::file::chunks::{NamesChunk, SizesChunk};
+use crate::file::Snapshot;
+use crate::store::{detect_moves, name_indexes, DiffResult, DiffType, DiffingIter, EntriesByName, FileLocation};
+use crate::utils::{BungeeIndex, ByteSize};
+use crate::{DataEntry, HashArray};
+use digest::consts::U32;
+use digest::Digest;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+/// Name hashes of snapshot resolved to their place in names chunk.
+pub struct SnapshotNames<'a> {
+    names: &'a NamesChunk,
+    sizes: &'a SizesChunk,
+    entries: EntriesByName<'a>,
+    by_id: HashMap<HashArray<32>, BungeeIndex>,
+    by_index: HashMap<BungeeIndex, HashArray<32>>,
+}
+
+impl<'a> SnapshotNames<'a> {
+    /// Names of snapshot hashed with key can't be hashed again, so they are rejected.
+    pub fn new<D: Digest<OutputSize = U32>>(snapshot: &'a Snapshot, prefix: &str) -> io::Result<Self> {
+        let (names, hashes) = (&snapshot.names, &snapshot.hashes);
+        if hashes.key_id.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Snapshot names were hashed with key"));
+        }
+        //linked entries are resolved directly, otherwise names are hashed again
+        let by_id = if !hashes.data.is_empty() && names.links_match(hashes) {
+            let linked = hashes.data.iter().enumerate();
+            linked.filter_map(|(i, e)| Some((e.id, names.entry_index(i)?))).collect()
+        } else {
+            name_indexes::<D>(names, hashes.identity, prefix).collect::<HashMap<_, _>>()
+        };
+        Ok(Self {
+            names: &snapshot.names,
+            sizes: &snapshot.sizes,
+            entries: EntriesByName::new(&snapshot.hashes),
+            by_index: by_id.iter().map(|(id, at)| (*at, *id)).collect(),
+            by_id,
+        })
+    }
+
+    /// Name hash of file entry, `None` for directories.
+    pub fn id_of(&self, at: BungeeIndex) -> Option<HashArray<32>> {
+        self.by_index.get(&at).copied()
+    }
+
+    /// Hashes entry of file entry, `None` for directories and files without hash.
+    pub fn entry_of(&self, at: BungeeIndex) -> Option<&'a DataEntry> {
+        self.entries.get(&self.id_of(at)?)
+    }
+
+    pub fn index_of(&self, id: &HashArray<32>) -> Option<BungeeIndex> {
+        self.by_id.get(id).copied()
+    }
+
+    pub fn path_of(&self, id: &HashArray<32>) -> Option<PathBuf> {
+        Some(self.names.bungee().os_path_of(self.index_of(id)?))
+    }
+
+    pub fn size_of(&self, id: &HashArray<32>) -> Option<u64> {
+        self.sizes.get(id)
+    }
+
+    /// Indexes of all parent directories of name, nearest first.
+    fn parents_of(&self, id: &HashArray<32>) -> impl Iterator<Item = BungeeIndex> + 'a {
+        let names = self.names;
+        self.index_of(id)
+            .into_iter()
+            .flat_map(move |at| names.bungee().reverse_follow_iter(at).skip(1).map(|(_, i)| i))
+    }
+}
+
+/// Diff entry joined with names of both snapshots.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PathDiff<'a> {
+    pub diff: DiffResult<&'a DataEntry>,
+    /// Path in new snapshot, or in old one for removed entries
+    pub path: Option<PathBuf>,
+    /// Path in old snapshot for moved and copied entries
+    pub old_path: Option<PathBuf>,
+    /// Size of content in new snapshot, or in old one for removed entries
+    pub size: Option<u64>,
+}
+
+/// Differences of two snapshots with paths resolved through their names chunks.
+pub struct SnapshotDiff<'a> {
+    old_snapshot: &'a Snapshot,
+    new_snapshot: &'a Snapshot,
+    old: SnapshotNames<'a>,
+    new: SnapshotNames<'a>,
+    prefix: String,
+}
+
+impl<'a> SnapshotDiff<'a> {
+    /// Snapshots hashed with key are rejected, see [`SnapshotNames::new`].
+    pub fn new<D: Digest<OutputSize = U32>>(old: &'a Snapshot, new: &'a Snapshot, prefix: &str) -> io::Result<Self> {
+        Ok(Self {
+            old_snapshot: old,
+            new_snapshot: new,
+            old: SnapshotNames::new::<D>(old, prefix)?,
+            new: SnapshotNames::new::<D>(new, prefix)?,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    pub fn old_names(&self) -> &SnapshotNames<'a> {
+        &self.old
+    }
+
+    pub fn new_names(&self) -> &SnapshotNames<'a> {
+        &self.new
+    }
+
+    pub fn resolve(&self, diff: DiffResult<&'a DataEntry>) -> PathDiff<'a> {
+        let (old, new) = match &diff {
+            DiffResult::Removed(o) => (Some(*o), None),
+            DiffResult::Moved(o, n) | DiffResult::Copied(o, n) => (Some(*o), Some(*n)),
+            DiffResult::Added(n) | DiffResult::Changed(_, n) | DiffResult::Same(n) => (None, Some(*n)),
+        };
+        let (path, old_path, size) = match (old, new) {
+            (Some(o), None) => (self.old.path_of(&o.id), None, self.old.size_of(&o.id)),
+            (o, Some(n)) => (
+                self.new.path_of(&n.id),
+                o.and_then(|o| self.old.path_of(&o.id)),
+                self.new.size_of(&n.id),
+            ),
+            (None, None) => unreachable!(),
+        };
+        PathDiff {
+            diff,
+            path,
+            old_path,
+            size,
+        }
+    }
+
+    /// All differences of snapshots, moves and copies are detected by content, unchanged entries are skipped.
+    pub fn changes(&self) -> Vec<PathDiff<'a>> {
+        let (old, new) = (
+            EntriesByName::new(&self.old_snapshot.hashes),
+            EntriesByName::new(&self.new_snapshot.hashes),
+        );
+        let diff = DiffingIter::new(old.iter(), new.iter());
+        let locate = |e: &&DataEntry| {
+            let path = self.old.path_of(&e.id).or_else(|| self.new.path_of(&e.id))?;
+            Some(FileLocation::of_path(self.new_snapshot.hashes.identity, &self.prefix, &path))
+        };
+        detect_moves(diff, locate)
+            .into_iter()
+            .filter(|d| d.diff_type() != DiffType::Same)
+            .map(|d| self.resolve(d))
+            .collect()
+    }
+
+    /// Changes aggregated into every directory above changed entries, directories deeper than `max_depth` are
+    /// skipped. Moved entries are counted under both their old and new directories, directories above both of them
+    /// count the move once. The first roll-up is the root with totals of all changes, rest is sorted by path.
+    pub fn rollup(&self, changes: &[PathDiff<'a>], max_depth: Option<usize>) -> Vec<DirRollup> {
+        let mut dirs = BTreeMap::<PathBuf, DirRollup>::new();
+        let mut paths = HashMap::<(bool, BungeeIndex), PathBuf>::new();
+        for change in changes {
+            let (old, new) = match &change.diff {
+                DiffResult::Removed(o) => (Some(o), None),
+                DiffResult::Moved(o, n) => (Some(o), Some(n)),
+                DiffResult::Added(n) | DiffResult::Changed(_, n) | DiffResult::Same(n) | DiffResult::Copied(_, n) => (None, Some(n)),
+            };
+            let sides = [(&self.old, old, true), (&self.new, new, false)];
+            let mut parents = BTreeSet::from([PathBuf::new()]);
+            for (names, entry, is_old) in sides {
+                let Some(entry) = entry else {
+                    continue;
+                };
+                parents.extend(names.parents_of(&entry.id).map(|at| {
+                    paths
+                        .entry((is_old, at))
+                        .or_insert_with(|| names.names.bungee().os_path_of(at))
+                        .clone()
+                }));
+            }
+            for path in parents {
+                if max_depth.is_some_and(|d| path.components().count() > d) {
+                    continue;
+                }
+                dirs.entry(path.clone()).or_insert_with(|| DirRollup::new(path)).add(change);
+            }
+        }
+        let mut result = Vec::with_capacity(dirs.len());
+        result.extend(dirs.remove(Path::new("")));
+        result.extend(dirs.into_values());
+        result
+    }
+}
+
+/// Summary of changes under single directory.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct DirRollup {
+    /// Root relative path, empty for root
+    pub path: PathBuf,
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    pub moved: usize,
+    pub copied: usize,
+    /// Known size of all changed entries
+    pub bytes: ByteSize,
+}
+
+impl DirRollup {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, change: &PathDiff) {
+        match change.diff.diff_type() {
+            DiffType::Added => self.added += 1,
+            DiffType::Removed => self.removed += 1,
+            DiffType::Changed => self.changed += 1,
+            DiffType::Moved => self.moved += 1,
+            DiffType::Copied => self.copied += 1,
+            DiffType::Same => return,
+        }
+        self.bytes += change.size.unwrap_or(0);
+    }
+
+    pub fn total(&self) -> usize {
+        self.added + self.removed + self.changed + self.moved + self.copied
+    }
+}
+
+impl Display for DirRollup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.path.as_os_str().is_empty() {
+            write!(f, ".:")?;
+        } else {
+            write!(f, "{}:", self.path.display())?;
+        }
+        let counts = [
+            (self.changed, "changed"),
+            (self.added, "added"),
+            (self.removed, "removed"),
+            (self.moved, "moved"),
+            (self.copied, "copied"),
+        ];
+        for (count, name) in counts.into_iter().filter(|(c, _)| *c != 0) {
+            write!(f, " {count} {name},")?;
+        }
+        write!(f, " {:.1}", self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::snapshot_of;
+    use crate::PathIdentity;
+    use sha2::Sha256;
+
+    #[test]
+    fn test_rollup_display() {
+        let rollup = DirRollup {
+            path: PathBuf::from("photos/2019"),
+            changed: 12,
+            removed: 3,
+            bytes: ByteSize(1288490189),
+            ..Default::default()
+        };
+        assert_eq!(rollup.to_string(), "photos/2019: 12 changed, 3 removed, 1.2 GB");
+        assert_eq!(DirRollup::default().to_string(), ".: 0.0 B");
+    }
+
+    #[test]
+    fn test_snapshot_diff_paths() {
+        let before: [(&str, &[u8]); 5] = [
+            ("photos/2019/a.jpg", b"a"),
+            ("photos/2019/b.jpg", b"b"),
+            ("photos/2019/old.jpg", b"old"),
+            ("photos/c.jpg", b"moved"),
+            ("docs/same", b"same"),
+        ];
+        let after: [(&str, &[u8]); 5] = [
+            ("photos/2019/a.jpg", b"changed"),
+            ("photos/2019/b.jpg", b"b"),
+            ("photos/2020/c.jpg", b"moved"),
+            ("docs/same", b"same"),
+            ("docs/new", b"new"),
+        ];
+        let before = snapshot_of(PathIdentity::EXACT, &before);
+        let mut after = snapshot_of(PathIdentity::EXACT, &after);
+
+        after.hashes.sort_by_data();
+        assert_eq!(SnapshotDiff::new::<Sha256>(&before, &after, "").unwrap().changes().len(), 4);
+        after.hashes.sort();
+        let mut keyed = before.clone();
+        keyed.hashes.key_id = Some(7);
+        assert!(SnapshotDiff::new::<Sha256>(&keyed, &after, "").is_err());
+
+        let diff = SnapshotDiff::new::<Sha256>(&before, &after, "").unwrap();
+        let changes = diff.changes();
+        let mut paths = changes
+            .iter()
+            .map(|c| (c.diff.diff_type(), c.path.clone().unwrap(), c.old_path.clone(), c.size))
+            .collect::<Vec<_>>();
+        paths.sort_by(|a, b| a.1.cmp(&b.1));
+        assert_eq!(
+            paths,
+            [
+                (DiffType::Added, PathBuf::from("docs/new"), None, Some(3)),
+                (DiffType::Changed, PathBuf::from("photos/2019/a.jpg"), None, Some(7)),
+                (DiffType::Removed, PathBuf::from("photos/2019/old.jpg"), None, Some(3)),
+                (
+                    DiffType::Moved,
+                    PathBuf::from("photos/2020/c.jpg"),
+                    Some(PathBuf::from("photos/c.jpg")),
+                    Some(5)
+                ),
+            ]
+        );
+
+        let rollup = diff.rollup(&changes, None);
+        let lines = rollup.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                ".: 1 changed, 1 added, 1 removed, 1 moved, 18.0 B",
+                "docs: 1 added, 3.0 B",
+                "photos: 1 changed, 1 removed, 1 moved, 15.0 B",
+                "photos/2019: 1 changed, 1 removed, 10.0 B",
+                "photos/2020: 1 moved, 5.0 B",
+            ]
+        );
+        assert_eq!(diff.rollup(&changes, Some(1)).len(), 3);
+    }
+
+    #[test]
+    fn test_rollup_moved() {
+        let before = snapshot_of(PathIdentity::EXACT, &[("a/x", b"x"), ("keep", b"k")]);
+        let after = snapshot_of(PathIdentity::EXACT, &[("b/c/x", b"x"), ("keep", b"k")]);
+        let diff = SnapshotDiff::new::<Sha256>(&before, &after, "").unwrap();
+        let changes = diff.changes();
+        assert_eq!(changes.len(), 1);
+        let lines = diff.rollup(&changes, None).iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [".: 1 moved, 1.0 B", "a: 1 moved, 1.0 B", "b: 1 moved, 1.0 B", "b/c: 1 moved, 1.0 B"]
+        );
+    }
+
+    #[test]
+    fn test_snapshot_names_walk() {
+        let snapshot = snapshot_of(PathIdentity::EXACT, &[("a/b/c.txt", b"c"), ("d.txt", b"d")]);
+        let names = SnapshotNames::new::<Sha256>(&snapshot, "").unwrap();
+        let walked = snapshot
+            .names
+            .walk()
+            .map(|e| (snapshot.names.bungee().os_path_of(e.at), e.depth, names.entry_of(e.at).is_some()))
+            .collect::<Vec<_>>();
+        let expected = [("d.txt", 0, true), ("a/b/c.txt", 2, true), ("a/b", 1, false), ("a", 0, false)];
+        assert_eq!(walked, expected.map(|(p, d, f)| (PathBuf::from(p), d, f)));
+
+        let mut resorted = snapshot.clone();
+        resorted.hashes.sort_by_data();
+        let names = SnapshotNames::new::<Sha256>(&resorted, "").unwrap();
+        for entry in &snapshot.hashes.data {
+            let at = names.index_of(&entry.id).unwrap();
+            assert_eq!(names.id_of(at), Some(entry.id));
+            assert_eq!(names.entry_of(at), Some(entry));
+        }
+    }
+}
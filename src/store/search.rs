@@ -0,0 +1,342 @@
+use crate::file::Snapshot;
+use crate::store::{path_id, EntriesByName};
+use crate::HashArray;
+use digest::consts::U32;
+use digest::Digest;
+use regex::{Regex, RegexBuilder};
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::marker::PhantomData;
+use std::path::PathBuf;
+
+/// Part of path that query is matched against.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum QueryTarget {
+    /// Last component of path
+    Name,
+    /// Whole root relative path, with `/` separators
+    Path,
+}
+
+#[derive(Clone, Debug)]
+enum QueryKind {
+    Substring(String),
+    Pattern(Regex),
+}
+
+/// Single condition of [`NameSearch`], matched against display form of paths, where names that are not valid UTF-8
+/// are escaped.
+#[derive(Clone, Debug)]
+pub struct NameQuery {
+    kind: QueryKind,
+    source: String,
+    target: QueryTarget,
+    ignore_case: bool,
+}
+
+impl NameQuery {
+    /// Names containing given text.
+    pub fn substring(text: &str) -> Self {
+        Self {
+            kind: QueryKind::Substring(text.to_string()),
+            source: text.to_string(),
+            target: QueryTarget::Name,
+            ignore_case: false,
+        }
+    }
+
+    /// Shell like pattern with `*`, `?`, `[...]` and `**`, patterns with `/` are matched against whole path, other
+    /// against names.
+    pub fn glob(pattern: &str) -> Result<Self, regex::Error> {
+        let target = if pattern.contains('/') {
+            QueryTarget::Path
+        } else {
+            QueryTarget::Name
+        };
+        Ok(Self::regex(&glob_to_regex(pattern))?.with_target(target))
+    }
+
+    /// Regular expression matched anywhere in path.
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            kind: QueryKind::Pattern(Regex::new(pattern)?),
+            source: pattern.to_string(),
+            target: QueryTarget::Path,
+            ignore_case: false,
+        })
+    }
+
+    pub fn with_target(mut self, target: QueryTarget) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Match regardless of case, patterns are compiled again and can fail, e.g. when case folding exceeds size limit.
+    pub fn with_ignore_case(mut self) -> Result<Self, regex::Error> {
+        self.ignore_case = true;
+        self.kind = match self.kind {
+            QueryKind::Substring(_) => QueryKind::Substring(self.source.to_lowercase()),
+            QueryKind::Pattern(_) => QueryKind::Pattern(RegexBuilder::new(&self.source).case_insensitive(true).build()?),
+        };
+        Ok(self)
+    }
+
+    pub fn target(&self) -> QueryTarget {
+        self.target
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        let text = match self.target {
+            QueryTarget::Name => path.rsplit('/').next().unwrap_or(path),
+            QueryTarget::Path => path,
+        };
+        match &self.kind {
+            QueryKind::Substring(s) if self.ignore_case => text.to_lowercase().contains(s.as_str()),
+            QueryKind::Substring(s) => text.contains(s.as_str()),
+            QueryKind::Pattern(regex) => regex.is_match(text),
+        }
+    }
+
+    /// Text that every matching path contains, used to narrow search with suffix array.
+    fn required_text(&self) -> Option<&str> {
+        match &self.kind {
+            QueryKind::Substring(s) if !self.ignore_case && !s.is_empty() => Some(s),
+            _ => None,
+        }
+    }
+}
+
+/// Convert shell like pattern to anchored regular expression.
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    regex.push_str("(?:.*/)?");
+                } else {
+                    regex.push_str(".*");
+                }
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                let class = chars.by_ref().take_while(|c| *c != ']').collect::<String>();
+                let (negate, class) = match class.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, class.as_str()),
+                };
+                regex.push('[');
+                if negate {
+                    regex.push('^');
+                }
+                push_class(&mut regex, class);
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Push members of glob character class, escaping characters that have special meaning in regex classes: nested
+/// classes, escapes and set operations `&&`, `--` and `~~`. Single `-` stays a range.
+fn push_class(regex: &mut String, class: &str) {
+    let chars = class.chars().collect::<Vec<_>>();
+    for (i, &c) in chars.iter().enumerate() {
+        let doubled = (i > 0 && chars[i - 1] == c) || chars.get(i + 1) == Some(&c);
+        match c {
+            '\\' | '[' | '&' | '~' => regex.push('\\'),
+            '-' if doubled => regex.push('\\'),
+            _ => {}
+        }
+        regex.push(c);
+    }
+}
+
+/// File found by [`NameSearch`].
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct SearchHit {
+    /// Root relative path
+    pub path: PathBuf,
+    pub id: HashArray<32>,
+    /// Content hash, when snapshot has hash for the name
+    pub data: Option<HashArray<32>>,
+}
+
+/// Search of files in snapshot by their names, without access to scanned files. Snapshot with suffix array, see
+/// [`Snapshot::with_suffixes`], is searched faster for substring queries.
+pub struct NameSearch<'a, D: Digest<OutputSize = U32>> {
+    snapshot: &'a Snapshot,
+    entries: EntriesByName<'a>,
+    /// Prefix name ids of snapshot were derived with
+    prefix: String,
+    /// Display paths, when snapshot has no suffix array, or when it was built for other names
+    paths: Vec<String>,
+    _digest: PhantomData<D>,
+}
+
+impl<'a, D: Digest<OutputSize = U32>> NameSearch<'a, D> {
+    /// Search in snapshot with root relative names, that were hashed with given prefix.
+    pub fn new(snapshot: &'a Snapshot, prefix: &str) -> io::Result<Self> {
+        let hashes = &snapshot.hashes;
+        if !hashes.root_relative {
+            return Err(Error::new(ErrorKind::InvalidInput, "Searched snapshot has no root relative names"));
+        }
+        if !hashes.matches_prefix(prefix) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Searched snapshot names were hashed with other prefix",
+            ));
+        }
+        if hashes.key_id.is_some() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Searched snapshot names were hashed with key"));
+        }
+        let names = &snapshot.names;
+        let paths = if snapshot.suffixes.is_built_for(names) {
+            Vec::new()
+        } else {
+            names.indexes().iter().map(|&at| names.bungee().path_of("/", at)).collect()
+        };
+        Ok(Self {
+            snapshot,
+            entries: EntriesByName::new(hashes),
+            prefix: prefix.to_string(),
+            paths,
+            _digest: PhantomData,
+        })
+    }
+
+    fn path(&self, ordinal: usize) -> &str {
+        match self.paths.get(ordinal) {
+            Some(path) => path,
+            None => self.snapshot.suffixes.path(ordinal),
+        }
+    }
+
+    /// Files matching all queries, in order of names chunk.
+    pub fn find(&self, queries: &[NameQuery]) -> Vec<SearchHit> {
+        let count = self.snapshot.names.indexes().len();
+        let candidates = match queries.iter().filter_map(|q| q.required_text()).max_by_key(|s| s.len()) {
+            Some(text) if self.paths.is_empty() => self.snapshot.suffixes.find(text),
+            _ => (0..count).collect(),
+        };
+        candidates
+            .into_iter()
+            .filter(|&i| queries.iter().all(|q| q.matches(self.path(i))))
+            .map(|i| self.hit(i))
+            .collect()
+    }
+
+    fn hit(&self, ordinal: usize) -> SearchHit {
+        let names = &self.snapshot.names;
+        let path = names.bungee().os_path_of(names.indexes()[ordinal]);
+        let id = path_id::<D>(self.snapshot.hashes.identity, &self.prefix, &path);
+        let data = self.entries.get(&id).map(|e| e.data);
+        SearchHit { path, id, data }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::snapshot_of;
+    use crate::PathIdentity;
+    use sha2::Sha256;
+    use std::path::Path;
+
+    #[test]
+    fn test_glob() {
+        let matches = |glob: &str, path: &str| Regex::new(&glob_to_regex(glob)).unwrap().is_match(path);
+        assert!(matches("*.psd", "final.psd"));
+        assert!(!matches("*.psd", "art/final.psd"));
+        assert!(!matches("*.psd", "final.psd.bak"));
+        assert!(matches("**/*.psd", "final.psd"));
+        assert!(matches("**/*.psd", "art/2020/final.psd"));
+        assert!(matches("art/**", "art/2020/final.psd"));
+        assert!(matches("file?.[ch]", "file1.c"));
+        assert!(!matches("file?.[!ch]", "file1.c"));
+        assert!(matches("a+b(1).txt", "a+b(1).txt"));
+
+        //class set operations of regex are plain characters in globs
+        assert!(matches("[a&&b]", "&"));
+        assert!(!matches("[a&&b]", "c"));
+        assert!(matches("[a--b]", "-"));
+        assert!(matches("[~~]", "~"));
+        assert!(matches("[a-c]", "b"));
+        assert!(matches("[\\[]", "["));
+
+        let query = NameQuery::glob("*.PSD").unwrap().with_ignore_case().unwrap();
+        assert!(query.matches("art/final.psd"));
+        assert_eq!(NameQuery::glob("art/*.psd").unwrap().target(), QueryTarget::Path);
+        assert!(NameQuery::substring("Final").with_ignore_case().unwrap().matches("art/final.psd"));
+        assert!(!NameQuery::substring("art").matches("art/final.psd"));
+        assert!(NameQuery::substring("art").with_target(QueryTarget::Path).matches("art/final.psd"));
+    }
+
+    #[test]
+    fn test_snapshot_search() {
+        let plain = snapshot_of(
+            PathIdentity::EXACT,
+            &[
+                ("art/final.psd", b"1"),
+                ("art/final_v2.PSD", b"2"),
+                ("art/draft.psd", b"3"),
+                ("final/notes.txt", b"4"),
+            ],
+        );
+        let mut bytes = Vec::new();
+        plain.with_suffixes().unwrap().write(&mut bytes).unwrap();
+        let indexed = Snapshot::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(indexed.suffixes.len(), 4);
+
+        for snapshot in [&indexed, &snapshot_of(PathIdentity::EXACT, &[])] {
+            let search = NameSearch::<Sha256>::new(snapshot, "").unwrap();
+            assert!(search.find(&[NameQuery::substring("zzz")]).is_empty());
+        }
+        let search = NameSearch::<Sha256>::new(&indexed, "").unwrap();
+        let queries = [
+            NameQuery::glob("*.psd").unwrap().with_ignore_case().unwrap(),
+            NameQuery::substring("final"),
+        ];
+        let mut hits = search.find(&queries);
+        hits.sort_by(|a, b| a.path.cmp(&b.path));
+        let paths = hits.iter().map(|h| h.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths, [PathBuf::from("art/final.psd"), PathBuf::from("art/final_v2.PSD")]);
+        assert_eq!(hits[0].data, Some(HashArray::new(Sha256::digest(b"1").into())));
+        assert!(indexed.hashes.data.iter().any(|e| e.id == hits[1].id));
+
+        let regex = search.find(&[NameQuery::regex(r"^final/.*\.txt$").unwrap()]);
+        assert_eq!(regex.len(), 1);
+        assert_eq!(regex[0].path, Path::new("final/notes.txt"));
+
+        let mut resorted = indexed.clone();
+        resorted.hashes.sort_by_data();
+        let hits = NameSearch::<Sha256>::new(&resorted, "")
+            .unwrap()
+            .find(&[NameQuery::substring("notes")]);
+        assert_eq!(hits[0].data, Some(HashArray::new(Sha256::digest(b"4").into())));
+        assert!(NameSearch::<Sha256>::new(&indexed, "other").is_err());
+        let mut keyed = indexed.clone();
+        keyed.hashes.key_id = Some(7);
+        assert!(NameSearch::<Sha256>::new(&keyed, "").is_err());
+    }
+
+    #[test]
+    fn test_stale_suffixes() {
+        let files: &[(&str, &[u8])] = &[("a/first.txt", b"1"), ("b/second.txt", b"2")];
+        let mut snapshot = snapshot_of(PathIdentity::EXACT, files).with_suffixes().unwrap();
+        //names replaced with the same number of other names, suffixes still describe old paths
+        snapshot.names = snapshot_of(PathIdentity::EXACT, &[("c/third.txt", b"3"), ("d/fourth.txt", b"4")]).names;
+        assert_eq!(snapshot.suffixes.len(), snapshot.names.indexes().len());
+        let search = NameSearch::<Sha256>::new(&snapshot, "").unwrap();
+        let hits = search.find(&[NameQuery::substring("third")]);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].path, Path::new("c/third.txt"));
+        assert!(search.find(&[NameQuery::substring("first")]).is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::utils::{escape_name, os_str_bytes};
 use cfg_if::cfg_if;
 use std::borrow::Cow;
 use std::char::decode_utf16;
@@ -6,10 +7,6 @@ use std::marker::PhantomData;
 use std::num::NonZeroUsize;
 
 pub fn convert_to_meaningful_str(os: &OsStr) -> Cow<'_, str> {
-    if let Some(s) = os.to_str() {
-        return Cow::Borrowed(s);
-    }
-
     cfg_if! {
         if #[cfg(windows)] {
 
@@ -26,8 +23,8 @@ pub fn convert_to_meaningful_str(os: &OsStr) -> Cow<'_, str> {
         }
     }
 
-    //when all else fails
-    os.to_string_lossy()
+    //when all else fails, escape invalid bytes and backslashes so that different names never look the same
+    escape_name(os_str_bytes(os))
 }
 
 ///Relative reference, where we can express self-referent struct with an offset, or global reference
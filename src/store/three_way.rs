@@ -0,0 +1,217 @@
+use crate::store::NamedValue;
+use std::mem::replace;
+
+/// How an entry changed on two sides relative to common base.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum MergeType {
+    /// Both sides are the same as base
+    Unchanged,
+    /// Only left side differs from base
+    Left,
+    /// Only right side differs from base
+    Right,
+    /// Both sides differ from base, but are equal
+    Both,
+    /// Both sides differ from base and from each other
+    Conflict,
+}
+
+/// Entry of one name in base and both sides, missing entry means that name is not present on that side.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct MergeEntry<E> {
+    pub base: Option<E>,
+    pub left: Option<E>,
+    pub right: Option<E>,
+}
+
+impl<E: NamedValue> MergeEntry<E> {
+    pub fn merge_type(&self) -> MergeType {
+        let same = |a: &Option<E>, b: &Option<E>| match (a, b) {
+            (Some(a), Some(b)) => a.get_value() == b.get_value(),
+            (None, None) => true,
+            _ => false,
+        };
+        match (same(&self.base, &self.left), same(&self.base, &self.right)) {
+            (true, true) => MergeType::Unchanged,
+            (false, true) => MergeType::Left,
+            (true, false) => MergeType::Right,
+            (false, false) if same(&self.left, &self.right) => MergeType::Both,
+            (false, false) => MergeType::Conflict,
+        }
+    }
+
+    pub fn get_name(&self) -> &E::Name {
+        self.base
+            .as_ref()
+            .or(self.left.as_ref())
+            .or(self.right.as_ref())
+            .map(|e| e.get_name())
+            .expect("merge entry is never empty")
+    }
+
+    /// Entry that both sides should have after reconciliation, `None` for conflicts. Inner `None` means that
+    /// name should be removed.
+    pub fn resolved(&self) -> Option<Option<&E>> {
+        match self.merge_type() {
+            MergeType::Unchanged | MergeType::Right => Some(self.right.as_ref()),
+            MergeType::Left | MergeType::Both => Some(self.left.as_ref()),
+            MergeType::Conflict => None,
+        }
+    }
+}
+
+/// Merges base and two descendant streams sorted by name, like [`crate::store::DiffingIter`] does for two.
+pub struct ThreeWayIter<B, L, R>
+where
+    B: Iterator,
+    L: Iterator<Item = B::Item>,
+    R: Iterator<Item = B::Item>,
+    B::Item: NamedValue,
+{
+    base: B,
+    left: L,
+    right: R,
+    curr_base: Option<B::Item>,
+    curr_left: Option<B::Item>,
+    curr_right: Option<B::Item>,
+}
+
+impl<B, L, R> ThreeWayIter<B, L, R>
+where
+    B: Iterator,
+    L: Iterator<Item = B::Item>,
+    R: Iterator<Item = B::Item>,
+    B::Item: NamedValue,
+{
+    pub fn new(mut base: B, mut left: L, mut right: R) -> Self {
+        Self {
+            curr_base: base.next(),
+            curr_left: left.next(),
+            curr_right: right.next(),
+            base,
+            left,
+            right,
+        }
+    }
+}
+
+impl<B, L, R> Iterator for ThreeWayIter<B, L, R>
+where
+    B: Iterator,
+    L: Iterator<Item = B::Item>,
+    R: Iterator<Item = B::Item>,
+    B::Item: NamedValue,
+{
+    type Item = MergeEntry<B::Item>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let min = [&self.curr_base, &self.curr_left, &self.curr_right]
+            .into_iter()
+            .flatten()
+            .map(|e| e.get_name())
+            .min()?;
+        let take = [&self.curr_base, &self.curr_left, &self.curr_right].map(|e| e.as_ref().is_some_and(|e| e.get_name() == min));
+        let base = if take[0] {
+            replace(&mut self.curr_base, self.base.next())
+        } else {
+            None
+        };
+        let left = if take[1] {
+            replace(&mut self.curr_left, self.left.next())
+        } else {
+            None
+        };
+        let right = if take[2] {
+            replace(&mut self.curr_right, self.right.next())
+        } else {
+            None
+        };
+        Some(MergeEntry { base, left, right })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let hints = [self.base.size_hint(), self.left.size_hint(), self.right.size_hint()];
+        let current = [self.curr_base.is_some(), self.curr_left.is_some(), self.curr_right.is_some()];
+        let lower = hints
+            .iter()
+            .zip(current)
+            .map(|((lower, _), curr)| lower + curr as usize)
+            .max()
+            .unwrap_or(0);
+        let upper = hints.iter().zip(current).try_fold(0usize, |sum, ((_, upper), curr)| {
+            sum.checked_add(upper.as_ref()?.checked_add(curr as usize)?)
+        });
+        (lower, upper)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{HashArray, HashEntry};
+
+    fn mock_entry(id: &str, data: &str) -> HashEntry<32, 32> {
+        HashEntry {
+            id: HashArray::parse_fill_zero(id),
+            data: HashArray::parse_fill_zero(data),
+        }
+    }
+
+    #[test]
+    fn test_three_way() {
+        let base = [
+            mock_entry("01", "11"),
+            mock_entry("02", "12"),
+            mock_entry("03", "13"),
+            mock_entry("04", "14"),
+            mock_entry("05", "15"),
+            mock_entry("06", "16"),
+        ];
+        let left = [
+            mock_entry("01", "11"),
+            mock_entry("02", "22"),
+            mock_entry("03", "13"),
+            mock_entry("04", "24"),
+            mock_entry("05", "25"),
+            mock_entry("07", "17"),
+        ];
+        let right = [
+            mock_entry("01", "11"),
+            mock_entry("02", "12"),
+            mock_entry("03", "33"),
+            mock_entry("04", "24"),
+            mock_entry("05", "35"),
+            mock_entry("07", "37"),
+        ];
+        let merged = ThreeWayIter::new(base.iter(), left.iter(), right.iter()).collect::<Vec<_>>();
+        let types = merged.iter().map(|m| m.merge_type()).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                MergeType::Unchanged,
+                MergeType::Left,
+                MergeType::Right,
+                MergeType::Both,
+                MergeType::Conflict,
+                //removed on both sides
+                MergeType::Both,
+                //added on both sides with different content
+                MergeType::Conflict,
+            ]
+        );
+        assert_eq!(merged[1].resolved(), Some(Some(&&left[1])));
+        assert_eq!(merged[2].resolved(), Some(Some(&&right[2])));
+        assert_eq!(merged[5].resolved(), Some(None));
+        assert_eq!(merged[4].resolved(), None);
+        assert_eq!(merged[6].get_name(), &left[5].id);
+    }
+
+    #[test]
+    fn test_three_way_one_side() {
+        let base = [mock_entry("01", "11"), mock_entry("02", "12")];
+        let merged = ThreeWayIter::new(base.iter(), base.iter(), [].iter()).collect::<Vec<_>>();
+        assert_eq!(merged.len(), 2);
+        assert!(merged.iter().all(|m| m.merge_type() == MergeType::Right));
+        assert!(merged.iter().all(|m| m.resolved() == Some(None)));
+    }
+}
@@ -0,0 +1,232 @@
+use crate::file::chunks::{HashesChunk, NamesChunk, TreeChunk};
+use crate::file::Snapshot;
+use crate::store::{hash_of, name_id, DiffResult, DiffType, DiffingIter, EntriesByName};
+use crate::utils::os_str_bytes;
+use crate::{relative_components, DataEntry, HashArray, HashEntry};
+use digest::consts::U32;
+use digest::Digest;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+
+const KIND_FILE: u8 = b'f';
+const KIND_DIR: u8 = b'd';
+
+/// Name bytes of children with their kind and hash
+type Children = BTreeMap<Vec<u8>, (u8, HashArray<32>)>;
+
+/// Compute aggregate hash of every directory that contains hashed files. Aggregate is a hash over children sorted by
+/// name bytes, every child is written as kind byte, name length (u64 LE), name bytes and its content or aggregate
+/// hash, so identical subtrees get the same aggregate wherever they are placed. Directory ids are name hashes of their
+/// root relative paths, computed the same way as file ids.
+pub fn build_tree<D: Digest<OutputSize = U32>>(hashes: &HashesChunk, names: &NamesChunk, prefix: &str) -> TreeChunk {
+    let identity = hashes.identity;
+    let lookup = EntriesByName::new(hashes);
+    let mut dirs: BTreeMap<Vec<OsString>, Children> = BTreeMap::new();
+    dirs.insert(Vec::new(), BTreeMap::new());
+    for &at in names.indexes() {
+        let path = names.bungee().os_path_of(at);
+        let components = relative_components(None, &path).collect::<Vec<_>>();
+        let id = name_id::<D>(identity, prefix, components.iter().copied());
+        let (Some(entry), Some((name, parent))) = (lookup.get(&id), components.split_last()) else {
+            continue; //file that couldn't be read has no hash
+        };
+        let parent = parent.iter().map(|c| c.to_os_string()).collect::<Vec<_>>();
+        for depth in 0..parent.len() {
+            dirs.entry(parent[..depth].to_vec()).or_default();
+        }
+        dirs.entry(parent)
+            .or_default()
+            .insert(os_str_bytes(name).to_vec(), (KIND_FILE, entry.data));
+    }
+
+    //children are always deeper than parents, so processing by depth makes all children ready before parent
+    let mut order = dirs.keys().cloned().collect::<Vec<_>>();
+    order.sort_by_key(|k| Reverse(k.len()));
+    let mut entries = Vec::with_capacity(order.len());
+    let mut root = HashArray::zero();
+    for key in order {
+        let children = dirs.remove(&key).unwrap_or_default();
+        let aggregate = hash_of::<D>(|h| {
+            for (name, (kind, hash)) in &children {
+                h.update([*kind]);
+                h.update((name.len() as u64).to_le_bytes());
+                h.update(name);
+                h.update(hash.get_ref());
+            }
+        });
+        let id = name_id::<D>(identity, prefix, key.iter().map(|c| c.as_os_str()));
+        entries.push(HashEntry { id, data: aggregate });
+        match key.split_last() {
+            Some((name, parent)) => {
+                if let Some(parent) = dirs.get_mut(parent) {
+                    parent.insert(os_str_bytes(name).to_vec(), (KIND_DIR, aggregate));
+                }
+            }
+            None => root = id,
+        }
+    }
+    TreeChunk::new(root, entries).with_identity(identity, prefix)
+}
+
+/// Directories that differ between two trees, changed directories are listed first, then removed and added ones.
+/// When root aggregates are equal, trees are identical and no entries are compared. Both trees need to be present and
+/// built with the same identity and prefix, otherwise their directories can't be compared.
+pub fn changed_dirs<'a>(old: &'a TreeChunk, new: &'a TreeChunk) -> io::Result<Vec<DiffResult<&'a DataEntry>>> {
+    if old.is_empty() || new.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "Snapshot has no directory tree"));
+    }
+    if !old.is_comparable(new) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "Directory trees were built with different identity or prefix",
+        ));
+    }
+    if old.root_hash().is_some() && old.root_hash() == new.root_hash() {
+        return Ok(Vec::new());
+    }
+    let mut diff = DiffingIter::new(old.dirs.iter(), new.dirs.iter())
+        .filter(|d| d.diff_type() != DiffType::Same)
+        .collect::<Vec<_>>();
+    diff.sort_by_key(|d| match d.diff_type() {
+        DiffType::Changed => 0,
+        DiffType::Removed => 1,
+        _ => 2,
+    });
+    Ok(diff)
+}
+
+/// Entries of files placed directly in given directories, directories not in set are skipped without hashing names
+/// of their files.
+fn files_in_dirs<'a, D: Digest<OutputSize = U32>>(
+    snapshot: &'a Snapshot,
+    dirs: &HashSet<HashArray<32>>,
+    prefix: &str,
+) -> Vec<&'a DataEntry> {
+    let identity = snapshot.hashes.identity;
+    let lookup = EntriesByName::new(&snapshot.hashes);
+    let mut last_parent: Option<(PathBuf, bool)> = None;
+    let mut files = Vec::new();
+    for &at in snapshot.names.indexes() {
+        let path = snapshot.names.bungee().os_path_of(at);
+        let parent = path.parent().unwrap_or(Path::new(""));
+        let included = match &last_parent {
+            //files of the same directory are stored together, so parent id is computed once per directory
+            Some((p, included)) if p == parent => *included,
+            _ => {
+                let included = dirs.contains(&name_id::<D>(identity, prefix, relative_components(None, parent)));
+                last_parent = Some((parent.to_path_buf(), included));
+                included
+            }
+        };
+        if included {
+            files.extend(lookup.get(&name_id::<D>(identity, prefix, relative_components(None, &path))));
+        }
+    }
+    files.sort_unstable();
+    files.dedup();
+    files
+}
+
+/// Diff of files, that visits only directories with changed aggregate hash, identical subtrees are skipped.
+/// Snapshots without tree chunk, see [`build_tree`], are diffed by all their entries. Both snapshots need to be hashed
+/// with the same identity, and their trees need to be built with given prefix.
+pub fn diff_changed_subtrees<'a, D: Digest<OutputSize = U32>>(
+    old: &'a Snapshot,
+    new: &'a Snapshot,
+    prefix: &str,
+) -> io::Result<Vec<DiffResult<&'a DataEntry>>> {
+    if old.hashes.identity != new.hashes.identity {
+        return Err(Error::new(ErrorKind::InvalidInput, "Snapshots were hashed with different identity"));
+    }
+    if old.tree.is_empty() || new.tree.is_empty() {
+        let (old, new) = (EntriesByName::new(&old.hashes), EntriesByName::new(&new.hashes));
+        return Ok(DiffingIter::new(old.iter(), new.iter())
+            .filter(|d| d.diff_type() != DiffType::Same)
+            .collect());
+    }
+    let prefix_id = old.hashes.identity.prefix_id(prefix);
+    if old.tree.prefix_id != prefix_id || new.tree.prefix_id != prefix_id {
+        return Err(Error::new(ErrorKind::InvalidInput, "Directory tree was built with other prefix"));
+    }
+    let dirs = changed_dirs(&old.tree, &new.tree)?
+        .iter()
+        .map(|d| *d.get_name())
+        .collect::<HashSet<_>>();
+    if dirs.is_empty() {
+        return Ok(Vec::new());
+    }
+    let old_files = files_in_dirs::<D>(old, &dirs, prefix);
+    let new_files = files_in_dirs::<D>(new, &dirs, prefix);
+    Ok(DiffingIter::new(old_files.into_iter(), new_files.into_iter())
+        .filter(|d| d.diff_type() != DiffType::Same)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::snapshot_of;
+    use crate::PathIdentity;
+    use sha2::Sha256;
+
+    fn files(changed: &'static [u8]) -> [(&'static str, &'static [u8]); 6] {
+        [
+            ("a/b/deep", changed),
+            ("a/file", b"1"),
+            ("same/x", b"2"),
+            ("same/y/z", b"3"),
+            ("copy/x", b"2"),
+            ("copy/y/z", b"3"),
+        ]
+    }
+
+    #[test]
+    fn test_snapshot_tree() {
+        let old = snapshot_of(PathIdentity::EXACT, &files(b"old"));
+        let mut bytes = Vec::new();
+        old.write(&mut bytes).unwrap();
+        let old = Snapshot::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(old.tree.dirs.len(), 7);
+        assert_eq!(
+            old.tree.root_hash(),
+            snapshot_of(PathIdentity::EXACT, &files(b"old")).tree.root_hash()
+        );
+        let dir = |name: &str| old.tree.get(&HashArray::new(Sha256::digest(name).into())).copied();
+        assert_eq!(dir("same"), dir("copy"));
+        assert_ne!(dir("same"), dir("a"));
+        let mut resorted = snapshot_of(PathIdentity::EXACT, &files(b"old"));
+        resorted.hashes.sort_by_data();
+        assert_eq!(build_tree::<Sha256>(&resorted.hashes, &resorted.names, ""), old.tree);
+
+        let new = snapshot_of(PathIdentity::EXACT, &files(b"new"));
+        assert_ne!(old.tree.root_hash(), new.tree.root_hash());
+        let dirs = changed_dirs(&old.tree, &new.tree).unwrap();
+        assert_eq!(dirs.len(), 3);
+        assert!(dirs.iter().all(|d| d.diff_type() == DiffType::Changed));
+        let diff = diff_changed_subtrees::<Sha256>(&old, &new, "").unwrap();
+        assert_eq!(diff.len(), 1);
+        assert_eq!(*diff[0].get_name(), HashArray::new(Sha256::digest("a/b/deep").into()));
+        assert!(diff_changed_subtrees::<Sha256>(&old, &old, "").unwrap().is_empty());
+        assert!(diff_changed_subtrees::<Sha256>(&old, &new, "other").is_err());
+    }
+
+    #[test]
+    fn test_diff_without_tree() {
+        let old = snapshot_of(PathIdentity::EXACT, &files(b"old"));
+        let mut new = snapshot_of(PathIdentity::EXACT, &files(b"new"));
+        new.tree = Default::default();
+        assert!(changed_dirs(&old.tree, &new.tree).is_err());
+        let diff = diff_changed_subtrees::<Sha256>(&old, &new, "").unwrap();
+        assert_eq!(diff.len(), 1);
+        assert_eq!(diff[0].diff_type(), DiffType::Changed);
+        assert_eq!(*diff[0].get_name(), HashArray::new(Sha256::digest("a/b/deep").into()));
+
+        let mut other = snapshot_of(PathIdentity::EXACT, &files(b"new"));
+        other.tree = build_tree::<Sha256>(&other.hashes, &other.names, "other");
+        assert!(changed_dirs(&old.tree, &other.tree).is_err());
+    }
+}
@@ -1,10 +1,15 @@
-use crate::utils::MeasureMemory;
+use crate::utils::{escape_name, os_str_bytes, os_str_from_bytes, MeasureMemory};
 use compact_str::CompactString;
-use std::io::Read;
-use std::iter::repeat;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::iter::repeat_n;
 use std::marker::PhantomData;
 use std::mem::size_of;
-use std::num::NonZeroUsize;
+use std::num::TryFromIntError;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::ops::Range;
 use std::ops::{Add, Sub};
 use std::path::PathBuf;
 use std::str::from_utf8;
@@ -30,16 +35,24 @@ pub struct BungeeIndex {
 
 pub trait OffsetInt: Copy + Eq {
     const MAX_BYTES: usize;
-    ///read value in reverse (starting from last byte in slice), returns value and number of bytes read
-    fn reverse_read(data: &[u8]) -> (Self, usize);
-    ///read value in regular order, returns value and number of bytes read
-    fn read(data: &[u8]) -> (Self, usize);
+    ///read value in reverse (starting from last byte in slice), returns value and number of bytes read, `None` when
+    ///slice doesn't end with valid value
+    fn reverse_read(data: &[u8]) -> Option<(Self, usize)>;
+    ///read value in regular order, returns value and number of bytes read, `None` when slice doesn't start with valid
+    ///value
+    fn read(data: &[u8]) -> Option<(Self, usize)>;
     fn write(self, data: &mut [u8]) -> usize;
 
     fn from_usize(val: usize) -> Self;
     fn to_usize(self) -> usize;
 }
 
+impl<T: OffsetInt> Default for BungeeBytes<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: OffsetInt> BungeeBytes<T> {
     pub const fn new() -> Self {
         Self {
@@ -50,13 +63,13 @@ impl<T: OffsetInt> BungeeBytes<T> {
 
     fn ensure_space(&mut self, space: usize) -> &mut [u8] {
         let pos = self.data.len();
-        self.data.extend(repeat(0u8).take(space));
+        self.data.extend(repeat_n(0u8, space));
         &mut self.data[pos..]
     }
 
     fn with_ensured_space(&mut self, max_space: usize, func: impl FnOnce(&mut [u8]) -> usize) -> usize {
         let pos = self.data.len();
-        self.data.extend(repeat(0u8).take(max_space));
+        self.data.extend(repeat_n(0u8, max_space));
         let count = func(&mut self.data[pos..]);
         let pos = pos + count;
         self.data.truncate(pos);
@@ -71,32 +84,80 @@ impl<T: OffsetInt> BungeeBytes<T> {
         self.data.as_slice()
     }
 
-    fn reverse_read(&self, at: BungeeIndex) -> (&[u8], Option<BungeeIndex>, Option<BungeeIndex>) {
-        let mut slice = &self.data.as_slice()[..at.index.get()];
-        let (data_len, count) = T::reverse_read(slice);
-        let data_len = data_len.to_usize();
-        let data_range = {
-            let end = slice.len() - count;
-            (end - data_len)..end
-        };
-        let (prev_index, count) = T::reverse_read(&slice[..data_range.start]);
-        let skip = data_range.start - count;
+    /// Checked read of entry ending at given position, returns range of its data, its flag, end of entry pushed
+    /// before it and end of its parent entry (zero when there is none). `None` when bytes are not a valid entry.
+    fn try_reverse_read(&self, at: usize) -> Option<(Range<usize>, bool, usize, usize)> {
+        let slice = self.data.get(..at)?;
+        let (data_len, count) = T::reverse_read(slice)?;
+        //lowest bit of length field is a flag of entry
+        let flag = data_len.to_usize() & 1 != 0;
+        let data_len = data_len.to_usize() >> 1;
+        let end = at - count;
+        let start = end.checked_sub(data_len)?;
+        let (prev_index, count) = T::reverse_read(&slice[..start])?;
+        let skip = start - count;
+        let prev = skip.checked_sub(prev_index.to_usize())?;
+        Some((start..end, flag, skip, prev))
+    }
+
+    fn reverse_read(&self, at: BungeeIndex) -> (&[u8], bool, Option<BungeeIndex>, Option<BungeeIndex>) {
+        //tables are validated when created from raw bytes, so only index that is not an entry fails here
+        let (data_range, flag, skip, prev) = self.try_reverse_read(at.index.get()).expect("Index is not an entry of names table");
         let skip_pos = NonZeroUsize::new(skip).map(|index| BungeeIndex { index });
-        let prev_pos = NonZeroUsize::new(skip - prev_index.to_usize()).map(|index| BungeeIndex { index });
-        (&slice[data_range], skip_pos, prev_pos)
+        let prev_pos = NonZeroUsize::new(prev).map(|index| BungeeIndex { index });
+        (&self.data[data_range], flag, skip_pos, prev_pos)
+    }
+
+    /// Table of given raw bytes, checked that every entry can be read and that its parent is an entry pushed before
+    /// it. Ends of all entries are returned, so indexes into table can be validated too.
+    fn from_raw_bytes(data: Vec<u8>) -> io::Result<(Self, BungeeEnds)> {
+        let table = Self {
+            data,
+            _phantom: PhantomData,
+        };
+        let broken = || io::Error::new(io::ErrorKind::InvalidData, "Names table is corrupted");
+        let mut ends = BungeeEnds {
+            bits: vec![0; table.data.len() / 64 + 1],
+        };
+        let mut at = table.data.len();
+        while at > 0 {
+            let (_, _, skip, _) = table.try_reverse_read(at).ok_or_else(broken)?;
+            ends.bits[at / 64] |= 1 << (at & 63);
+            at = skip;
+        }
+        //parents can be checked only when all entries are known
+        let mut at = table.data.len();
+        while at > 0 {
+            let (_, _, skip, prev) = table.try_reverse_read(at).ok_or_else(broken)?;
+            if prev != 0 && !ends.contains(prev) {
+                return Err(broken());
+            }
+            at = skip;
+        }
+        Ok((table, ends))
     }
 
     pub fn reverse_skip(&self, at: BungeeIndex) -> (&[u8], Option<BungeeIndex>) {
-        let (data, skip, _prev) = self.reverse_read(at);
+        let (data, _flag, skip, _prev) = self.reverse_read(at);
         (data, skip)
     }
 
     pub fn reverse_follow(&self, at: BungeeIndex) -> (&[u8], Option<BungeeIndex>) {
-        let (data, _skip, prev) = self.reverse_read(at);
+        let (data, _flag, _skip, prev) = self.reverse_read(at);
         (data, prev)
     }
 
-    pub fn reverse_follow_iter(&self, at: BungeeIndex) -> BungeeFollowIter<T> {
+    /// Same as [`Self::reverse_follow`], but also returns flag that was stored with entry.
+    pub fn reverse_follow_flagged(&self, at: BungeeIndex) -> (&[u8], bool, Option<BungeeIndex>) {
+        let (data, flag, _skip, prev) = self.reverse_read(at);
+        (data, flag, prev)
+    }
+
+    pub fn is_flagged(&self, at: BungeeIndex) -> bool {
+        self.reverse_read(at).1
+    }
+
+    pub fn reverse_follow_iter(&self, at: BungeeIndex) -> BungeeFollowIter<'_, T> {
         BungeeFollowIter {
             parent: self,
             last: Some(at),
@@ -104,6 +165,11 @@ impl<T: OffsetInt> BungeeBytes<T> {
     }
 
     pub fn push(&mut self, prev: Option<BungeeIndex>, data: &[u8]) -> Option<BungeeIndex> {
+        self.push_flagged(prev, data, false)
+    }
+
+    /// Push entry with additional flag bit, that is stored in lowest bit of length field.
+    pub fn push_flagged(&mut self, prev: Option<BungeeIndex>, data: &[u8], flag: bool) -> Option<BungeeIndex> {
         if data.is_empty() {
             return prev;
         }
@@ -122,8 +188,8 @@ impl<T: OffsetInt> BungeeBytes<T> {
             //write stored data
             slice[..data.len()].copy_from_slice(data);
             count += data.len();
-            //write length of stored data
-            let len = T::from_usize(data.len()).write(&mut slice[data.len()..]);
+            //write length of stored data together with flag
+            let len = T::from_usize((data.len() << 1) | flag as usize).write(&mut slice[data.len()..]);
             count += len;
             count
         });
@@ -147,15 +213,15 @@ macro_rules! impl_fixed_int {
         impl OffsetInt for FixedInt<$name> {
             const MAX_BYTES: usize = size_of::<$name>();
 
-            fn reverse_read(data: &[u8]) -> (Self, usize) {
-                let len = data.len() - Self::MAX_BYTES;
+            fn reverse_read(data: &[u8]) -> Option<(Self, usize)> {
+                let len = data.len().checked_sub(Self::MAX_BYTES)?;
                 let arr: [u8;Self::MAX_BYTES] = data[len..].try_into().unwrap();
-                (Self($name::from_le_bytes(arr)),Self::MAX_BYTES)
+                Some((Self($name::from_le_bytes(arr)),Self::MAX_BYTES))
             }
 
-            fn read(data: &[u8]) -> (Self, usize) {
-                let arr: [u8;Self::MAX_BYTES] = data[..Self::MAX_BYTES].try_into().unwrap();
-                (Self($name::from_le_bytes(arr)),Self::MAX_BYTES)
+            fn read(data: &[u8]) -> Option<(Self, usize)> {
+                let arr: [u8;Self::MAX_BYTES] = data.get(..Self::MAX_BYTES)?.try_into().unwrap();
+                Some((Self($name::from_le_bytes(arr)),Self::MAX_BYTES))
             }
 
             fn write(self, data: &mut [u8]) -> usize {
@@ -172,8 +238,6 @@ macro_rules! impl_fixed_int {
 
 impl_fixed_int!(u8, u16, u32, u64, usize);
 
-type NameTy = usize;
-
 //Variable size integer, that can be read in reverse order
 // 1. 0b0xxx_xxxx - value of range from 0 to 127 (7 data bits)
 // 2. 0b1xxx_xxxx 0b1xxx_xxxx (two bytes, 14 data bits)
@@ -186,89 +250,117 @@ fn msb_bit(value: u8) -> bool {
 const DATA_MASK: u8 = 0x7f;
 const MSB_BIT: u8 = 0x80;
 
-//todo tests
-impl OffsetInt for VarInt<NameTy> {
-    const MAX_BYTES: usize = size_of::<NameTy>() + 1;
-
-    fn reverse_read(data: &[u8]) -> (Self, usize) {
-        //single byte
-        let d0 = data[data.len() - 1];
-        if !msb_bit(d0) {
-            return (Self(d0 as _), 1);
-        }
-        let mut value = (d0 & DATA_MASK) as NameTy;
-        //rest of the bytes, read until msb bit is set
-        let mut i = 1;
-        for &byte in data[..(data.len() - 1)].iter().rev() {
-            value = value.wrapping_shl(7);
-            value |= (byte & DATA_MASK) as NameTy;
-            i += 1;
-            if msb_bit(byte) {
-                break;
+macro_rules! impl_var_int {
+    ($($name:ident),*) => {
+        $(
+        impl OffsetInt for VarInt<$name> {
+            const MAX_BYTES: usize = $name::BITS.div_ceil(7) as usize;
+
+            fn reverse_read(data: &[u8]) -> Option<(Self, usize)> {
+                //single byte
+                let (&d0, rest) = data.split_last()?;
+                if !msb_bit(d0) {
+                    return Some((Self(d0 as _), 1));
+                }
+                let mut value = (d0 & DATA_MASK) as $name;
+                //rest of the bytes, read until msb bit is set
+                for (i, &byte) in rest.iter().rev().enumerate().take(Self::MAX_BYTES - 1) {
+                    if value.leading_zeros() < 7 {
+                        return None; //value doesn't fit
+                    }
+                    value = (value << 7) | (byte & DATA_MASK) as $name;
+                    if msb_bit(byte) {
+                        return Some((Self(value), i + 2));
+                    }
+                }
+                None //value is not terminated
             }
-        }
-        (Self(value), i as usize)
-    }
 
-    fn read(data: &[u8]) -> (Self, usize) {
-        //single byte
-        let d0 = data[0];
-        if !msb_bit(d0) {
-            return (Self(d0 as _), 1);
-        }
-        let mut value = (d0 & DATA_MASK) as NameTy;
-        //rest of the bytes, read until msb bit is set
-        let mut i = 1;
-        for &byte in &data[1..] {
-            value |= ((byte & DATA_MASK) as NameTy).wrapping_shl(i * 7);
-            i += 1;
-            if msb_bit(byte) {
-                break;
+            fn read(data: &[u8]) -> Option<(Self, usize)> {
+                //single byte
+                let (&d0, rest) = data.split_first()?;
+                if !msb_bit(d0) {
+                    return Some((Self(d0 as _), 1));
+                }
+                let mut value = (d0 & DATA_MASK) as $name;
+                //rest of the bytes, read until msb bit is set
+                for (i, &byte) in rest.iter().enumerate().take(Self::MAX_BYTES - 1) {
+                    let part = (byte & DATA_MASK) as $name;
+                    let shift = (i as u32 + 1) * 7;
+                    if part.leading_zeros() < shift {
+                        return None; //value doesn't fit
+                    }
+                    value |= part << shift;
+                    if msb_bit(byte) {
+                        return Some((Self(value), i + 2));
+                    }
+                }
+                None //value is not terminated
             }
-        }
-        (Self(value), i as usize)
-    }
 
-    fn write(self, data: &mut [u8]) -> usize {
-        if self.0 <= 127 {
-            data[0] = self.0 as u8;
-            return 1;
-        }
-        let mut value = self.0;
-        let mut i = 0;
-        data[i] = (value as u8 & DATA_MASK) | MSB_BIT; //first byte with msb set
-        value = value.wrapping_shr(7);
-        loop {
-            i += 1;
-            let byte = value as u8 & DATA_MASK;
-            value = value.wrapping_shr(7);
-            if value == 0 {
-                //all data was written
-                data[i] = byte | MSB_BIT;
-                break i + 1;
-            } else {
-                data[i] = byte;
+            fn write(self, data: &mut [u8]) -> usize {
+                if self.0 <= 127 {
+                    data[0] = self.0 as u8;
+                    return 1;
+                }
+                let mut value = self.0;
+                let mut i = 0;
+                data[i] = (value as u8 & DATA_MASK) | MSB_BIT; //first byte with msb set
+                value = value.wrapping_shr(7);
+                loop {
+                    i += 1;
+                    let byte = value as u8 & DATA_MASK;
+                    value = value.wrapping_shr(7);
+                    if value == 0 {
+                        //all data was written
+                        data[i] = byte | MSB_BIT;
+                        break i + 1;
+                    } else {
+                        data[i] = byte;
+                    }
+                }
+            }
+            fn from_usize(val: usize) -> Self {
+                Self(val as _)
+            }
+            fn to_usize(self) -> usize {
+                self.0 as _
             }
         }
-    }
-    fn from_usize(val: usize) -> Self {
-        Self(val as _)
-    }
-    fn to_usize(self) -> usize {
-        self.0 as _
+        )*
     }
 }
 
+impl_var_int!(u32, usize);
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug)]
 pub struct BungeeStr {
     inner: BungeeBytes<VarInt<usize>>,
 }
 
+impl Default for BungeeStr {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BungeeStr {
     pub const fn new() -> Self {
         Self { inner: BungeeBytes::new() }
     }
 
+    /// Create names table from bytes previously obtained from [`Self::raw_bytes`], fails with
+    /// [`io::ErrorKind::InvalidData`] when bytes are not a valid table.
+    pub fn from_raw_bytes(data: Vec<u8>) -> io::Result<Self> {
+        Ok(Self::with_ends(data)?.0)
+    }
+
+    /// Same as [`Self::from_raw_bytes`], also returns ends of all entries to validate indexes read with table.
+    pub(crate) fn with_ends(data: Vec<u8>) -> io::Result<(Self, BungeeEnds)> {
+        let (inner, ends) = BungeeBytes::from_raw_bytes(data)?;
+        Ok((Self { inner }, ends))
+    }
+
     pub fn last_index(&self) -> Option<BungeeIndex> {
         self.inner.last_index()
     }
@@ -277,40 +369,63 @@ impl BungeeStr {
         self.inner.push(prev, data.as_bytes())
     }
 
-    pub fn reverse_skip(&self, at: BungeeIndex) -> (&str, Option<BungeeIndex>) {
-        let (data, skip) = self.inner.reverse_skip(at);
-        (from_utf8(data).unwrap(), skip)
+    /// Push os name without any lossy conversion, names that are not valid UTF-8 are stored as raw bytes with
+    /// entry flag set.
+    pub fn push_os(&mut self, prev: Option<BungeeIndex>, data: &OsStr) -> Option<BungeeIndex> {
+        match data.to_str() {
+            Some(s) => self.push(prev, s),
+            None => self.inner.push_flagged(prev, os_str_bytes(data), true),
+        }
+    }
+
+    /// Push name taken from other table, keeping it raw when it was raw.
+    pub fn push_name(&mut self, prev: Option<BungeeIndex>, name: BungeeName) -> Option<BungeeIndex> {
+        self.inner.push_flagged(prev, name.bytes, name.raw)
     }
 
-    pub fn reverse_follow(&self, at: BungeeIndex) -> (&str, Option<BungeeIndex>) {
-        let (data, prev) = self.inner.reverse_follow(at);
-        (from_utf8(data).unwrap(), prev)
+    pub fn reverse_skip(&self, at: BungeeIndex) -> (BungeeName<'_>, Option<BungeeIndex>) {
+        let (bytes, raw, skip, _prev) = self.inner.reverse_read(at);
+        (BungeeName { bytes, raw }, skip)
     }
 
-    pub fn reverse_follow_iter(&self, at: BungeeIndex) -> BungeeStrFollowIter {
+    pub fn reverse_follow(&self, at: BungeeIndex) -> (BungeeName<'_>, Option<BungeeIndex>) {
+        let (bytes, raw, prev) = self.inner.reverse_follow_flagged(at);
+        (BungeeName { bytes, raw }, prev)
+    }
+
+    pub fn reverse_follow_iter(&self, at: BungeeIndex) -> BungeeStrFollowIter<'_> {
         BungeeStrFollowIter {
-            inner: self.inner.reverse_follow_iter(at),
+            parent: self,
+            last: Some(at),
         }
     }
 
+    /// All entries from the last pushed to the first one, with index of their parent entry.
+    pub fn reverse_entries(&self) -> impl Iterator<Item = (BungeeName<'_>, BungeeIndex, Option<BungeeIndex>)> + '_ {
+        let mut last = self.last_index();
+        std::iter::from_fn(move || {
+            let at = last?;
+            let (bytes, raw, skip, prev) = self.inner.reverse_read(at);
+            last = skip;
+            Some((BungeeName { bytes, raw }, at, prev))
+        })
+    }
+
+    /// Display form of path, names that are not valid UTF-8 are escaped.
     pub fn path_of(&self, sep: &str, at: BungeeIndex) -> String {
-        let parts = self.reverse_follow_iter(at).map(|(s, _)| s).collect::<Vec<_>>();
-        let bytes: usize = parts.iter().map(|v| v.len()).sum();
-        let bytes = bytes + sep.len() * parts.len().saturating_sub(1);
-        let mut result = String::with_capacity(bytes);
-        let mut it = parts.into_iter().rev();
-        if let Some(v) = it.next() {
-            result.push_str(v);
-        }
-        for v in it {
-            result.push_str(sep);
-            result.push_str(v);
-        }
-        result
+        display_path(sep, self.reverse_follow_iter(at).map(|(s, _)| s))
+    }
+
+    /// Lossless path made of original os names.
+    pub fn os_path_of(&self, at: BungeeIndex) -> PathBuf {
+        os_path(self.reverse_follow_iter(at).map(|(s, _)| s))
     }
 
     pub fn raw_path(&self, at: BungeeIndex) -> Vec<CompactString> {
-        let mut path = self.reverse_follow_iter(at).map(|(s, _)| CompactString::new(s)).collect::<Vec<_>>();
+        let mut path = self
+            .reverse_follow_iter(at)
+            .map(|(s, _)| CompactString::new(s.escaped()))
+            .collect::<Vec<_>>();
         path.reverse();
         path
     }
@@ -320,6 +435,247 @@ impl BungeeStr {
     }
 }
 
+/// Join names given from the last one to the first one.
+fn display_path<'a>(sep: &str, names: impl Iterator<Item = BungeeName<'a>>) -> String {
+    let parts = names.map(|s| s.escaped()).collect::<Vec<_>>();
+    let bytes: usize = parts.iter().map(|v| v.len()).sum();
+    let bytes = bytes + sep.len() * parts.len().saturating_sub(1);
+    let mut result = String::with_capacity(bytes);
+    let mut it = parts.into_iter().rev();
+    if let Some(v) = it.next() {
+        result.push_str(&v);
+    }
+    for v in it {
+        result.push_str(sep);
+        result.push_str(&v);
+    }
+    result
+}
+
+/// Join os names given from the last one to the first one.
+fn os_path<'a>(names: impl Iterator<Item = BungeeName<'a>>) -> PathBuf {
+    let parts = names.collect::<Vec<_>>();
+    parts.into_iter().rev().map(|v| v.to_os_str()).collect()
+}
+
+/// Half sized index of [`BungeeStr32`], `Option<BungeeIndex32>` takes 4 bytes too.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct BungeeIndex32 {
+    pub(crate) index: NonZeroU32,
+}
+
+impl From<BungeeIndex32> for BungeeIndex {
+    fn from(value: BungeeIndex32) -> Self {
+        Self {
+            index: NonZeroUsize::new(value.index.get() as usize).unwrap(),
+        }
+    }
+}
+
+impl TryFrom<BungeeIndex> for BungeeIndex32 {
+    type Error = TryFromIntError;
+
+    fn try_from(value: BungeeIndex) -> Result<Self, Self::Error> {
+        Ok(Self {
+            index: NonZeroU32::try_from(value.index)?,
+        })
+    }
+}
+
+/// Names table of [`BungeeStr`] with `u32` offsets, for trees where index vectors dominate memory. Table is limited
+/// to 4 GiB, pushing beyond that fails instead of wrapping offsets.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct BungeeStr32 {
+    inner: BungeeBytes<VarInt<u32>>,
+}
+
+impl BungeeStr32 {
+    pub const MAX_SIZE: usize = u32::MAX as usize;
+
+    pub const fn new() -> Self {
+        Self { inner: BungeeBytes::new() }
+    }
+
+    fn check_size(size: usize) -> io::Result<()> {
+        if size > Self::MAX_SIZE {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Names table exceeds 4 GiB"));
+        }
+        Ok(())
+    }
+
+    /// Create names table from bytes previously obtained from [`Self::raw_bytes`], see [`BungeeStr::from_raw_bytes`].
+    pub fn from_raw_bytes(data: Vec<u8>) -> io::Result<Self> {
+        Ok(Self::with_ends(data)?.0)
+    }
+
+    /// See [`BungeeStr::with_ends`].
+    pub(crate) fn with_ends(data: Vec<u8>) -> io::Result<(Self, BungeeEnds)> {
+        Self::check_size(data.len())?;
+        let (inner, ends) = BungeeBytes::from_raw_bytes(data)?;
+        Ok((Self { inner }, ends))
+    }
+
+    fn index(at: BungeeIndex) -> BungeeIndex32 {
+        //size of table is checked on every push
+        BungeeIndex32::try_from(at).expect("Index out of u32 range")
+    }
+
+    pub fn last_index(&self) -> Option<BungeeIndex32> {
+        self.inner.last_index().map(Self::index)
+    }
+
+    fn push_flagged(&mut self, prev: Option<BungeeIndex32>, data: &[u8], flag: bool) -> io::Result<Option<BungeeIndex32>> {
+        let size = self.inner.data.len();
+        Self::check_size(size.saturating_add(data.len()))?;
+        let at = self.inner.push_flagged(prev.map(BungeeIndex::from), data, flag);
+        if let Err(e) = Self::check_size(self.inner.data.len()) {
+            self.inner.data.truncate(size);
+            return Err(e);
+        }
+        Ok(at.map(Self::index))
+    }
+
+    pub fn push(&mut self, prev: Option<BungeeIndex32>, data: &str) -> io::Result<Option<BungeeIndex32>> {
+        self.push_flagged(prev, data.as_bytes(), false)
+    }
+
+    /// See [`BungeeStr::push_os`].
+    pub fn push_os(&mut self, prev: Option<BungeeIndex32>, data: &OsStr) -> io::Result<Option<BungeeIndex32>> {
+        match data.to_str() {
+            Some(s) => self.push(prev, s),
+            None => self.push_flagged(prev, os_str_bytes(data), true),
+        }
+    }
+
+    pub fn reverse_follow(&self, at: BungeeIndex32) -> (BungeeName<'_>, Option<BungeeIndex32>) {
+        let (bytes, raw, prev) = self.inner.reverse_follow_flagged(at.into());
+        (BungeeName { bytes, raw }, prev.map(Self::index))
+    }
+
+    /// Entry pushed before given one.
+    pub fn reverse_skip(&self, at: BungeeIndex32) -> Option<BungeeIndex32> {
+        self.inner.reverse_skip(at.into()).1.map(Self::index)
+    }
+
+    pub fn reverse_follow_iter(&self, at: BungeeIndex32) -> impl Iterator<Item = (BungeeName<'_>, BungeeIndex32)> + '_ {
+        let mut last = Some(at);
+        std::iter::from_fn(move || {
+            let at = last?;
+            let (name, prev) = self.reverse_follow(at);
+            last = prev;
+            Some((name, at))
+        })
+    }
+
+    pub fn path_of(&self, sep: &str, at: BungeeIndex32) -> String {
+        display_path(sep, self.reverse_follow_iter(at).map(|(s, _)| s))
+    }
+
+    pub fn os_path_of(&self, at: BungeeIndex32) -> PathBuf {
+        os_path(self.reverse_follow_iter(at).map(|(s, _)| s))
+    }
+
+    pub fn raw_bytes(&self) -> &[u8] {
+        self.inner.raw_bytes()
+    }
+}
+
+/// Raw bytes of both tables are the same, only size of the table is checked.
+impl TryFrom<BungeeStr> for BungeeStr32 {
+    type Error = io::Error;
+
+    fn try_from(value: BungeeStr) -> io::Result<Self> {
+        Self::check_size(value.inner.data.len())?;
+        Ok(Self {
+            inner: BungeeBytes {
+                data: value.inner.data,
+                _phantom: PhantomData,
+            },
+        })
+    }
+}
+
+impl From<BungeeStr32> for BungeeStr {
+    fn from(value: BungeeStr32) -> Self {
+        Self {
+            inner: BungeeBytes {
+                data: value.inner.data,
+                _phantom: PhantomData,
+            },
+        }
+    }
+}
+
+/// Ends of all entries of validated names table, see [`BungeeStr::with_ends`].
+pub(crate) struct BungeeEnds {
+    bits: Vec<u64>,
+}
+
+impl BungeeEnds {
+    fn contains(&self, at: usize) -> bool {
+        self.bits.get(at / 64).is_some_and(|v| v >> (at & 63) & 1 != 0)
+    }
+
+    /// Index of entry ending at given position, fails with [`io::ErrorKind::InvalidData`] when no entry ends there.
+    pub(crate) fn index(&self, at: u64) -> io::Result<BungeeIndex> {
+        usize::try_from(at)
+            .ok()
+            .filter(|&at| self.contains(at))
+            .and_then(NonZeroUsize::new)
+            .map(|index| BungeeIndex { index })
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Name index is not an entry of names table"))
+    }
+}
+
+impl MeasureMemory for BungeeStr32 {
+    fn memory_usage(&self) -> usize {
+        self.inner.data.capacity()
+    }
+}
+
+/// Single name stored in [`BungeeStr`], either valid UTF-8 or raw os bytes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct BungeeName<'a> {
+    bytes: &'a [u8],
+    raw: bool,
+}
+
+impl<'a> BungeeName<'a> {
+    pub(crate) fn new(bytes: &'a [u8], raw: bool) -> Self {
+        Self { bytes, raw }
+    }
+
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    /// True when name was not valid UTF-8, and was stored as raw os bytes.
+    pub fn is_raw(&self) -> bool {
+        self.raw
+    }
+
+    pub fn as_str(&self) -> Option<&'a str> {
+        if self.raw {
+            return None;
+        }
+        from_utf8(self.bytes).ok()
+    }
+
+    pub fn to_os_str(&self) -> Cow<'a, OsStr> {
+        os_str_from_bytes(self.bytes)
+    }
+
+    pub fn escaped(&self) -> Cow<'a, str> {
+        escape_name(self.bytes)
+    }
+}
+
+impl Display for BungeeName<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.escaped())
+    }
+}
+
 impl MeasureMemory for BungeeStr {
     fn memory_usage(&self) -> usize {
         self.inner.data.capacity()
@@ -332,7 +688,8 @@ pub struct BungeeFollowIter<'a, T: OffsetInt> {
 }
 
 pub struct BungeeStrFollowIter<'a> {
-    inner: BungeeFollowIter<'a, VarInt<usize>>,
+    parent: &'a BungeeStr,
+    last: Option<BungeeIndex>,
 }
 
 impl<'a, T: OffsetInt> Iterator for BungeeFollowIter<'a, T> {
@@ -351,10 +708,13 @@ impl<'a, T: OffsetInt> Iterator for BungeeFollowIter<'a, T> {
 }
 
 impl<'a> Iterator for BungeeStrFollowIter<'a> {
-    type Item = (&'a str, BungeeIndex);
+    type Item = (BungeeName<'a>, BungeeIndex);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|(a, b)| (from_utf8(a).unwrap(), b))
+        let last = self.last?;
+        let (name, prev) = self.parent.reverse_follow(last);
+        self.last = prev;
+        Some((name, last))
     }
 }
 
@@ -392,4 +752,133 @@ mod tests {
         assert_eq!(val, b"1234");
         assert_eq!(idx, None);
     }
+
+    #[test]
+    fn test_bungee_flags() {
+        let mut bungee = BungeeBytes::<VarInt<usize>>::new();
+        let i1 = bungee.push_flagged(None, b"raw", true).unwrap();
+        let i2 = bungee.push(Some(i1), &[b'x'; 300]).unwrap();
+        assert!(bungee.is_flagged(i1));
+        assert!(!bungee.is_flagged(i2));
+        assert_eq!(bungee.reverse_follow_flagged(i2), (&[b'x'; 300][..], false, Some(i1)));
+        assert_eq!(bungee.reverse_follow_flagged(i1), (&b"raw"[..], true, None));
+    }
+
+    fn check_var_int<T: OffsetInt + std::fmt::Debug>(values: &[usize]) {
+        for &value in values {
+            let mut buf = [0u8; 16];
+            let len = T::from_usize(value).write(&mut buf[4..]);
+            assert!(len <= T::MAX_BYTES, "{value} written in {len} bytes");
+            let (read, count) = T::read(&buf[4..]).unwrap();
+            assert_eq!((read.to_usize(), count), (value, len));
+            //reverse reading stops at first byte of value, even when preceded by other data
+            buf[..4].fill(0xff);
+            let (read, count) = T::reverse_read(&buf[..4 + len]).unwrap();
+            assert_eq!((read.to_usize(), count), (value, len));
+        }
+    }
+
+    #[test]
+    fn test_var_int() {
+        let boundaries = [0, 1, 127, 128, 255, 16383, 16384, (1 << 21) - 1, 1 << 21, (1 << 28) - 1, 1 << 28];
+        check_var_int::<VarInt<u32>>(&boundaries);
+        check_var_int::<VarInt<u32>>(&[u32::MAX as usize - 1, u32::MAX as usize]);
+        check_var_int::<VarInt<usize>>(&boundaries);
+        check_var_int::<VarInt<usize>>(&[u32::MAX as usize + 1, usize::MAX >> 1, usize::MAX]);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(VarInt(127u32).write(&mut buf), 1);
+        assert_eq!(VarInt(128u32).write(&mut buf), 2);
+        assert_eq!(VarInt(u32::MAX).write(&mut buf), VarInt::<u32>::MAX_BYTES);
+
+        //empty, not terminated and too large values
+        assert_eq!(VarInt::<u32>::read(&[]), None);
+        assert_eq!(VarInt::<u32>::reverse_read(&[]), None);
+        assert_eq!(VarInt::<u32>::read(&[0x80, 0x01]), None);
+        assert_eq!(VarInt::<u32>::reverse_read(&[0x01, 0x80]), None);
+        assert_eq!(VarInt::<u32>::read(&[0xff, 0x7f, 0x7f, 0x7f, 0xff]), None);
+        assert_eq!(VarInt::<u32>::reverse_read(&[0xff, 0x7f, 0x7f, 0x7f, 0xff]), None);
+        assert_eq!(VarInt::<u32>::read(&[0x80, 0, 0, 0, 0, 0x80]), None);
+        assert_eq!(FixedInt::<u32>::reverse_read(&[1, 2, 3]), None);
+    }
+
+    #[test]
+    fn test_corrupted_table() {
+        let mut bungee = BungeeStr::new();
+        let dir = bungee.push(None, "dir");
+        let file = bungee.push(dir, "file.txt").unwrap();
+        let bytes = bungee.raw_bytes().to_vec();
+        let (restored, ends) = BungeeStr::with_ends(bytes.clone()).unwrap();
+        assert_eq!(restored, bungee);
+        assert_eq!(ends.index(file.index.get() as u64).unwrap(), file);
+        assert_eq!(
+            ends.index(dir.unwrap().index.get() as u64 + 1).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(ends.index(0).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(ends.index(u64::MAX).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        //truncated table, and length of name past start of table
+        let invalid = |bytes: Vec<u8>| BungeeStr::from_raw_bytes(bytes).unwrap_err().kind() == io::ErrorKind::InvalidData;
+        assert!(invalid(bytes[..bytes.len() - 1].to_vec()));
+        assert!(invalid(vec![0x7f]));
+        //parent offset pointing into name of other entry
+        let mut moved = bytes.clone();
+        moved[dir.unwrap().index.get()] = 2;
+        assert!(invalid(moved));
+        assert!(BungeeStr32::from_raw_bytes(bytes[1..].to_vec()).is_err());
+        assert!(BungeeStr::from_raw_bytes(Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn test_bungee_32() {
+        let mut bungee = BungeeStr32::new();
+        let dir = bungee.push(None, "dir").unwrap();
+        let file = bungee.push(dir, &"x".repeat(200)).unwrap().unwrap();
+        let other = bungee.push_os(dir, OsStr::new("other.txt")).unwrap().unwrap();
+        assert_eq!(bungee.reverse_follow(file).1, dir);
+        assert_eq!(bungee.path_of("/", other), "dir/other.txt");
+        assert_eq!(bungee.os_path_of(file), PathBuf::from(format!("dir/{}", "x".repeat(200))));
+        assert_eq!(size_of::<Option<BungeeIndex32>>(), 4);
+
+        //the same entries as in regular table, but with smaller offsets
+        let mut wide = BungeeStr::new();
+        let wide_dir = wide.push(None, "dir");
+        wide.push(wide_dir, &"x".repeat(200));
+        let wide_other = wide.push(wide_dir, "other.txt").unwrap();
+        assert_eq!(bungee.raw_bytes(), wide.raw_bytes());
+        assert_eq!(BungeeIndex::from(other), wide_other);
+        assert_eq!(BungeeIndex32::try_from(wide_other), Ok(other));
+
+        let restored = BungeeStr32::from_raw_bytes(bungee.raw_bytes().to_vec()).unwrap();
+        assert_eq!(restored.path_of("/", other), "dir/other.txt");
+        assert!(BungeeStr32::check_size(BungeeStr32::MAX_SIZE).is_ok());
+        let err = BungeeStr32::check_size(BungeeStr32::MAX_SIZE + 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_bungee_os_names() {
+        use std::os::unix::ffi::OsStrExt;
+        let mut bungee = BungeeStr::new();
+        let dir = bungee.push(None, "dir").unwrap();
+        let latin = bungee.push_os(Some(dir), OsStr::from_bytes(b"caf\xe9")).unwrap();
+        let utf = bungee.push_os(Some(dir), OsStr::new("café")).unwrap();
+
+        let (name, prev) = bungee.reverse_follow(latin);
+        assert!(name.is_raw());
+        assert_eq!(name.as_str(), None);
+        assert_eq!(prev, Some(dir));
+        assert_eq!(bungee.path_of("/", latin), "dir/caf\\xE9");
+        assert_eq!(bungee.os_path_of(latin).as_os_str().as_bytes(), b"dir/caf\xe9");
+
+        let (name, _) = bungee.reverse_follow(utf);
+        assert!(!name.is_raw());
+        assert_eq!(name.as_str(), Some("café"));
+        assert_eq!(bungee.path_of("/", utf), "dir/café");
+
+        let restored = BungeeStr::from_raw_bytes(bungee.raw_bytes().to_vec()).unwrap();
+        assert_eq!(restored.os_path_of(latin), bungee.os_path_of(latin));
+    }
 }
@@ -0,0 +1,222 @@
+use crate::utils::{os_str_bytes, BungeeIndex, BungeeName, BungeeStr, MeasureMemory};
+use crate::PathIdentity;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::mem::size_of;
+use std::path::{Component, Path};
+
+/// Children of every entry of [`BungeeStr`] sorted by name, resolves paths to entries, which is not possible with
+/// parent links alone.
+pub struct BungeeLookup<'a> {
+    bungee: &'a BungeeStr,
+    /// Children of entries, top level entries are stored under `None`
+    children: HashMap<Option<BungeeIndex>, Vec<BungeeIndex>>,
+    /// Names are compared after normalization with this policy
+    identity: PathIdentity,
+}
+
+impl<'a> BungeeLookup<'a> {
+    pub fn new(bungee: &'a BungeeStr) -> Self {
+        let mut children = HashMap::<_, Vec<_>>::new();
+        for (_, at, parent) in bungee.reverse_entries() {
+            children.entry(parent).or_default().push(at);
+        }
+        let mut lookup = Self {
+            bungee,
+            children,
+            identity: PathIdentity::EXACT,
+        };
+        lookup.sort_children();
+        lookup
+    }
+
+    /// Compare names normalized with given policy, so that paths are resolved the same way as name ids of snapshot
+    /// with this identity were derived.
+    pub fn with_identity(mut self, identity: PathIdentity) -> Self {
+        if self.identity != identity {
+            self.identity = identity;
+            self.sort_children();
+        }
+        self
+    }
+
+    pub fn identity(&self) -> PathIdentity {
+        self.identity
+    }
+
+    fn sort_children(&mut self) {
+        let mut children = std::mem::take(&mut self.children);
+        for list in children.values_mut() {
+            list.sort_by_cached_key(|at| (self.key_at(*at).into_owned(), at.index));
+        }
+        self.children = children;
+    }
+
+    /// Bytes name is compared by, see [`PathIdentity::name_key`].
+    fn key_of<'n>(&self, name: &'n OsStr) -> Cow<'n, [u8]> {
+        if self.identity.is_exact() {
+            Cow::Borrowed(os_str_bytes(name))
+        } else {
+            Cow::Owned(self.identity.name_key("", [name]))
+        }
+    }
+
+    fn key_at(&self, at: BungeeIndex) -> Cow<'a, [u8]> {
+        let name = self.bungee.reverse_follow(at).0;
+        if self.identity.is_exact() {
+            Cow::Borrowed(name.as_bytes())
+        } else {
+            Cow::Owned(self.key_of(&name.to_os_str()).into_owned())
+        }
+    }
+
+    pub fn bungee(&self) -> &'a BungeeStr {
+        self.bungee
+    }
+
+    /// Children of entry with given name key, the same name may be pushed more times under one parent.
+    fn find_children<'s>(&'s self, parent: Option<BungeeIndex>, key: &'s [u8]) -> impl Iterator<Item = BungeeIndex> + 's {
+        let list = self.children.get(&parent).map(|v| v.as_slice()).unwrap_or_default();
+        let start = list.partition_point(|v| *self.key_at(*v) < *key);
+        list[start..].iter().copied().take_while(move |v| *self.key_at(*v) == *key)
+    }
+
+    /// All entries of root relative path, there are more of them when the same directory was pushed more times.
+    fn lookup_all(&self, path: &Path) -> Vec<BungeeIndex> {
+        let mut candidates = vec![None];
+        for component in path.components() {
+            let Component::Normal(name) = component else {
+                return Vec::new();
+            };
+            let key = self.key_of(name);
+            candidates = candidates
+                .iter()
+                .flat_map(|parent| self.find_children(*parent, &key))
+                .map(Some)
+                .collect();
+        }
+        candidates.into_iter().flatten().collect()
+    }
+
+    /// Entry of root relative path, eg. `a/b/c.txt`, empty path and paths with other than normal components are not
+    /// resolved. Components are normalized with identity of lookup.
+    pub fn lookup(&self, path: &Path) -> Option<BungeeIndex> {
+        let found = self.lookup_all(path);
+        //prefer entry that has children, when the same name was pushed more times
+        let first = *found.first()?;
+        Some(found.into_iter().find(|at| self.children.contains_key(&Some(*at))).unwrap_or(first))
+    }
+
+    /// Children of entry sorted by name, `None` lists top level entries.
+    pub fn children(&self, parent: Option<BungeeIndex>) -> impl Iterator<Item = (BungeeName<'a>, BungeeIndex)> + '_ {
+        self.children
+            .get(&parent)
+            .into_iter()
+            .flatten()
+            .map(|&at| (self.bungee.reverse_follow(at).0, at))
+    }
+
+    /// Children of directory at root relative path sorted by name, empty path lists top level entries. When the same
+    /// directory was pushed more times, children of all of them are listed, and names that are present more times are
+    /// listed once, preferring entry that has children.
+    pub fn list(&self, path: &Path) -> Option<impl Iterator<Item = (BungeeName<'a>, BungeeIndex)> + '_> {
+        let parents = if path.as_os_str().is_empty() {
+            vec![None]
+        } else {
+            let found = self.lookup_all(path);
+            if found.is_empty() {
+                return None;
+            }
+            found.into_iter().map(Some).collect()
+        };
+        let mut listed = parents
+            .iter()
+            .flat_map(|p| self.children.get(p))
+            .flatten()
+            .map(|&at| (self.key_at(at), !self.children.contains_key(&Some(at)), at.index))
+            .collect::<Vec<_>>();
+        listed.sort();
+        listed.dedup_by(|b, a| a.0 == b.0);
+        Some(listed.into_iter().map(|(_, _, index)| {
+            let at = BungeeIndex { index };
+            (self.bungee.reverse_follow(at).0, at)
+        }))
+    }
+}
+
+impl MeasureMemory for BungeeLookup<'_> {
+    fn memory_usage(&self) -> usize {
+        self.children.capacity() * size_of::<(Option<BungeeIndex>, Vec<BungeeIndex>)>()
+            + self
+                .children
+                .values()
+                .map(|v| v.capacity() * size_of::<BungeeIndex>())
+                .sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UnicodeForm;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_lookup() {
+        let mut bungee = BungeeStr::new();
+        let a = bungee.push(None, "a");
+        let b = bungee.push(a, "b");
+        let c = bungee.push(b, "c.txt").unwrap();
+        let d = bungee.push(b, "d.txt").unwrap();
+        let top = bungee.push(None, "top").unwrap();
+        //the same directory pushed again, without children
+        bungee.push(a, "b");
+
+        let lookup = BungeeLookup::new(&bungee);
+        assert_eq!(lookup.lookup(Path::new("a/b/c.txt")), Some(c));
+        assert_eq!(lookup.lookup(Path::new("a/b/d.txt")), Some(d));
+        assert_eq!(lookup.lookup(Path::new("top")), Some(top));
+        assert_eq!(lookup.lookup(Path::new("a/b")), b);
+        assert_eq!(lookup.lookup(Path::new("a/x")), None);
+        assert_eq!(lookup.lookup(Path::new("/a")), None);
+        assert_eq!(bungee.os_path_of(c), PathBuf::from("a/b/c.txt"));
+
+        let names = lookup
+            .list(Path::new("a/b"))
+            .unwrap()
+            .map(|(n, _)| n.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["c.txt", "d.txt"]);
+        let top_level = lookup.list(Path::new("")).unwrap().map(|(n, _)| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(top_level, ["a", "top"]);
+        assert!(lookup.list(Path::new("missing")).is_none());
+    }
+
+    #[test]
+    fn test_lookup_identity() {
+        let mut bungee = BungeeStr::new();
+        let upper = bungee.push(None, "Docs");
+        let cafe = bungee.push(upper, "Café.txt").unwrap();
+        //the same directory under other case, with other children
+        let lower = bungee.push(None, "docs");
+        let b = bungee.push(lower, "b.txt").unwrap();
+
+        let exact = BungeeLookup::new(&bungee);
+        assert_eq!(exact.lookup(Path::new("docs/b.txt")), Some(b));
+        assert_eq!(exact.lookup(Path::new("Docs/b.txt")), None);
+        assert_eq!(exact.list(Path::new("")).unwrap().count(), 2);
+
+        let folded = exact.with_identity(PathIdentity::new(UnicodeForm::Nfc, true));
+        assert_eq!(folded.lookup(Path::new("DOCS/CAFE\u{301}.TXT")), Some(cafe));
+        assert_eq!(folded.lookup(Path::new("Docs/B.txt")), Some(b));
+        let names = folded
+            .list(Path::new("DOCS"))
+            .unwrap()
+            .map(|(n, _)| n.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b.txt", "Café.txt"]);
+        let top_level = folded.list(Path::new("")).unwrap().map(|(_, at)| at).collect::<Vec<_>>();
+        assert_eq!(top_level, [upper.unwrap()]);
+    }
+}
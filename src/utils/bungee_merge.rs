@@ -0,0 +1,127 @@
+use crate::utils::{BungeeIndex, BungeeName, BungeeStr, MeasureMemory, NameTable};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::mem::size_of;
+
+/// Builds new [`BungeeStr`] from entries of other tables, equal names under equal parent are stored once, so tables
+/// of overlapping trees share their common directories. Only added entries and their parents are copied.
+#[derive(Default)]
+pub struct BungeeMerger {
+    bungee: BungeeStr,
+    /// Merged entry for parent, name bytes and raw flag
+    entries: HashMap<(Option<BungeeIndex>, Vec<u8>, bool), BungeeIndex>,
+}
+
+impl BungeeMerger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merged table built so far.
+    pub fn bungee(&self) -> &BungeeStr {
+        &self.bungee
+    }
+
+    pub fn into_bungee(self) -> BungeeStr {
+        self.bungee
+    }
+
+    fn push_name(&mut self, parent: Option<BungeeIndex>, name: BungeeName) -> Option<BungeeIndex> {
+        let key = (parent, name.as_bytes().to_vec(), name.is_raw());
+        if let Some(at) = self.entries.get(&key) {
+            return Some(*at);
+        }
+        let at = self.bungee.push_name(parent, name)?;
+        self.entries.insert(key, at);
+        Some(at)
+    }
+
+    /// Push single name, eg. directory that added tables are placed under, returns existing entry when it was already
+    /// pushed under the same parent.
+    pub fn push_os(&mut self, parent: Option<BungeeIndex>, name: &OsStr) -> Option<BungeeIndex> {
+        let mut single = BungeeStr::new();
+        let at = single.push_os(None, name)?;
+        self.push_name(parent, single.reverse_follow(at).0)
+    }
+
+    /// Copy entries of any source table with all their parents under given parent, `None` places them at top level.
+    /// Returns merged index of every entry, in order of entries.
+    pub fn add<T: NameTable>(&mut self, parent: Option<BungeeIndex>, source: &T, entries: &[T::Index]) -> Vec<BungeeIndex> {
+        //source entries already copied in this call, so shared parents are followed only once
+        let mut copied = HashMap::<T::Index, BungeeIndex>::new();
+        let mut chain = Vec::new();
+        entries
+            .iter()
+            .map(|&entry| {
+                chain.clear();
+                let mut merged = parent;
+                for (name, at) in source.reverse_follow_iter(entry) {
+                    if let Some(done) = copied.get(&at) {
+                        merged = Some(*done);
+                        break;
+                    }
+                    chain.push((name, at));
+                }
+                for &(name, at) in chain.iter().rev() {
+                    //names are never empty, so push always returns entry
+                    let new = self.push_name(merged, name).expect("Empty name in source table");
+                    copied.insert(at, new);
+                    merged = Some(new);
+                }
+                merged.expect("Entry resolved to parent")
+            })
+            .collect()
+    }
+}
+
+impl MeasureMemory for BungeeMerger {
+    fn memory_usage(&self) -> usize {
+        self.bungee.memory_usage()
+            + self.entries.capacity() * size_of::<((Option<BungeeIndex>, Vec<u8>, bool), BungeeIndex)>()
+            + self.entries.keys().map(|k| k.1.capacity()).sum::<usize>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_merge_and_subset() {
+        let mut first = BungeeStr::new();
+        let docs = first.push(None, "docs");
+        let a = first.push(docs, "a.txt").unwrap();
+        let b = first.push(docs, "b.txt").unwrap();
+        let c = first.push(None, "c.txt").unwrap();
+        let mut second = BungeeStr::new();
+        let docs2 = second.push(None, "docs");
+        let d = second.push(docs2, "d.txt").unwrap();
+        let a2 = second.push(docs2, "a.txt").unwrap();
+
+        let mut merger = BungeeMerger::new();
+        let merged_first = merger.add(None, &first, &[a, b, c]);
+        let merged_second = merger.add(None, &second, &[d, a2]);
+        let bungee = merger.into_bungee();
+        let paths = |indexes: &[BungeeIndex]| indexes.iter().map(|i| bungee.path_of("/", *i)).collect::<Vec<_>>();
+        assert_eq!(paths(&merged_first), ["docs/a.txt", "docs/b.txt", "c.txt"]);
+        assert_eq!(paths(&merged_second), ["docs/d.txt", "docs/a.txt"]);
+        assert_eq!(merged_first[0], merged_second[1]);
+        assert_eq!(bungee.reverse_entries().count(), 5);
+
+        //subset keeps only selected entries and their parents
+        let mut merger = BungeeMerger::new();
+        let subset = merger.add(None, &first, &[b]);
+        let bungee = merger.into_bungee();
+        assert_eq!(bungee.os_path_of(subset[0]), PathBuf::from("docs/b.txt"));
+        assert_eq!(bungee.reverse_entries().count(), 2);
+        assert!(bungee.raw_bytes().len() < first.raw_bytes().len());
+
+        //tables placed under their own roots
+        let mut merger = BungeeMerger::new();
+        let root = merger.push_os(None, OsStr::new("disk1"));
+        assert_eq!(merger.push_os(None, OsStr::new("disk1")), root);
+        let under = merger.add(root, &second, &[d]);
+        assert_eq!(merger.bungee().path_of("/", under[0]), "disk1/docs/d.txt");
+    }
+}
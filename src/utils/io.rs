@@ -1,5 +1,24 @@
 use std::io;
-use std::io::{ErrorKind, Read};
+use std::io::{Error, ErrorKind, Read};
+use std::mem::size_of;
+
+/// Read exactly `size` bytes. Memory is allocated as data arrives, so a corrupted size doesn't allocate more than
+/// the stream actually holds.
+pub fn read_sized<R: Read + ?Sized>(read: &mut R, size: u64) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    read.take(size).read_to_end(&mut data)?;
+    if data.len() as u64 != size {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "Block is shorter than its declared size"));
+    }
+    Ok(data)
+}
+
+/// Read little endian `u64`, as all sizes and offsets in blocks are stored.
+pub fn read_u64<R: Read + ?Sized>(read: &mut R) -> io::Result<u64> {
+    let mut buf = [0u8; size_of::<u64>()];
+    read.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
 
 pub fn with_counted_read<R: Read, T, E: From<io::Error>>(
     read: &mut R,
@@ -23,7 +42,7 @@ pub fn with_counted_read<R: Read, T, E: From<io::Error>>(
     let result = func(&mut wrapper)?;
     if wrapper.2 {
         //if there was unpropagated error, raise it here.
-        return Err(io::Error::new(ErrorKind::Other, "IO Error was ignored by file codec").into());
+        return Err(io::Error::other("IO Error was ignored by file codec").into());
     }
     Ok(result)
 }
@@ -1,13 +1,21 @@
 mod bungee;
+mod bungee_lookup;
+mod bungee_merge;
 mod cursor;
 mod io;
 mod lifo;
+mod name_table;
+mod os_name;
 mod size;
 mod sort;
 
 pub use bungee::*;
+pub use bungee_lookup::*;
+pub use bungee_merge::*;
 pub use io::*;
 pub use lifo::*;
+pub use name_table::*;
+pub use os_name::*;
 use parking_lot::RwLock;
 pub use size::*;
 pub use sort::*;
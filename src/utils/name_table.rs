@@ -0,0 +1,261 @@
+use crate::utils::{read_sized, read_u64, BungeeIndex, BungeeIndex32, BungeeMerger, BungeeName, BungeeStr, BungeeStr32, MeasureMemory};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+/// Storage of tree of names, where every entry links to its parent. Implemented by [`BungeeStr`], [`BungeeStr32`],
+/// [`crate::hasher::FileNames`] and compressed [`crate::hasher::FlatedFileNames`], so scanning and names chunk work
+/// with any of them.
+pub trait NameTable: MeasureMemory + Default + Sized {
+    type Index: Copy + Eq + Hash + Debug;
+
+    /// Largest size of table in layout of [`BungeeStr`] that is accepted, see [`Self::from_bungee`].
+    const MAX_SIZE: u64 = usize::MAX as u64;
+
+    /// Push name under parent, empty names are not stored and parent is returned instead.
+    fn try_push_os(&mut self, parent: Option<Self::Index>, name: &OsStr) -> io::Result<Option<Self::Index>>;
+
+    /// Name of entry and its parent.
+    fn reverse_follow(&self, at: Self::Index) -> (BungeeName<'_>, Option<Self::Index>);
+
+    /// Entry pushed before given one.
+    fn reverse_skip(&self, at: Self::Index) -> Option<Self::Index>;
+
+    fn last_index(&self) -> Option<Self::Index>;
+
+    /// Write table in form that is read back by [`Self::read_table`].
+    fn write_table<W: Write>(&self, write: &mut W) -> io::Result<()>;
+
+    fn read_table<R: Read>(read: &mut R) -> io::Result<Self>;
+
+    /// Raw bytes of [`BungeeStr`] with given entries, and offsets of entries into them. Names chunk of any table is
+    /// stored in this form, tables of other layout are copied, see [`BungeeMerger`].
+    fn bungee_parts<'a>(&'a self, entries: &'a [Self::Index]) -> (Cow<'a, [u8]>, impl ExactSizeIterator<Item = u64> + 'a) {
+        let mut merger = BungeeMerger::new();
+        let indexes = merger.add(None, self, entries);
+        let bytes = merger.into_bungee().raw_bytes().to_vec();
+        (Cow::Owned(bytes), indexes.into_iter().map(|at| at.index.get() as u64))
+    }
+
+    /// Table with given entries of [`BungeeStr`], reverse of [`Self::bungee_parts`]. Entries are copied with all
+    /// their parents, returns index of every entry, in order of entries.
+    fn from_bungee(bungee: BungeeStr, entries: impl IntoIterator<Item = io::Result<BungeeIndex>>) -> io::Result<(Self, Vec<Self::Index>)> {
+        let mut table = Self::default();
+        //entries already copied, so shared parents are followed only once
+        let mut copied = HashMap::<BungeeIndex, Self::Index>::new();
+        let mut chain = Vec::new();
+        let mut indexes = Vec::new();
+        for entry in entries {
+            chain.clear();
+            let mut parent = None;
+            for (name, at) in bungee.reverse_follow_iter(entry?) {
+                if let Some(done) = copied.get(&at) {
+                    parent = Some(*done);
+                    break;
+                }
+                chain.push((name, at));
+            }
+            for &(name, at) in chain.iter().rev() {
+                let new = table
+                    .try_push_os(parent, &name.to_os_str())?
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Empty name in names table"))?;
+                copied.insert(at, new);
+                parent = Some(new);
+            }
+            indexes.push(parent.ok_or_else(|| Error::new(ErrorKind::InvalidData, "Entry resolved to parent"))?);
+        }
+        Ok((table, indexes))
+    }
+
+    /// All entries from the last pushed to the first one, with their parents.
+    fn reverse_entries(&self) -> NameTableEntries<'_, Self> {
+        NameTableEntries {
+            table: self,
+            last: self.last_index(),
+        }
+    }
+
+    /// Entry and its parents, nearest first.
+    fn reverse_follow_iter(&self, at: Self::Index) -> impl Iterator<Item = (BungeeName<'_>, Self::Index)> + '_ {
+        let mut last = Some(at);
+        std::iter::from_fn(move || {
+            let at = last?;
+            let (name, parent) = self.reverse_follow(at);
+            last = parent;
+            Some((name, at))
+        })
+    }
+
+    /// Display form of path, see [`BungeeStr::path_of`].
+    fn path_of(&self, sep: &str, at: Self::Index) -> String {
+        let mut parts = self.reverse_follow_iter(at).map(|(n, _)| n.escaped()).collect::<Vec<_>>();
+        parts.reverse();
+        parts.join(sep)
+    }
+
+    /// Lossless path made of original os names.
+    fn os_path_of(&self, at: Self::Index) -> PathBuf {
+        let mut parts = self.reverse_follow_iter(at).map(|(n, _)| n).collect::<Vec<_>>();
+        parts.reverse();
+        parts.iter().map(|n| n.to_os_str()).collect()
+    }
+}
+
+pub struct NameTableEntries<'a, T: NameTable> {
+    table: &'a T,
+    last: Option<T::Index>,
+}
+
+impl<'a, T: NameTable> Iterator for NameTableEntries<'a, T> {
+    type Item = (BungeeName<'a>, T::Index, Option<T::Index>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let at = self.last?;
+        let (name, parent) = self.table.reverse_follow(at);
+        self.last = self.table.reverse_skip(at);
+        Some((name, at, parent))
+    }
+}
+
+/// Read bytes prefixed with their size, as written by [`NameTable::write_table`].
+pub(crate) fn read_table_bytes<R: Read + ?Sized>(read: &mut R, limit: u64) -> io::Result<Vec<u8>> {
+    let size = read_u64(read)?;
+    if size > limit {
+        return Err(Error::new(ErrorKind::Unsupported, "Names table is too big"));
+    }
+    read_sized(read, size)
+}
+
+impl NameTable for BungeeStr {
+    type Index = BungeeIndex;
+
+    fn try_push_os(&mut self, parent: Option<BungeeIndex>, name: &OsStr) -> io::Result<Option<BungeeIndex>> {
+        Ok(self.push_os(parent, name))
+    }
+
+    fn reverse_follow(&self, at: BungeeIndex) -> (BungeeName<'_>, Option<BungeeIndex>) {
+        BungeeStr::reverse_follow(self, at)
+    }
+
+    fn reverse_skip(&self, at: BungeeIndex) -> Option<BungeeIndex> {
+        BungeeStr::reverse_skip(self, at).1
+    }
+
+    fn last_index(&self) -> Option<BungeeIndex> {
+        BungeeStr::last_index(self)
+    }
+
+    fn write_table<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_all(&(self.raw_bytes().len() as u64).to_le_bytes())?;
+        write.write_all(self.raw_bytes())
+    }
+
+    fn read_table<R: Read>(read: &mut R) -> io::Result<Self> {
+        Self::from_raw_bytes(read_table_bytes(read, <Self as NameTable>::MAX_SIZE)?)
+    }
+
+    fn bungee_parts<'a>(&'a self, entries: &'a [BungeeIndex]) -> (Cow<'a, [u8]>, impl ExactSizeIterator<Item = u64> + 'a) {
+        (Cow::Borrowed(self.raw_bytes()), entries.iter().map(|at| at.index.get() as u64))
+    }
+
+    fn from_bungee(bungee: BungeeStr, entries: impl IntoIterator<Item = io::Result<BungeeIndex>>) -> io::Result<(Self, Vec<BungeeIndex>)> {
+        let indexes = entries.into_iter().collect::<io::Result<_>>()?;
+        Ok((bungee, indexes))
+    }
+}
+
+impl NameTable for BungeeStr32 {
+    type Index = BungeeIndex32;
+
+    const MAX_SIZE: u64 = BungeeStr32::MAX_SIZE as u64;
+
+    fn try_push_os(&mut self, parent: Option<BungeeIndex32>, name: &OsStr) -> io::Result<Option<BungeeIndex32>> {
+        self.push_os(parent, name)
+    }
+
+    fn reverse_follow(&self, at: BungeeIndex32) -> (BungeeName<'_>, Option<BungeeIndex32>) {
+        BungeeStr32::reverse_follow(self, at)
+    }
+
+    fn reverse_skip(&self, at: BungeeIndex32) -> Option<BungeeIndex32> {
+        BungeeStr32::reverse_skip(self, at)
+    }
+
+    fn last_index(&self) -> Option<BungeeIndex32> {
+        BungeeStr32::last_index(self)
+    }
+
+    fn write_table<W: Write>(&self, write: &mut W) -> io::Result<()> {
+        write.write_all(&(self.raw_bytes().len() as u64).to_le_bytes())?;
+        write.write_all(self.raw_bytes())
+    }
+
+    fn read_table<R: Read>(read: &mut R) -> io::Result<Self> {
+        Self::from_raw_bytes(read_table_bytes(read, <Self as NameTable>::MAX_SIZE)?)
+    }
+
+    fn bungee_parts<'a>(&'a self, entries: &'a [BungeeIndex32]) -> (Cow<'a, [u8]>, impl ExactSizeIterator<Item = u64> + 'a) {
+        (Cow::Borrowed(self.raw_bytes()), entries.iter().map(|at| at.index.get() as u64))
+    }
+
+    /// Raw bytes of both tables are the same, so only size of the table is checked.
+    fn from_bungee(
+        bungee: BungeeStr,
+        entries: impl IntoIterator<Item = io::Result<BungeeIndex>>,
+    ) -> io::Result<(Self, Vec<BungeeIndex32>)> {
+        let table = BungeeStr32::try_from(bungee)?;
+        let indexes = entries
+            .into_iter()
+            .map(|at| BungeeIndex32::try_from(at?).map_err(|_| Error::new(ErrorKind::InvalidData, "Name index out of names table bounds")))
+            .collect::<io::Result<_>>()?;
+        Ok((table, indexes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::{FileNames, FlatedFileNames};
+    use std::path::Path;
+
+    fn check_table<T: NameTable>(mut table: T) {
+        let dir = table.try_push_os(None, OsStr::new("photos")).unwrap();
+        let year = table.try_push_os(dir, OsStr::new("2020")).unwrap();
+        let a = table.try_push_os(year, OsStr::new("a.jpg")).unwrap().unwrap();
+        let b = table.try_push_os(dir, OsStr::new("b.jpg")).unwrap().unwrap();
+        assert_eq!(table.try_push_os(dir, OsStr::new("")).unwrap(), dir);
+        assert_eq!(table.reverse_follow(a).1, year);
+        assert_eq!(table.path_of("/", a), "photos/2020/a.jpg");
+        assert_eq!(table.os_path_of(b), Path::new("photos/b.jpg"));
+        let entries = table.reverse_entries().map(|(n, _, _)| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(entries, ["b.jpg", "a.jpg", "2020", "photos"]);
+        assert!(table.memory_usage() > 0);
+
+        let mut bytes = Vec::new();
+        table.write_table(&mut bytes).unwrap();
+        let restored = T::read_table(&mut bytes.as_slice()).unwrap();
+        assert_eq!(restored.path_of("/", a), "photos/2020/a.jpg");
+        assert_eq!(restored.reverse_entries().count(), 4);
+
+        //conversion to bungee layout and back
+        let entries = [b, a];
+        let (bytes, indexes) = table.bungee_parts(&entries);
+        let (bungee, ends) = BungeeStr::with_ends(bytes.into_owned()).unwrap();
+        let (copy, indexes) = T::from_bungee(bungee, indexes.map(|at| ends.index(at))).unwrap();
+        assert_eq!(copy.path_of("/", indexes[0]), "photos/b.jpg");
+        assert_eq!(copy.path_of("/", indexes[1]), "photos/2020/a.jpg");
+    }
+
+    #[test]
+    fn test_name_tables() {
+        check_table(BungeeStr::new());
+        check_table(BungeeStr32::new());
+        check_table(FileNames::new());
+        check_table(FlatedFileNames::default());
+    }
+}
@@ -0,0 +1,65 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::Write;
+
+/// Raw bytes of os string, on unix these are exact bytes of file name, on windows it's WTF-8 encoding.
+pub fn os_str_bytes(os: &OsStr) -> &[u8] {
+    os.as_encoded_bytes()
+}
+
+/// Restore os string from raw bytes stored by [`os_str_bytes`]. On platforms other than unix, bytes that are not
+/// valid UTF-8 can't be safely converted back, so escaped form is returned instead.
+pub fn os_str_from_bytes(bytes: &[u8]) -> Cow<'_, OsStr> {
+    cfg_if::cfg_if! {
+        if #[cfg(unix)] {
+            use std::os::unix::ffi::OsStrExt;
+            Cow::Borrowed(OsStr::from_bytes(bytes))
+        } else {
+            match std::str::from_utf8(bytes) {
+                Ok(s) => Cow::Borrowed(OsStr::new(s)),
+                Err(_) => Cow::Owned(escape_name(bytes).into_owned().into()),
+            }
+        }
+    }
+}
+
+/// Display form of raw name, valid UTF-8 is kept as is, every byte of invalid sequence is written as `\xNN`.
+/// Backslash itself is written as `\\`, so escaped form of different names is always different.
+pub fn escape_name(bytes: &[u8]) -> Cow<'_, str> {
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        if !s.contains('\\') {
+            return Cow::Borrowed(s);
+        }
+    }
+    let mut result = String::with_capacity(bytes.len() + 8);
+    for chunk in bytes.utf8_chunks() {
+        result.push_str(&chunk.valid().replace('\\', "\\\\"));
+        for b in chunk.invalid() {
+            let _ = write!(result, "\\x{b:02X}");
+        }
+    }
+    Cow::Owned(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_name() {
+        assert_eq!(escape_name(b"plain.txt"), "plain.txt");
+        assert_eq!(escape_name(b"caf\xe9.txt"), "caf\\xE9.txt");
+        assert_eq!(escape_name(b"\xff\xfe"), "\\xFF\\xFE");
+        assert_eq!(escape_name("zażółć".as_bytes()), "zażółć");
+        assert_eq!(escape_name(b"a\\b"), "a\\\\b");
+        assert_ne!(escape_name(b"caf\\xE9"), escape_name(b"caf\xe9"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_os_round_trip() {
+        use std::os::unix::ffi::OsStrExt;
+        let name = OsStr::from_bytes(b"caf\xe9.txt");
+        assert_eq!(os_str_from_bytes(os_str_bytes(name)), name);
+    }
+}
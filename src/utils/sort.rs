@@ -75,10 +75,10 @@ where
     let mut curr_next = next_iter.next();
 
     #[inline]
-    fn bubble<'b, I, E: 'b>(value: &mut E, mut iter: I, mut compare: impl FnMut(&E, &E) -> Ordering)
+    fn bubble<'b, I, E>(value: &mut E, mut iter: I, mut compare: impl FnMut(&E, &E) -> Ordering)
     where
         I: Iterator<Item = &'b mut E>,
-        E: Debug,
+        E: Debug + 'b,
     {
         for elem in iter {
             if compare(value, elem) == Ordering::Greater {
@@ -249,6 +249,31 @@ where
 {
 }
 
+/// Merge many sorted iterators into one, iterators are merged pairwise in balanced tree of [`MergeSortedWith`], so
+/// every item is compared at most `log2(n)` times.
+pub fn merge_sorted_all<'a, I, F>(iters: Vec<I>, compare: F) -> Box<dyn FusedIterator<Item = I::Item> + 'a>
+where
+    I: FusedIterator + 'a,
+    F: FnMut(&I::Item, &I::Item) -> Ordering + Clone + 'a,
+{
+    let mut level = iters
+        .into_iter()
+        .map(|v| Box::new(v) as Box<dyn FusedIterator<Item = I::Item> + 'a>)
+        .collect::<Vec<_>>();
+    while level.len() > 1 {
+        let mut next = Vec::with_capacity(level.len().div_ceil(2));
+        let mut iter = level.into_iter();
+        while let Some(a) = iter.next() {
+            match iter.next() {
+                Some(b) => next.push(Box::new(MergeSortedWith::new(a, b, compare.clone())) as Box<_>),
+                None => next.push(a),
+            }
+        }
+        level = next;
+    }
+    level.pop().unwrap_or_else(|| Box::new(std::iter::empty()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -282,4 +307,21 @@ mod tests {
             //assert!(array.split_at_mut(split).0.windows(2).all(|w| w[0].cmp(&w[1]) != Ordering::Greater));
         }
     }
+
+    #[test]
+    fn test_merge_sorted_all() {
+        let mut rng = StdRng::seed_from_u64(4321);
+        for count in [0, 1, 2, 5, 8] {
+            let mut runs = (0..count).map(|_| Vec::new()).collect::<Vec<_>>();
+            for v in 0..200u16 {
+                if count > 0 {
+                    runs[rng.gen_range(0..count)].push(v);
+                }
+            }
+            let iters = runs.iter().map(|r| r.iter().copied()).collect::<Vec<_>>();
+            let sorted = merge_sorted_all(iters, u16::cmp).collect::<Vec<_>>();
+            let expected = if count == 0 { vec![] } else { (0..200).collect() };
+            assert_eq!(sorted, expected);
+        }
+    }
 }
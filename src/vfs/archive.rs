@@ -0,0 +1,229 @@
+use crate::vfs::tree::{Node, Tree};
+use crate::vfs::{DirIter, FileReader, FileSystem, VfsMetadata};
+use crate::FileId;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Cursor, Error, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tar::{Archive, EntryType};
+
+/// Read only file system of uncompressed tar archive. Archive is indexed once when opened, file contents are then
+/// read directly from their position in archive, without extracting them.
+pub struct TarFs {
+    source: TarSource,
+    tree: Tree<TarFile>,
+    /// Number of paths sharing content at given archive offset, more than one for hard linked files
+    links: HashMap<u64, usize>,
+}
+
+enum TarSource {
+    File(PathBuf),
+    Memory(Arc<[u8]>),
+}
+
+#[derive(Copy, Clone)]
+struct TarFile {
+    offset: u64,
+    size: u64,
+}
+
+impl TarFs {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let (tree, links) = Self::index(BufReader::new(File::open(path)?))?;
+        Ok(Self {
+            source: TarSource::File(path.to_path_buf()),
+            tree,
+            links,
+        })
+    }
+
+    pub fn from_bytes(bytes: impl Into<Arc<[u8]>>) -> io::Result<Self> {
+        let bytes = bytes.into();
+        let (tree, links) = Self::index(Cursor::new(bytes.clone()))?;
+        Ok(Self {
+            source: TarSource::Memory(bytes),
+            tree,
+            links,
+        })
+    }
+
+    fn index<R: Read>(read: R) -> io::Result<(Tree<TarFile>, HashMap<u64, usize>)> {
+        let mut tree = Tree::<TarFile>::default();
+        let mut links = HashMap::new();
+        for entry in Archive::new(read).entries()? {
+            let entry = entry?;
+            let node = match entry.header().entry_type() {
+                EntryType::Regular | EntryType::Continuous => {
+                    let file = TarFile {
+                        offset: entry.raw_file_position(),
+                        size: entry.size(),
+                    };
+                    links.insert(file.offset, 1);
+                    Node::File(file)
+                }
+                //hard link has no content, it shares content of earlier entry
+                EntryType::Link => {
+                    let target = entry
+                        .link_name()?
+                        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Hard link has no target"))?;
+                    let Ok(&file) = tree.get_file(&target) else {
+                        continue; //target is not a regular file or is not part of archive
+                    };
+                    *links.entry(file.offset).or_default() += 1;
+                    Node::File(file)
+                }
+                EntryType::Directory => Node::Dir(BTreeMap::new()),
+                EntryType::Symlink => Node::Symlink,
+                //sparse files and special entries can't be read from raw archive position
+                _ => continue,
+            };
+            tree.insert(&entry.path()?, node)?;
+        }
+        Ok((tree, links))
+    }
+}
+
+impl FileSystem for TarFs {
+    fn read_dir(&self, path: &Path) -> io::Result<DirIter> {
+        self.tree.read_dir(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<FileReader> {
+        let file = *self.tree.get_file(path)?;
+        match &self.source {
+            TarSource::File(archive) => {
+                let mut read = File::open(archive)?;
+                read.seek(SeekFrom::Start(file.offset))?;
+                Ok(Box::new(read.take(file.size)))
+            }
+            TarSource::Memory(bytes) => {
+                let mut read = Cursor::new(bytes.clone());
+                read.set_position(file.offset);
+                Ok(Box::new(read.take(file.size)))
+            }
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        self.tree.metadata(path, |file| file.size)
+    }
+
+    /// Hard linked entries share position of their content in archive, which is used as inode.
+    fn linked_id(&self, path: &Path) -> io::Result<Option<FileId>> {
+        let file = self.tree.get_file(path)?;
+        let linked = self.links.get(&file.offset).is_some_and(|&count| count > 1);
+        Ok(linked.then_some(FileId {
+            device: 0,
+            inode: file.offset,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::{FileKind, MemFs, RealFs};
+    use crate::DepthFileScanner;
+    use tar::{Builder, Header};
+
+    fn build_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, data) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as _);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_tar_fs() {
+        let bytes = build_tar(&[("data/a.txt", b"first"), ("data/sub/b.txt", b"second file")]);
+        let fs = TarFs::from_bytes(bytes).unwrap();
+
+        let names = fs
+            .read_dir(Path::new("data"))
+            .unwrap()
+            .map(|e| e.map(|e| (e.file_name().to_owned(), e.kind())))
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(names, [("a.txt".into(), FileKind::File), ("sub".into(), FileKind::Dir)]);
+
+        let mut content = String::new();
+        fs.open(Path::new("data/sub/b.txt")).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "second file");
+        assert_eq!(fs.metadata(Path::new("data/a.txt")).unwrap().len, 5);
+    }
+
+    #[test]
+    fn test_tar_hard_link() {
+        let mut builder = Builder::new(Vec::new());
+        for (path, data) in [("data/a.txt", b"shared".as_slice()), ("data/b.txt", b"other")] {
+            let mut header = Header::new_gnu();
+            header.set_size(data.len() as _);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, data).unwrap();
+        }
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        builder.append_link(&mut header, "data/c.txt", "data/a.txt").unwrap();
+        let fs = TarFs::from_bytes(builder.into_inner().unwrap()).unwrap();
+
+        let mut content = String::new();
+        fs.open(Path::new("data/c.txt")).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "shared");
+        assert_eq!(fs.metadata(Path::new("data/c.txt")).unwrap().kind, FileKind::File);
+        let id = fs.linked_id(Path::new("data/a.txt")).unwrap();
+        assert!(id.is_some());
+        assert_eq!(fs.linked_id(Path::new("data/c.txt")).unwrap(), id);
+        assert_eq!(fs.linked_id(Path::new("data/b.txt")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_tar_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("archive.tar");
+        std::fs::write(&path, build_tar(&[("a.txt", b"content")])).unwrap();
+        let fs = TarFs::open(&path).unwrap();
+        let mut content = Vec::new();
+        fs.open(Path::new("a.txt")).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(content, b"content");
+    }
+
+    #[test]
+    fn test_scan_matches_other_backends() {
+        let files: [(&str, &[u8]); 3] = [("data/a.txt", b"first"), ("data/sub/b.txt", b"second"), ("data/sub/c", b"")];
+        let mut mem = MemFs::new();
+        let dir = tempfile::tempdir().unwrap();
+        for (path, data) in files {
+            mem.add_file(path, data).unwrap();
+            std::fs::create_dir_all(dir.path().join(path).parent().unwrap()).unwrap();
+            std::fs::write(dir.path().join(path), data).unwrap();
+        }
+        let scan = |fs: Arc<dyn FileSystem>, root: &Path| {
+            let mut files = DepthFileScanner::from_fs(fs.clone(), root, true)
+                .into_iter()
+                .filter(|(_, typ)| typ.is_file())
+                .map(|(entry, _)| {
+                    let path = entry.path();
+                    let mut data = Vec::new();
+                    fs.open(&path).unwrap().read_to_end(&mut data).unwrap();
+                    (path.strip_prefix(root).unwrap().to_path_buf(), data)
+                })
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+        let archive = scan(Arc::new(TarFs::from_bytes(build_tar(&files)).unwrap()), Path::new("data"));
+        assert_eq!(archive.len(), 3);
+        assert_eq!(archive, scan(Arc::new(mem), Path::new("data")));
+        assert_eq!(archive, scan(Arc::new(RealFs), &dir.path().join("data")));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::vfs::tree::{Node, Tree};
+use crate::vfs::{DirIter, FileReader, FileSystem, VfsMetadata};
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::Arc;
+
+/// File system kept fully in memory, mostly for deterministic tests.
+#[derive(Default)]
+pub struct MemFs {
+    tree: Tree<Arc<[u8]>>,
+}
+
+impl MemFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add file with given content, parent directories are created when missing.
+    pub fn add_file(&mut self, path: impl AsRef<Path>, data: impl Into<Arc<[u8]>>) -> io::Result<()> {
+        self.tree.insert(path.as_ref(), Node::File(data.into()))
+    }
+
+    pub fn add_dir(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.tree.insert(path.as_ref(), Node::Dir(BTreeMap::new()))
+    }
+
+    pub fn add_symlink(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.tree.insert(path.as_ref(), Node::Symlink)
+    }
+
+    pub fn with_file(mut self, path: impl AsRef<Path>, data: impl Into<Arc<[u8]>>) -> Self {
+        self.add_file(path, data).expect("Invalid file path");
+        self
+    }
+}
+
+impl FileSystem for MemFs {
+    fn read_dir(&self, path: &Path) -> io::Result<DirIter> {
+        self.tree.read_dir(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<FileReader> {
+        Ok(Box::new(Cursor::new(self.tree.get_file(path)?.clone())))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        self.tree.metadata(path, |data| data.len() as _)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::{FileKind, VfsEntry};
+    use std::io::{ErrorKind, Read};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_mem_fs() {
+        let mut fs = MemFs::new()
+            .with_file("b/file", b"content".as_slice())
+            .with_file("/a", b"".as_slice());
+        fs.add_dir("b/c").unwrap();
+        fs.add_symlink("./b/link").unwrap();
+
+        let list = |path: &str| fs.read_dir(Path::new(path)).unwrap().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(
+            list(""),
+            [
+                VfsEntry::new(PathBuf::from("a"), FileKind::File),
+                VfsEntry::new(PathBuf::from("b"), FileKind::Dir)
+            ]
+        );
+        let kinds = list("/b").iter().map(|e| (e.file_name().to_owned(), e.kind())).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                ("c".into(), FileKind::Dir),
+                ("file".into(), FileKind::File),
+                ("link".into(), FileKind::Symlink)
+            ]
+        );
+
+        let mut content = String::new();
+        fs.open(Path::new("b/file")).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "content");
+        assert_eq!(fs.metadata(Path::new("b/file")).unwrap().len, 7);
+        assert_eq!(fs.open(Path::new("b")).err().unwrap().kind(), ErrorKind::InvalidInput);
+        assert_eq!(fs.read_dir(Path::new("x")).err().unwrap().kind(), ErrorKind::NotFound);
+        assert_eq!(fs.add_file("b", b"x".as_slice()).unwrap_err().kind(), ErrorKind::AlreadyExists);
+    }
+}
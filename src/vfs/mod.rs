@@ -0,0 +1,86 @@
+mod archive;
+mod mem;
+mod real;
+mod tree;
+
+pub use archive::*;
+pub use mem::*;
+pub use real::*;
+
+use crate::FileId;
+use std::ffi::OsStr;
+use std::io;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Type of file system entry, independent of platform.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    /// Devices, pipes, sockets and any other entries that are not scanned
+    Other,
+}
+
+impl FileKind {
+    pub fn is_file(&self) -> bool {
+        *self == Self::File
+    }
+    pub fn is_dir(&self) -> bool {
+        *self == Self::Dir
+    }
+    pub fn is_symlink(&self) -> bool {
+        *self == Self::Symlink
+    }
+}
+
+/// Single entry of directory listing.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct VfsEntry {
+    path: PathBuf,
+    kind: FileKind,
+}
+
+impl VfsEntry {
+    pub fn new(path: PathBuf, kind: FileKind) -> Self {
+        Self { path, kind }
+    }
+    /// Full path of entry, it's directory path passed to [`FileSystem::read_dir`] joined with entry name
+    pub fn path(&self) -> PathBuf {
+        self.path.clone()
+    }
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+    pub fn file_name(&self) -> &OsStr {
+        self.path.file_name().unwrap_or(self.path.as_os_str())
+    }
+    pub fn kind(&self) -> FileKind {
+        self.kind
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct VfsMetadata {
+    pub kind: FileKind,
+    /// Length of file content in bytes, 0 for directories
+    pub len: u64,
+}
+
+pub type DirIter = Box<dyn Iterator<Item = io::Result<VfsEntry>> + Send>;
+pub type FileReader = Box<dyn Read + Send>;
+
+/// Source of directories and files used by scanner and runner.
+pub trait FileSystem: Send + Sync {
+    fn read_dir(&self, path: &Path) -> io::Result<DirIter>;
+
+    fn open(&self, path: &Path) -> io::Result<FileReader>;
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata>;
+
+    /// Id of the file content, when it's shared by more than one path, see [`FileId::of_linked`].
+    fn linked_id(&self, path: &Path) -> io::Result<Option<FileId>> {
+        Ok(None)
+    }
+}
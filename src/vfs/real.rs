@@ -0,0 +1,50 @@
+use crate::vfs::{DirIter, FileKind, FileReader, FileSystem, VfsEntry, VfsMetadata};
+use crate::FileId;
+use std::fs::{read_dir, File, FileType};
+use std::io;
+use std::path::Path;
+
+/// File system of the operating system.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct RealFs;
+
+impl From<FileType> for FileKind {
+    fn from(value: FileType) -> Self {
+        if value.is_file() {
+            Self::File
+        } else if value.is_dir() {
+            Self::Dir
+        } else if value.is_symlink() {
+            Self::Symlink
+        } else {
+            Self::Other
+        }
+    }
+}
+
+impl FileSystem for RealFs {
+    fn read_dir(&self, path: &Path) -> io::Result<DirIter> {
+        let iter = read_dir(path)?.map(|entry| {
+            let entry = entry?;
+            let kind = entry.file_type()?.into();
+            Ok(VfsEntry::new(entry.path(), kind))
+        });
+        Ok(Box::new(iter))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<FileReader> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<VfsMetadata> {
+        let meta = path.symlink_metadata()?;
+        Ok(VfsMetadata {
+            kind: meta.file_type().into(),
+            len: meta.len(),
+        })
+    }
+
+    fn linked_id(&self, path: &Path) -> io::Result<Option<FileId>> {
+        Ok(FileId::of_linked(&path.metadata()?))
+    }
+}
@@ -0,0 +1,103 @@
+use crate::vfs::{DirIter, FileKind, VfsEntry, VfsMetadata};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+/// Directory tree kept in memory, shared by virtual file systems. Paths are matched by their normal components,
+/// so `a/b`, `/a/b` and `./a/b` are the same path, and empty path is the root directory.
+pub(crate) struct Tree<T> {
+    nodes: HashMap<PathBuf, Node<T>>,
+}
+
+pub(crate) enum Node<T> {
+    /// Children names with their kinds, kept sorted so listing is deterministic
+    Dir(BTreeMap<OsString, FileKind>),
+    File(T),
+    Symlink,
+}
+
+impl<T> Node<T> {
+    fn kind(&self) -> FileKind {
+        match self {
+            Node::Dir(_) => FileKind::Dir,
+            Node::File(_) => FileKind::File,
+            Node::Symlink => FileKind::Symlink,
+        }
+    }
+}
+
+impl<T> Default for Tree<T> {
+    fn default() -> Self {
+        Self {
+            nodes: HashMap::from([(PathBuf::new(), Node::Dir(BTreeMap::new()))]),
+        }
+    }
+}
+
+fn key(path: &Path) -> PathBuf {
+    path.components()
+        .filter_map(|c| match c {
+            Component::Normal(n) => Some(n),
+            _ => None,
+        })
+        .collect()
+}
+
+impl<T> Tree<T> {
+    /// Insert node at path, missing parent directories are created. Existing directory is never replaced by
+    /// another directory, so its children are kept.
+    pub fn insert(&mut self, path: &Path, node: Node<T>) -> io::Result<()> {
+        let key = key(path);
+        let Some(name) = key.file_name() else {
+            return Err(Error::new(ErrorKind::InvalidInput, "Can't replace root directory"));
+        };
+        let parent = key.parent().unwrap_or(Path::new(""));
+        if !matches!(self.nodes.get(parent), Some(Node::Dir(_))) {
+            self.insert(parent, Node::Dir(BTreeMap::new()))?;
+        }
+        if let Some(Node::Dir(children)) = self.nodes.get_mut(parent) {
+            children.insert(name.to_os_string(), node.kind());
+        }
+        match (self.nodes.get(&key), &node) {
+            (Some(Node::Dir(_)), Node::Dir(_)) => {}
+            (Some(Node::Dir(_)), _) => return Err(Error::new(ErrorKind::AlreadyExists, "Path is a directory")),
+            _ => _ = self.nodes.insert(key, node),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, path: &Path) -> io::Result<&Node<T>> {
+        self.nodes
+            .get(&key(path))
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, "Path not found"))
+    }
+
+    pub fn get_file(&self, path: &Path) -> io::Result<&T> {
+        match self.get(path)? {
+            Node::File(file) => Ok(file),
+            _ => Err(Error::new(ErrorKind::InvalidInput, "Path is not a file")),
+        }
+    }
+
+    pub fn read_dir(&self, path: &Path) -> io::Result<DirIter> {
+        let Node::Dir(children) = self.get(path)? else {
+            return Err(Error::new(ErrorKind::InvalidInput, "Path is not a directory"));
+        };
+        let entries = children
+            .iter()
+            .map(|(name, kind)| Ok(VfsEntry::new(path.join(name), *kind)))
+            .collect::<Vec<_>>();
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    pub fn metadata(&self, path: &Path, len: impl FnOnce(&T) -> u64) -> io::Result<VfsMetadata> {
+        let node = self.get(path)?;
+        let len = match node {
+            Node::File(file) => len(file),
+            _ => 0,
+        };
+        Ok(VfsMetadata { kind: node.kind(), len })
+    }
+}